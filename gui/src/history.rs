@@ -0,0 +1,329 @@
+use raytracer::{CameraConfig, Fog, Hit, Scene, Sky, Sphere};
+
+// Every mutation of the scene goes through an `Edit`. Applying an edit returns the edit that
+// reverts it, so the undo and redo stacks are just the inverses of whatever was applied last.
+
+pub enum Edit {
+    Insert { index: usize, object: Box<dyn Hit> },
+    Remove { index: usize },
+    SetSphere { index: usize, sphere: Sphere },
+    SetSky(Sky),
+    SetFog(Option<Fog>),
+    SetCamera(CameraConfig),
+}
+
+#[derive(PartialEq)]
+enum Target {
+    Object(usize),
+    Sky,
+    Fog,
+    Camera,
+}
+
+impl Edit {
+    fn apply(self, scene: &mut Scene, camera: &mut CameraConfig) -> Edit {
+        match self {
+            Edit::Insert { index, object } => {
+                scene.objects.insert(index, object);
+                Edit::Remove { index }
+            }
+
+            Edit::Remove { index } => Edit::Insert {
+                index,
+                object: scene.objects.remove(index),
+            },
+
+            Edit::SetSphere { index, sphere } => {
+                let current = scene.objects[index]
+                    .as_any_mut()
+                    .downcast_mut::<Sphere>()
+                    .expect("object is a sphere");
+
+                Edit::SetSphere {
+                    index,
+                    sphere: std::mem::replace(current, sphere),
+                }
+            }
+
+            Edit::SetSky(sky) => Edit::SetSky(std::mem::replace(&mut scene.sky, sky)),
+
+            Edit::SetFog(fog) => Edit::SetFog(std::mem::replace(&mut scene.fog, fog)),
+
+            Edit::SetCamera(config) => Edit::SetCamera(std::mem::replace(camera, config)),
+        }
+    }
+
+    // Only property changes can be merged, structural edits always get their own entry.
+    fn target(&self) -> Option<Target> {
+        match self {
+            Edit::SetSphere { index, .. } => Some(Target::Object(*index)),
+            Edit::SetSky(_) => Some(Target::Sky),
+            Edit::SetFog(_) => Some(Target::Fog),
+            Edit::SetCamera(_) => Some(Target::Camera),
+            Edit::Insert { .. } | Edit::Remove { .. } => None,
+        }
+    }
+}
+
+pub struct History {
+    undo: Vec<Edit>,
+    redo: Vec<Edit>,
+    open: bool,
+    // How many entries the undo stack held when the scene was last saved, or `None` once no
+    // amount of undoing or redoing gets back to that state.
+    saved: Option<usize>,
+}
+
+/// An empty history, with the scene as it is counting as saved.
+impl Default for History {
+    fn default() -> Self {
+        Self {
+            undo: Vec::new(),
+            redo: Vec::new(),
+            open: false,
+            saved: Some(0),
+        }
+    }
+}
+
+impl History {
+    /// Applies an edit and records it. While the entry is left open (e.g. a slider is still
+    /// being dragged), further edits to the same target fold into it instead of stacking up.
+    pub fn commit(&mut self, edit: Edit, scene: &mut Scene, camera: &mut CameraConfig) {
+        let target = edit.target();
+        let inverse = edit.apply(scene, camera);
+        self.redo.clear();
+
+        let merge =
+            self.open && target.is_some() && self.undo.last().and_then(Edit::target) == target;
+
+        // The saved state is lost when it was on the redo stack, or when it's the one a merged
+        // edit just changed.
+        let depth = self.undo.len();
+        self.saved = self
+            .saved
+            .filter(|&saved| saved < depth || (saved == depth && !merge));
+
+        if !merge {
+            self.undo.push(inverse);
+        }
+
+        self.open = target.is_some();
+    }
+
+    /// Closes the latest entry so the next edit starts a new one.
+    pub fn seal(&mut self) {
+        self.open = false;
+    }
+
+    pub fn undo(&mut self, scene: &mut Scene, camera: &mut CameraConfig) -> bool {
+        self.open = false;
+
+        match self.undo.pop() {
+            Some(edit) => {
+                self.redo.push(edit.apply(scene, camera));
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn redo(&mut self, scene: &mut Scene, camera: &mut CameraConfig) -> bool {
+        self.open = false;
+
+        match self.redo.pop() {
+            Some(edit) => {
+                self.undo.push(edit.apply(scene, camera));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Remembers the current state as the one on disk.
+    pub fn mark_saved(&mut self) {
+        self.saved = Some(self.undo.len());
+    }
+
+    /// Whether undoing and redoing led back to the state that was saved last.
+    pub fn is_saved(&self) -> bool {
+        self.saved == Some(self.undo.len())
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use raytracer::{color, vector3, Lambertian, Lens, Number, Projection};
+
+    use super::*;
+
+    fn sphere(radius: Number) -> Sphere {
+        Sphere {
+            center: vector3(0.0, 0.0, -1.0),
+            radius,
+            material: Box::new(Lambertian {
+                albedo: color(0.5, 0.5, 0.5),
+            }),
+        }
+    }
+
+    fn sky(brightness: Number) -> Sky {
+        Sky {
+            top: color(brightness, brightness, brightness),
+            bottom: color(1.0, 1.0, 1.0),
+        }
+    }
+
+    fn scene() -> (Scene, CameraConfig) {
+        let scene = Scene {
+            objects: vec![Box::new(sphere(1.0))],
+            sky: sky(0.5),
+            fog: None,
+        };
+        let camera = CameraConfig {
+            position: vector3(0.0, 0.0, 0.0),
+            viewport_width: 2.0,
+            viewport_height: 2.0,
+            focal_length: 1.0,
+            target: None,
+            projection: Projection::Perspective,
+            lens: Lens::Pinhole,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+            stereo: None,
+        };
+
+        (scene, camera)
+    }
+
+    fn radius(scene: &Scene) -> Number {
+        scene.objects[0]
+            .as_any()
+            .downcast_ref::<Sphere>()
+            .expect("object is a sphere")
+            .radius
+    }
+
+    fn set_radius(radius: Number) -> Edit {
+        Edit::SetSphere {
+            index: 0,
+            sphere: sphere(radius),
+        }
+    }
+
+    #[test]
+    fn undoing_back_to_the_save_point_counts_as_saved() {
+        let (mut scene, mut camera) = scene();
+        let mut history = History::default();
+        assert!(history.is_saved());
+
+        history.commit(set_radius(2.0), &mut scene, &mut camera);
+        history.seal();
+        history.mark_saved();
+        history.commit(Edit::SetSky(sky(0.1)), &mut scene, &mut camera);
+        history.seal();
+        history.commit(Edit::SetFog(None), &mut scene, &mut camera);
+        assert!(!history.is_saved());
+
+        history.undo(&mut scene, &mut camera);
+        assert!(!history.is_saved());
+        history.undo(&mut scene, &mut camera);
+        assert!(history.is_saved());
+        assert!(scene.sky == sky(0.5));
+
+        // Past it in either direction is unsaved again, and coming back is saved again.
+        history.undo(&mut scene, &mut camera);
+        assert!(!history.is_saved());
+        history.redo(&mut scene, &mut camera);
+        assert!(history.is_saved());
+        history.redo(&mut scene, &mut camera);
+        assert!(!history.is_saved());
+        history.undo(&mut scene, &mut camera);
+        assert!(history.is_saved());
+    }
+
+    #[test]
+    fn editing_after_undoing_past_the_save_point_makes_it_unreachable() {
+        let (mut scene, mut camera) = scene();
+        let mut history = History::default();
+
+        history.commit(set_radius(2.0), &mut scene, &mut camera);
+        history.seal();
+        history.commit(Edit::SetSky(sky(0.1)), &mut scene, &mut camera);
+        history.seal();
+        history.mark_saved();
+
+        history.undo(&mut scene, &mut camera);
+        history.undo(&mut scene, &mut camera);
+        history.commit(set_radius(3.0), &mut scene, &mut camera);
+        history.seal();
+        assert!(!history.can_redo());
+
+        // The saved state had two entries, but no path through the history leads back to it.
+        history.commit(Edit::SetFog(None), &mut scene, &mut camera);
+        assert!(!history.is_saved());
+        history.undo(&mut scene, &mut camera);
+        assert!(!history.is_saved());
+        history.undo(&mut scene, &mut camera);
+        assert!(!history.is_saved());
+        history.redo(&mut scene, &mut camera);
+        history.redo(&mut scene, &mut camera);
+        assert!(!history.is_saved());
+    }
+
+    #[test]
+    fn merged_edits_undo_as_one_step() {
+        let (mut scene, mut camera) = scene();
+        let mut history = History::default();
+
+        // A slider being dragged.
+        for radius in [1.5, 2.0, 2.5] {
+            history.commit(set_radius(radius), &mut scene, &mut camera);
+        }
+        history.seal();
+        assert_eq!(radius(&scene), 2.5);
+
+        history.undo(&mut scene, &mut camera);
+        assert_eq!(radius(&scene), 1.0);
+        assert!(!history.can_undo());
+
+        history.redo(&mut scene, &mut camera);
+        assert_eq!(radius(&scene), 2.5);
+        assert!(!history.can_redo());
+
+        // Sealing or changing something else starts a new entry.
+        history.commit(set_radius(3.0), &mut scene, &mut camera);
+        history.commit(Edit::SetSky(sky(0.1)), &mut scene, &mut camera);
+        history.commit(Edit::SetSky(sky(0.2)), &mut scene, &mut camera);
+        history.undo(&mut scene, &mut camera);
+        assert!(scene.sky == sky(0.5));
+        assert_eq!(radius(&scene), 3.0);
+        history.undo(&mut scene, &mut camera);
+        assert_eq!(radius(&scene), 2.5);
+    }
+
+    #[test]
+    fn merging_into_the_saved_entry_changes_what_undo_gets_back_to() {
+        let (mut scene, mut camera) = scene();
+        let mut history = History::default();
+
+        // Saved halfway through a drag: undoing the rest of it also undoes the first half.
+        history.commit(set_radius(2.0), &mut scene, &mut camera);
+        history.mark_saved();
+        history.commit(set_radius(3.0), &mut scene, &mut camera);
+        assert!(!history.is_saved());
+
+        history.undo(&mut scene, &mut camera);
+        assert!(!history.is_saved());
+        history.redo(&mut scene, &mut camera);
+        assert!(!history.is_saved());
+    }
+}
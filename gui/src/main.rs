@@ -1,23 +1,167 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+mod history;
+mod render_dialog;
+
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use eframe::{
-    egui::{Button, CentralPanel, Context, ScrollArea, SidePanel, Visuals},
+    egui::{
+        color_picker::{self, Alpha},
+        menu, Align2, Button, CentralPanel, ComboBox, Context, DragValue, Key, Layout, Rgba,
+        ScrollArea, SidePanel, TopBottomPanel, Ui, Visuals, Window,
+    },
     epaint::{mutex::RwLock, Stroke},
     App, CreationContext, Frame,
 };
-use raytracer::{color, vector3, Material, Scene, Sky, Sphere};
+use raytracer::{
+    color, vector3, Animation, Aperture, CameraConfig, Color, ComplexIor, Conductor, Dielectric,
+    Fog, HenyeyGreenstein, Ior, Lambertian, Lens, Material, Metal, Number, Principled, Projection,
+    Scene, SceneFile, Sky, Sphere, Stereo, StereoLayout, Texture, TransferFunction, Vector3,
+};
+
+use history::{Edit, History};
+use render_dialog::RenderDialog;
+
+// Actions that throw away the current scene, so they have to ask about unsaved changes first.
+#[derive(Copy, Clone)]
+enum Pending {
+    Open,
+    Quit,
+}
+
+enum Dialog {
+    Open(String),
+    SaveAs { path: String, then: Option<Pending> },
+    Unsaved(Pending),
+    Error(String),
+}
 
 struct RaytracingGui {
     scene: Arc<RwLock<Scene>>,
+    camera: CameraConfig,
+    // Kept as loaded, so saving doesn't drop it.
+    animation: Animation,
+    history: History,
     selected: Option<usize>,
+    path: Option<PathBuf>,
+    // Where the scene's relative paths start from. It stays the directory the scene was opened
+    // from after saving it elsewhere, since only the saved file has its paths rebased.
+    directory: PathBuf,
+    dialog: Option<Dialog>,
+    quit_confirmed: bool,
+    render_dialog: RenderDialog,
 }
 
 impl App for RaytracingGui {
-    fn update(&mut self, ctx: &Context, _: &mut Frame) {
-        CentralPanel::default().show(ctx, |ui| {});
+    fn on_exit_event(&mut self) -> bool {
+        if self.modified() && !self.quit_confirmed {
+            self.dialog = Some(Dialog::Unsaved(Pending::Quit));
+            false
+        } else {
+            true
+        }
+    }
+
+    fn update(&mut self, ctx: &Context, frame: &mut Frame) {
+        // The render thread reads the scene for as long as it runs, so editing is locked until
+        // it's done.
+        let editable = !self.rendering();
+
+        let (undo, redo) = {
+            let input = ctx.input();
+            let z = input.modifiers.command && input.key_pressed(Key::Z);
+            (z && !input.modifiers.shift, z && input.modifiers.shift)
+        };
+
+        if editable && undo {
+            self.undo();
+        } else if editable && redo {
+            self.redo();
+        }
+
+        TopBottomPanel::top("menu").show(ctx, |ui| {
+            menu::bar(ui, |ui| {
+                ui.set_enabled(editable);
+
+                ui.menu_button("File", |ui| {
+                    if ui.button("Open...").clicked() {
+                        self.request(Pending::Open, frame);
+                        ui.close_menu();
+                    }
+
+                    if ui.button("Save").clicked() {
+                        self.save();
+                        ui.close_menu();
+                    }
+
+                    if ui.button("Save As...").clicked() {
+                        self.dialog = Some(Dialog::SaveAs {
+                            path: self.path_string(),
+                            then: None,
+                        });
+                        ui.close_menu();
+                    }
+
+                    ui.separator();
+
+                    if ui.button("Quit").clicked() {
+                        self.request(Pending::Quit, frame);
+                        ui.close_menu();
+                    }
+                });
+
+                ui.menu_button("Edit", |ui| {
+                    if ui
+                        .add_enabled(self.history.can_undo(), Button::new("Undo"))
+                        .clicked()
+                    {
+                        self.undo();
+                        ui.close_menu();
+                    }
+
+                    if ui
+                        .add_enabled(self.history.can_redo(), Button::new("Redo"))
+                        .clicked()
+                    {
+                        self.redo();
+                        ui.close_menu();
+                    }
+                });
+
+                ui.menu_button("Render", |ui| {
+                    if ui.button("Render...").clicked() {
+                        self.render_dialog.open = true;
+                        ui.close_menu();
+                    }
+                });
+
+                ui.with_layout(Layout::right_to_left(), |ui| {
+                    let name = match &self.path {
+                        Some(path) => path.file_name().unwrap_or_default().to_string_lossy(),
+                        None => "Untitled".into(),
+                    };
+
+                    ui.label(if self.modified() {
+                        format!("{} (modified)", name)
+                    } else {
+                        name.into_owned()
+                    });
+                });
+            });
+        });
+
+        if let Some(dialog) = self.dialog.take() {
+            self.dialog = self.show_dialog(ctx, frame, dialog);
+        }
+
+        self.render_dialog
+            .show(ctx, &self.scene, &self.camera, &self.animation);
+
         SidePanel::left("scene").show(ctx, |ui| {
+            ui.set_enabled(editable);
             ui.heading("Scene");
             ui.separator();
 
@@ -47,45 +191,869 @@ impl App for RaytracingGui {
                     }
                 },
             );
+
+            if ui.button("Add Sphere").clicked() {
+                let index = self.scene.read().objects.len();
+                self.commit(Edit::Insert {
+                    index,
+                    object: Box::new(Sphere {
+                        center: vector3(0.0, 0.0, -1.0),
+                        radius: 0.5,
+                        material: Box::new(Lambertian {
+                            albedo: color(0.5, 0.5, 0.5),
+                        }),
+                    }),
+                });
+                self.selected = Some(index);
+            }
+
+            ui.separator();
+            ui.heading("Sky");
+
+            let mut sky = self.scene.read().sky;
+            color_editor(ui, "Top", &mut sky.top);
+            color_editor(ui, "Bottom", &mut sky.bottom);
+            if sky != self.scene.read().sky {
+                self.commit(Edit::SetSky(sky));
+            }
+
+            ui.separator();
+            ui.heading("Fog");
+
+            let mut fog = self.scene.read().fog;
+            fog_editor(ui, &mut fog);
+            if fog != self.scene.read().fog {
+                self.commit(Edit::SetFog(fog));
+            }
+
+            ui.separator();
+            ui.heading("Camera");
+
+            let mut camera = self.camera.clone();
+            vector_editor(ui, "Position", &mut camera.position);
+            target_editor(ui, &mut camera.target);
+            number_editor(ui, "Viewport Width", &mut camera.viewport_width);
+            number_editor(ui, "Viewport Height", &mut camera.viewport_height);
+            number_editor(ui, "Focal Length", &mut camera.focal_length);
+            projection_editor(ui, &mut camera.projection);
+            lens_editor(ui, &mut camera.lens);
+            stereo_editor(ui, &mut camera.stereo);
+            number_editor(ui, "Shutter Open", &mut camera.shutter_open);
+            number_editor(ui, "Shutter Close", &mut camera.shutter_close);
+            if camera != self.camera {
+                self.commit(Edit::SetCamera(camera));
+            }
         });
 
-        if let Some(idx) = self.selected {
+        if let Some(index) = self.selected {
             SidePanel::right("inspector").show(ctx, |ui| {
+                ui.set_enabled(editable);
                 ui.heading("Inspector");
                 ui.separator();
+
+                let sphere = self.scene.read().objects[index]
+                    .as_any()
+                    .downcast_ref::<Sphere>()
+                    .cloned();
+
+                if let Some(mut sphere) = sphere {
+                    let original = sphere.clone();
+                    sphere_editor(ui, &mut sphere, &self.directory);
+                    if sphere != original {
+                        self.commit(Edit::SetSphere { index, sphere });
+                    }
+                }
+
+                ui.separator();
+
+                if ui.button("Remove").clicked() {
+                    self.commit(Edit::Remove { index });
+                    self.selected = None;
+                }
             });
         }
+
+        CentralPanel::default().show(ctx, |ui| self.render_dialog.viewport(ui));
+
+        // A drag or a color picker keeps the pointer down, so everything that changes until it
+        // is released ends up in the same history entry.
+        if !ctx.input().pointer.any_down() {
+            self.history.seal();
+        }
     }
 }
 
 impl RaytracingGui {
     fn new() -> Self {
+        let camera = CameraConfig {
+            position: vector3(0.0, 0.0, 0.0),
+            viewport_width: 2.0 * 16.0 / 9.0,
+            viewport_height: 2.0,
+            focal_length: 1.0,
+            target: None,
+            projection: Projection::Perspective,
+            lens: Lens::Pinhole,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+            stereo: None,
+        };
+
         Self {
             selected: None,
+            path: None,
+            directory: PathBuf::new(),
+            dialog: None,
+            quit_confirmed: false,
+            render_dialog: RenderDialog::new(&camera),
+            history: History::default(),
+            camera,
+            animation: Animation::default(),
             scene: Arc::new(RwLock::new(Scene {
                 sky: Sky {
                     top: color(0.5, 0.7, 1.0),
                     bottom: color(1.0, 1.0, 1.0),
                 },
+                fog: None,
                 objects: vec![
                     Box::new(Sphere {
                         center: vector3(0.0, 0.0, -1.0),
                         radius: 0.5,
-                        material: Material::Lambertian {
+                        material: Box::new(Lambertian {
                             albedo: color(1.0, 0.3, 0.3),
-                        },
+                        }),
                     }),
                     Box::new(Sphere {
                         center: vector3(0.0, 0.0, -1.0),
                         radius: 0.5,
-                        material: Material::Lambertian {
+                        material: Box::new(Lambertian {
                             albedo: color(1.0, 0.3, 0.3),
-                        },
+                        }),
                     }),
                 ],
             })),
         }
     }
+
+    fn commit(&mut self, edit: Edit) {
+        self.history
+            .commit(edit, &mut self.scene.write(), &mut self.camera);
+    }
+
+    fn undo(&mut self) {
+        if self.history.undo(&mut self.scene.write(), &mut self.camera) {
+            self.clamp_selection();
+        }
+    }
+
+    fn redo(&mut self) {
+        if self.history.redo(&mut self.scene.write(), &mut self.camera) {
+            self.clamp_selection();
+        }
+    }
+
+    fn clamp_selection(&mut self) {
+        let len = self.scene.read().objects.len();
+        self.selected = self.selected.filter(|&index| index < len);
+    }
+
+    fn modified(&self) -> bool {
+        !self.history.is_saved()
+    }
+
+    fn rendering(&self) -> bool {
+        self.render_dialog.is_running()
+    }
+
+    fn path_string(&self) -> String {
+        self.path
+            .as_ref()
+            .map(|path| path.display().to_string())
+            .unwrap_or_default()
+    }
+
+    fn request(&mut self, pending: Pending, frame: &mut Frame) {
+        if self.modified() {
+            self.dialog = Some(Dialog::Unsaved(pending));
+        } else {
+            self.dialog = self.proceed(pending, frame);
+        }
+    }
+
+    fn proceed(&mut self, pending: Pending, frame: &mut Frame) -> Option<Dialog> {
+        match pending {
+            Pending::Open => Some(Dialog::Open(self.path_string())),
+            Pending::Quit => {
+                self.quit_confirmed = true;
+                frame.quit();
+                None
+            }
+        }
+    }
+
+    fn open(&mut self, path: PathBuf) -> Result<(), String> {
+        let file = SceneFile::load(&path).map_err(|e| format!("Couldn't open scene: {}", e))?;
+
+        *self.scene.write() = file.scene;
+        self.camera = file.camera;
+        self.animation = file.animation;
+        self.history = History::default();
+        self.selected = None;
+        self.directory = path.parent().map(PathBuf::from).unwrap_or_default();
+        self.path = Some(path);
+
+        Ok(())
+    }
+
+    fn save_to(&mut self, path: PathBuf) -> Result<(), String> {
+        SceneFile::save(
+            &path,
+            &self.directory,
+            &self.scene.read(),
+            &self.camera,
+            &self.animation,
+        )
+        .map_err(|e| format!("Couldn't save scene: {}", e))?;
+
+        self.path = Some(path);
+        self.history.mark_saved();
+
+        Ok(())
+    }
+
+    fn save(&mut self) {
+        self.dialog = match self.path.clone() {
+            Some(path) => self.save_to(path).err().map(Dialog::Error),
+            None => Some(Dialog::SaveAs {
+                path: String::new(),
+                then: None,
+            }),
+        };
+    }
+
+    // Shows the current dialog and returns the one to show next frame.
+    fn show_dialog(&mut self, ctx: &Context, frame: &mut Frame, dialog: Dialog) -> Option<Dialog> {
+        let window = |title: &str| {
+            Window::new(title)
+                .collapsible(false)
+                .resizable(false)
+                .anchor(Align2::CENTER_CENTER, (0.0, 0.0))
+        };
+
+        match dialog {
+            Dialog::Open(mut path) => {
+                let mut next = None;
+                window("Open Scene").show(ctx, |ui| {
+                    ui.text_edit_singleline(&mut path);
+                    ui.horizontal(|ui| {
+                        if ui.button("Open").clicked() {
+                            next = Some(self.open(PathBuf::from(&path)).err().map(Dialog::Error));
+                        }
+                        if ui.button("Cancel").clicked() {
+                            next = Some(None);
+                        }
+                    });
+                });
+                next.unwrap_or(Some(Dialog::Open(path)))
+            }
+
+            Dialog::SaveAs { mut path, then } => {
+                let mut next = None;
+                window("Save Scene As").show(ctx, |ui| {
+                    ui.text_edit_singleline(&mut path);
+                    ui.horizontal(|ui| {
+                        if ui.button("Save").clicked() {
+                            next = Some(match self.save_to(PathBuf::from(&path)) {
+                                Ok(()) => then.and_then(|pending| self.proceed(pending, frame)),
+                                Err(e) => Some(Dialog::Error(e)),
+                            });
+                        }
+                        if ui.button("Cancel").clicked() {
+                            next = Some(None);
+                        }
+                    });
+                });
+                next.unwrap_or(Some(Dialog::SaveAs { path, then }))
+            }
+
+            Dialog::Unsaved(pending) => {
+                let mut next = None;
+                window("Unsaved Changes").show(ctx, |ui| {
+                    ui.label("The scene has unsaved changes.");
+                    ui.horizontal(|ui| {
+                        if ui.button("Save").clicked() {
+                            next = Some(match self.path.clone() {
+                                Some(path) => match self.save_to(path) {
+                                    Ok(()) => self.proceed(pending, frame),
+                                    Err(e) => Some(Dialog::Error(e)),
+                                },
+                                None => Some(Dialog::SaveAs {
+                                    path: String::new(),
+                                    then: Some(pending),
+                                }),
+                            });
+                        }
+                        if ui.button("Discard").clicked() {
+                            next = Some(self.proceed(pending, frame));
+                        }
+                        if ui.button("Cancel").clicked() {
+                            next = Some(None);
+                        }
+                    });
+                });
+                next.unwrap_or(Some(Dialog::Unsaved(pending)))
+            }
+
+            Dialog::Error(message) => {
+                let mut close = false;
+                window("Error").show(ctx, |ui| {
+                    ui.label(message.as_str());
+                    close = ui.button("Ok").clicked();
+                });
+                (!close).then(|| Dialog::Error(message))
+            }
+        }
+    }
+}
+
+fn number_editor(ui: &mut Ui, label: &str, value: &mut Number) {
+    ui.horizontal(|ui| {
+        ui.label(label);
+        ui.add(DragValue::new(value).speed(0.01));
+    });
+}
+
+fn vector_editor(ui: &mut Ui, label: &str, vector: &mut Vector3) {
+    ui.horizontal(|ui| {
+        ui.label(label);
+        ui.add(DragValue::new(&mut vector.x).speed(0.01).prefix("x: "));
+        ui.add(DragValue::new(&mut vector.y).speed(0.01).prefix("y: "));
+        ui.add(DragValue::new(&mut vector.z).speed(0.01).prefix("z: "));
+    });
+}
+
+fn color_editor(ui: &mut Ui, label: &str, value: &mut Color) {
+    ui.horizontal(|ui| {
+        ui.label(label);
+        color_value(ui, value);
+    });
+}
+
+fn fog_editor(ui: &mut Ui, fog: &mut Option<Fog>) {
+    let mut enabled = fog.is_some();
+    if ui.checkbox(&mut enabled, "Enabled").changed() {
+        *fog = enabled.then(|| Fog {
+            density: 0.1,
+            height: 1.0,
+            phase: HenyeyGreenstein {
+                albedo: color(1.0, 1.0, 1.0),
+                anisotropy: 0.0,
+            },
+        });
+    }
+
+    if let Some(fog) = fog {
+        ui.horizontal(|ui| {
+            ui.label("Density");
+            ui.add(
+                DragValue::new(&mut fog.density)
+                    .speed(0.001)
+                    .clamp_range(0.0..=Number::MAX),
+            );
+        });
+        number_editor(ui, "Height", &mut fog.height);
+        color_editor(ui, "Albedo", &mut fog.phase.albedo);
+        ui.horizontal(|ui| {
+            ui.label("Anisotropy");
+            ui.add(
+                DragValue::new(&mut fog.phase.anisotropy)
+                    .speed(0.01)
+                    .clamp_range(-1.0..=1.0),
+            );
+        });
+    }
+}
+
+fn target_editor(ui: &mut Ui, target: &mut Option<Vector3>) {
+    let mut enabled = target.is_some();
+    if ui.checkbox(&mut enabled, "Look At").changed() {
+        *target = enabled.then(|| vector3(0.0, 0.0, -1.0));
+    }
+
+    if let Some(target) = target {
+        vector_editor(ui, "Target", target);
+    }
+}
+
+fn projection_editor(ui: &mut Ui, projection: &mut Projection) {
+    ComboBox::from_label("Projection")
+        .selected_text(projection.name())
+        .show_ui(ui, |ui| {
+            for option in Projection::ALL {
+                let name = option.name();
+                if ui
+                    .selectable_label(projection.name() == name, name)
+                    .clicked()
+                    && projection.name() != name
+                {
+                    *projection = option;
+                }
+            }
+        });
+
+    if let Projection::Fisheye { fov } = projection {
+        ui.horizontal(|ui| {
+            ui.label("Field of View");
+            ui.add(DragValue::new(fov).speed(1.0).clamp_range(1.0..=360.0));
+        });
+    }
+}
+
+fn lens_editor(ui: &mut Ui, lens: &mut Lens) {
+    // Compound lenses come from prescription files, so they can only be kept, not picked.
+    let thin = Lens::Thin {
+        radius: 0.05,
+        focus_distance: 1.0,
+        aperture: Aperture::ROUND,
+        tilt: (0.0, 0.0),
+    };
+    let kind = lens.name();
+
+    ComboBox::from_label("Lens")
+        .selected_text(kind)
+        .show_ui(ui, |ui| {
+            for option in [Lens::Pinhole, thin] {
+                let name = option.name();
+                if ui.selectable_label(kind == name, name).clicked() && kind != name {
+                    *lens = option;
+                }
+            }
+        });
+
+    let (focus_distance, aperture, tilt) = match lens {
+        Lens::Pinhole => return,
+        Lens::Thin {
+            radius,
+            focus_distance,
+            aperture,
+            tilt,
+        } => {
+            ui.horizontal(|ui| {
+                ui.label("Radius");
+                ui.add(
+                    DragValue::new(radius)
+                        .speed(0.001)
+                        .clamp_range(0.0..=Number::MAX),
+                );
+            });
+            (focus_distance, aperture, tilt)
+        }
+        Lens::Compound {
+            path,
+            film_diagonal,
+            focus_distance,
+            aperture,
+            tilt,
+            ..
+        } => {
+            ui.label(format!("Prescription: {}", path));
+            ui.horizontal(|ui| {
+                ui.label("Film Diagonal");
+                ui.add(
+                    DragValue::new(film_diagonal)
+                        .speed(0.1)
+                        .clamp_range(0.0..=Number::MAX),
+                );
+            });
+            (focus_distance, aperture, tilt)
+        }
+    };
+
+    ui.horizontal(|ui| {
+        ui.label("Focus Distance");
+        ui.add(
+            DragValue::new(focus_distance)
+                .speed(0.01)
+                .clamp_range(0.0..=Number::MAX),
+        );
+    });
+    ui.horizontal(|ui| {
+        ui.label("Blades");
+        ui.add(DragValue::new(&mut aperture.blades).clamp_range(0..=16));
+        ui.label("Rotation");
+        ui.add(DragValue::new(&mut aperture.rotation).speed(1.0));
+    });
+    ui.horizontal(|ui| {
+        ui.label("Tilt");
+        ui.add(DragValue::new(&mut tilt.0).speed(0.1).prefix("x: "));
+        ui.add(DragValue::new(&mut tilt.1).speed(0.1).prefix("y: "));
+    });
+}
+
+fn stereo_editor(ui: &mut Ui, stereo: &mut Option<Stereo>) {
+    let mut enabled = stereo.is_some();
+    if ui.checkbox(&mut enabled, "Stereo").changed() {
+        *stereo = enabled.then(|| Stereo {
+            eye_separation: 0.064,
+            convergence: 2.0,
+            layout: StereoLayout::SideBySide,
+        });
+    }
+
+    if let Some(stereo) = stereo {
+        ui.horizontal(|ui| {
+            ui.selectable_value(&mut stereo.layout, StereoLayout::SideBySide, "Side by Side");
+            ui.selectable_value(&mut stereo.layout, StereoLayout::OverUnder, "Over Under");
+        });
+        ui.horizontal(|ui| {
+            ui.label("Eye Separation");
+            ui.add(
+                DragValue::new(&mut stereo.eye_separation)
+                    .speed(0.001)
+                    .clamp_range(0.0..=Number::MAX),
+            );
+        });
+        ui.horizontal(|ui| {
+            ui.label("Convergence");
+            ui.add(
+                DragValue::new(&mut stereo.convergence)
+                    .speed(0.01)
+                    .clamp_range(0.0..=Number::MAX),
+            );
+        });
+    }
+}
+
+fn sphere_editor(ui: &mut Ui, sphere: &mut Sphere, directory: &Path) {
+    vector_editor(ui, "Center", &mut sphere.center);
+    ui.horizontal(|ui| {
+        ui.label("Radius");
+        ui.add(
+            DragValue::new(&mut sphere.radius)
+                .speed(0.01)
+                .clamp_range(0.0..=Number::MAX),
+        );
+    });
+
+    ui.separator();
+
+    // Switching materials keeps the color the surface had, as seen from the middle of the
+    // texture for textured ones.
+    let albedo = sphere.material.albedo((0.5, 0.5));
+    let kind = sphere.material.name();
+
+    ComboBox::from_label("Material")
+        .selected_text(kind)
+        .show_ui(ui, |ui| {
+            let options: [Box<dyn Material>; 5] = [
+                Box::new(Lambertian { albedo }),
+                Box::new(Metal { albedo, fuzz: 0.0 }),
+                Box::new(Conductor {
+                    ior: ComplexIor::GOLD,
+                    roughness_u: 0.3,
+                    roughness_v: 0.3,
+                }),
+                Box::new(Dielectric {
+                    ior: Ior::Constant(1.5),
+                    roughness_u: 0.0,
+                    roughness_v: 0.0,
+                }),
+                Box::new(Principled {
+                    base_color: Texture::Constant(albedo),
+                    ..Principled::default()
+                }),
+            ];
+
+            for option in options {
+                let name = option.name();
+                if ui.selectable_label(kind == name, name).clicked() && kind != name {
+                    sphere.material = option;
+                }
+            }
+        });
+
+    let material = sphere.material.as_any_mut();
+
+    if let Some(Lambertian { albedo }) = material.downcast_mut::<Lambertian>() {
+        color_editor(ui, "Albedo", albedo);
+    } else if let Some(Metal { albedo, fuzz }) = material.downcast_mut::<Metal>() {
+        color_editor(ui, "Albedo", albedo);
+        number_editor(ui, "Fuzz", fuzz);
+    } else if let Some(Conductor {
+        ior,
+        roughness_u,
+        roughness_v,
+    }) = material.downcast_mut::<Conductor>()
+    {
+        ComboBox::from_label("Preset")
+            .selected_text(ior.name().unwrap_or("custom"))
+            .show_ui(ui, |ui| {
+                for (name, preset) in ComplexIor::PRESETS {
+                    ui.selectable_value(ior, preset, name);
+                }
+            });
+        ior_editor(ui, "Eta", &mut ior.eta);
+        ior_editor(ui, "K", &mut ior.k);
+        roughness_editor(ui, roughness_u, roughness_v);
+    } else if let Some(Dielectric {
+        ior,
+        roughness_u,
+        roughness_v,
+    }) = material.downcast_mut::<Dielectric>()
+    {
+        dielectric_ior_editor(ui, ior);
+        roughness_editor(ui, roughness_u, roughness_v);
+    } else if let Some(principled) = material.downcast_mut::<Principled>() {
+        texture_editor(
+            ui,
+            "Base Color",
+            &mut principled.base_color,
+            directory,
+            color_value,
+        );
+        for (label, texture, range) in [
+            ("Metallic", &mut principled.metallic, 0.0..=1.0),
+            ("Roughness", &mut principled.roughness, 0.0..=1.0),
+            ("Specular", &mut principled.specular, 0.0..=1.0),
+            ("Specular Tint", &mut principled.specular_tint, 0.0..=1.0),
+            ("Sheen", &mut principled.sheen, 0.0..=1.0),
+            ("Clearcoat", &mut principled.clearcoat, 0.0..=1.0),
+            ("Transmission", &mut principled.transmission, 0.0..=1.0),
+            ("IOR", &mut principled.ior, 1.0..=4.0),
+        ] {
+            texture_editor(ui, label, texture, directory, |ui, value| {
+                scalar_value(ui, value, range.clone())
+            });
+        }
+    } else {
+        // Materials from other crates can be rendered, just not edited.
+        ui.label("This material can't be edited here.");
+    }
+}
+
+// A combo for the kind of texture, followed by its values, each edited by `value_editor`. Image
+// paths start from `directory`, like they do in the scene file.
+fn texture_editor(
+    ui: &mut Ui,
+    label: &str,
+    texture: &mut Texture,
+    directory: &Path,
+    value_editor: impl Fn(&mut Ui, &mut Color),
+) {
+    let kind = texture_name(texture);
+    let value = texture.value((0.5, 0.5));
+
+    ui.horizontal(|ui| {
+        ui.label(label);
+        ComboBox::from_id_source(label)
+            .selected_text(kind)
+            .show_ui(ui, |ui| {
+                let options = [
+                    Texture::Constant(value),
+                    Texture::Checker {
+                        even: value,
+                        odd: value * 0.5,
+                        scale: 8.0,
+                    },
+                    Texture::Image {
+                        path: String::new(),
+                        transfer: TransferFunction::Srgb,
+                        image: None,
+                    },
+                ];
+
+                for option in options {
+                    let name = texture_name(&option);
+                    if ui.selectable_label(kind == name, name).clicked() && kind != name {
+                        *texture = option;
+                    }
+                }
+            });
+
+        match texture {
+            Texture::Constant(value) => value_editor(ui, value),
+            Texture::Checker { even, odd, scale } => {
+                value_editor(ui, even);
+                value_editor(ui, odd);
+                ui.add(
+                    DragValue::new(scale)
+                        .speed(0.1)
+                        .clamp_range(0.0..=1000.0)
+                        .prefix("scale: "),
+                );
+            }
+            Texture::Image {
+                path,
+                transfer,
+                image,
+            } => {
+                // The editor works on a copy of the sphere each frame, so why the image didn't
+                // load is kept with the path it was for instead of in the texture.
+                let error_id = ui.make_persistent_id(label);
+                let path_changed = ui.text_edit_singleline(path).changed();
+                let mut linear = *transfer == TransferFunction::Linear;
+                let linear_changed = ui.checkbox(&mut linear, "Linear").changed();
+
+                if path_changed || linear_changed {
+                    let transfer = if linear {
+                        TransferFunction::Linear
+                    } else {
+                        TransferFunction::Srgb
+                    };
+                    *texture = match Texture::load(path, directory, transfer) {
+                        Ok(texture) => texture,
+                        Err(e) => {
+                            ui.data()
+                                .insert_temp(error_id, (path.clone(), e.to_string()));
+                            Texture::Image {
+                                path: path.clone(),
+                                transfer,
+                                image: None,
+                            }
+                        }
+                    };
+                } else if image.is_none() && !path.is_empty() {
+                    let error = ui.data().get_temp::<(String, String)>(error_id);
+                    match error {
+                        Some((failed, e)) if failed == *path => {
+                            ui.label(format!("Couldn't load image: {}", e))
+                        }
+                        _ => ui.label("Couldn't load image"),
+                    };
+                }
+            }
+        }
+    });
+}
+
+fn texture_name(texture: &Texture) -> &'static str {
+    match texture {
+        Texture::Constant(_) => "Constant",
+        Texture::Checker { .. } => "Checker",
+        Texture::Image { .. } => "Image",
+    }
+}
+
+// Scene colors are linear sRGB, and so is `Rgba`, which lets the picker show them as they render.
+fn color_value(ui: &mut Ui, value: &mut Color) {
+    let mut rgba = Rgba::from_rgb(value.r, value.g, value.b);
+    if color_picker::color_edit_button_rgba(ui, &mut rgba, Alpha::Opaque).changed() {
+        *value = color(rgba.r(), rgba.g(), rgba.b());
+    }
+}
+
+// Scalar textures are grey, and editing one sets all channels.
+fn scalar_value(ui: &mut Ui, value: &mut Color, range: RangeInclusive<Number>) {
+    let mut scalar = value.r;
+    if ui
+        .add(DragValue::new(&mut scalar).speed(0.01).clamp_range(range))
+        .changed()
+    {
+        *value = color(scalar, scalar, scalar);
+    }
+}
+
+// Indices of refraction go well past 1, so they can't use the color picker.
+fn ior_editor(ui: &mut Ui, label: &str, value: &mut Color) {
+    ui.horizontal(|ui| {
+        ui.label(label);
+        for channel in [&mut value.r, &mut value.g, &mut value.b] {
+            ui.add(DragValue::new(channel).speed(0.01).clamp_range(0.0..=100.0));
+        }
+    });
+}
+
+// A combo for the kind of index, followed by its coefficients. Switching to a dispersive kind
+// starts from BK7 glass.
+fn dielectric_ior_editor(ui: &mut Ui, ior: &mut Ior) {
+    let kind = dielectric_ior_name(ior);
+
+    ui.horizontal(|ui| {
+        ui.label("IOR");
+        ComboBox::from_id_source("dielectric_ior")
+            .selected_text(kind)
+            .show_ui(ui, |ui| {
+                let options = [
+                    Ior::Constant(ior.value()),
+                    Ior::Cauchy {
+                        a: 1.5046,
+                        b: 0.0042,
+                    },
+                    Ior::Sellmeier {
+                        b: [1.03961212, 0.231792344, 1.01046945],
+                        c: [0.00600069867, 0.0200179144, 103.560653],
+                    },
+                ];
+
+                for option in options {
+                    let name = dielectric_ior_name(&option);
+                    if ui.selectable_label(kind == name, name).clicked() && kind != name {
+                        *ior = option;
+                    }
+                }
+            });
+
+        match ior {
+            Ior::Constant(value) => {
+                ui.add(DragValue::new(value).speed(0.01).clamp_range(1.0..=4.0));
+            }
+            Ior::Cauchy { a, b } => {
+                ui.add(
+                    DragValue::new(a)
+                        .speed(0.01)
+                        .clamp_range(1.0..=4.0)
+                        .prefix("a: "),
+                );
+                ui.add(
+                    DragValue::new(b)
+                        .speed(0.0001)
+                        .clamp_range(0.0..=1.0)
+                        .prefix("b: "),
+                );
+            }
+            Ior::Sellmeier { b, c } => {
+                ui.vertical(|ui| {
+                    for (prefix, coefficients) in [("b: ", b), ("c: ", c)] {
+                        ui.horizontal(|ui| {
+                            for coefficient in coefficients {
+                                ui.add(
+                                    DragValue::new(coefficient)
+                                        .speed(0.001)
+                                        .clamp_range(0.0..=1000.0)
+                                        .prefix(prefix),
+                                );
+                            }
+                        });
+                    }
+                });
+            }
+        }
+    });
+}
+
+fn dielectric_ior_name(ior: &Ior) -> &'static str {
+    match ior {
+        Ior::Constant(_) => "Constant",
+        Ior::Cauchy { .. } => "Cauchy",
+        Ior::Sellmeier { .. } => "Sellmeier",
+    }
+}
+
+fn roughness_editor(ui: &mut Ui, u: &mut Number, v: &mut Number) {
+    ui.horizontal(|ui| {
+        ui.label("Roughness");
+        ui.add(
+            DragValue::new(u)
+                .speed(0.01)
+                .clamp_range(0.0..=1.0)
+                .prefix("u: "),
+        );
+        ui.add(
+            DragValue::new(v)
+                .speed(0.01)
+                .clamp_range(0.0..=1.0)
+                .prefix("v: "),
+        );
+    });
 }
 
 fn main() {
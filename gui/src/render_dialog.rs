@@ -0,0 +1,539 @@
+use std::any::Any;
+use std::fs::File;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use eframe::{
+    egui::{
+        pos2, vec2, Checkbox, Color32, ColorImage, ComboBox, Context, DragValue, Grid, Pos2,
+        ProgressBar, Rect, Sense, Shape, Stroke, TextureHandle, Ui, Window,
+    },
+    epaint::mutex::RwLock,
+};
+use raytracer::{
+    render, Adaptive, Animation, Camera, CameraConfig, ColorSpace, Crop, DisplayTransform, Filter,
+    FilterKind, Image, ImageFormat, ImageWriter, Number, RenderOutput, RenderSettings, SamplerKind,
+    Scene, TransferFunction,
+};
+
+// Final quality renders go through `raytracer::render` with the same settings the headless
+// binary takes, so a scene renders identically in both.
+
+struct Job {
+    // Bits of the finished fraction, there is no atomic float.
+    progress: Arc<AtomicU32>,
+    cancel: Arc<AtomicBool>,
+    started: Instant,
+    handle: JoinHandle<Option<RenderOutput>>,
+    region: Crop,
+}
+
+// The latest render and where it sits in the frame. That's only the whole frame if it wasn't
+// cropped, or if the crop was pasted into a previous full render.
+struct Rendered {
+    image: Image,
+    texture: TextureHandle,
+    region: Crop,
+}
+
+pub struct RenderDialog {
+    pub open: bool,
+    settings: RenderSettings,
+    paste: bool,
+    format: ImageFormat,
+    display: DisplayTransform,
+    path: String,
+    job: Option<Job>,
+    rendered: Option<Rendered>,
+    status: Option<String>,
+    drag_start: Option<Pos2>,
+}
+
+impl RenderDialog {
+    pub fn new(camera: &CameraConfig) -> Self {
+        let mut settings = RenderSettings::default();
+        settings.height = (settings.width as Number / camera.aspect_ratio()) as usize;
+
+        Self {
+            open: false,
+            settings,
+            paste: true,
+            format: ImageFormat::Png,
+            display: DisplayTransform::default(),
+            path: "render.png".to_string(),
+            job: None,
+            rendered: None,
+            status: None,
+            drag_start: None,
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.job.is_some()
+    }
+
+    pub fn show(
+        &mut self,
+        ctx: &Context,
+        scene: &Arc<RwLock<Scene>>,
+        camera: &CameraConfig,
+        animation: &Animation,
+    ) {
+        self.poll(ctx);
+
+        let mut open = self.open;
+        Window::new("Render").open(&mut open).show(ctx, |ui| {
+            ui.add_enabled_ui(!self.is_running(), |ui| {
+                Grid::new("render settings").num_columns(2).show(ui, |ui| {
+                    let settings = &mut self.settings;
+
+                    ui.label("Resolution");
+                    ui.horizontal(|ui| {
+                        ui.add(DragValue::new(&mut settings.width).clamp_range(2..=16384));
+                        ui.label("x");
+                        ui.add(DragValue::new(&mut settings.height).clamp_range(2..=16384));
+                    });
+                    ui.end_row();
+
+                    ui.label("Samples");
+                    ui.add(DragValue::new(&mut settings.samples).clamp_range(1..=100000));
+                    ui.end_row();
+
+                    ui.label("Max Depth");
+                    ui.add(DragValue::new(&mut settings.max_depth).clamp_range(1..=1000));
+                    ui.end_row();
+
+                    ui.label("Seed");
+                    ui.add(DragValue::new(&mut settings.seed));
+                    ui.end_row();
+
+                    ui.label("Sampler");
+                    ComboBox::from_id_source("sampler")
+                        .selected_text(settings.sampler.name())
+                        .show_ui(ui, |ui| {
+                            for option in SamplerKind::ALL {
+                                ui.selectable_value(&mut settings.sampler, option, option.name());
+                            }
+                        });
+                    ui.end_row();
+
+                    ui.label("Filter");
+                    ui.horizontal(|ui| {
+                        let kind = settings.filter.kind;
+                        ComboBox::from_id_source("filter")
+                            .selected_text(kind.name())
+                            .show_ui(ui, |ui| {
+                                for option in FilterKind::ALL {
+                                    ui.selectable_value(
+                                        &mut settings.filter.kind,
+                                        option,
+                                        option.name(),
+                                    );
+                                }
+                            });
+                        if settings.filter.kind != kind {
+                            settings.filter = Filter::new(settings.filter.kind);
+                        }
+
+                        ui.add(
+                            DragValue::new(&mut settings.filter.radius)
+                                .speed(0.05)
+                                .clamp_range(0.5..=8.0)
+                                .prefix("radius: "),
+                        );
+                    });
+                    ui.end_row();
+
+                    let mut cropped = settings.crop.is_some();
+                    ui.checkbox(&mut cropped, "Crop");
+                    match (cropped, &mut settings.crop) {
+                        (true, Some(crop)) => {
+                            ui.horizontal(|ui| {
+                                ui.add(DragValue::new(&mut crop.x).prefix("x: "));
+                                ui.add(DragValue::new(&mut crop.y).prefix("y: "));
+                                ui.add(DragValue::new(&mut crop.width).prefix("w: "));
+                                ui.add(DragValue::new(&mut crop.height).prefix("h: "));
+                            });
+                        }
+                        (true, None) => {
+                            settings.crop = Some(Crop {
+                                x: settings.width / 4,
+                                y: settings.height / 4,
+                                width: settings.width / 2,
+                                height: settings.height / 2,
+                            });
+                        }
+                        (false, _) => settings.crop = None,
+                    }
+                    ui.end_row();
+
+                    ui.label("");
+                    ui.add_enabled(
+                        cropped,
+                        Checkbox::new(&mut self.paste, "Paste into previous frame"),
+                    );
+                    ui.end_row();
+
+                    let mut adaptive = settings.adaptive.is_some();
+                    ui.checkbox(&mut adaptive, "Adaptive");
+                    match (adaptive, &mut settings.adaptive) {
+                        (true, Some(adaptive)) => {
+                            ui.horizontal(|ui| {
+                                ui.add(
+                                    DragValue::new(&mut adaptive.threshold)
+                                        .speed(0.001)
+                                        .clamp_range(0.0..=1.0)
+                                        .prefix("error: "),
+                                );
+                                ui.add(
+                                    DragValue::new(&mut adaptive.min_samples)
+                                        .clamp_range(2..=100000)
+                                        .prefix("min: "),
+                                );
+                                ui.add(
+                                    DragValue::new(&mut adaptive.max_samples)
+                                        .clamp_range(2..=100000)
+                                        .prefix("max: "),
+                                );
+                            });
+                        }
+                        (true, None) => settings.adaptive = Some(Adaptive::default()),
+                        (false, _) => settings.adaptive = None,
+                    }
+                    ui.end_row();
+
+                    ui.label("");
+                    ui.checkbox(&mut settings.denoise, "Denoise");
+                    ui.end_row();
+
+                    ui.label("");
+                    ui.checkbox(&mut settings.spectral, "Spectral");
+                    ui.end_row();
+
+                    ui.label("Format");
+                    let format = self.format;
+                    ComboBox::from_id_source("format")
+                        .selected_text(format.extension())
+                        .show_ui(ui, |ui| {
+                            for option in ImageFormat::ALL {
+                                ui.selectable_value(&mut self.format, option, option.extension());
+                            }
+                        });
+                    if self.format != format {
+                        self.path = Path::new(&self.path)
+                            .with_extension(self.format.extension())
+                            .display()
+                            .to_string();
+                    }
+                    ui.end_row();
+
+                    // Only saved images get this transform. The preview is for the screen, so
+                    // it's always sRGB.
+                    ui.label("Color Space");
+                    let space = self.display.space;
+                    ComboBox::from_id_source("color_space")
+                        .selected_text(space.name())
+                        .show_ui(ui, |ui| {
+                            for option in ColorSpace::ALL {
+                                if ui
+                                    .selectable_label(space == option, option.name())
+                                    .clicked()
+                                {
+                                    self.display = DisplayTransform::new(option);
+                                }
+                            }
+                        });
+                    ui.end_row();
+
+                    ui.label("Transfer");
+                    ui.horizontal(|ui| {
+                        let transfer = &mut self.display.transfer;
+                        let kind = transfer_name(transfer);
+                        ComboBox::from_id_source("transfer")
+                            .selected_text(kind)
+                            .show_ui(ui, |ui| {
+                                let options = [
+                                    TransferFunction::Linear,
+                                    TransferFunction::Srgb,
+                                    TransferFunction::Gamma(2.2),
+                                ];
+
+                                for option in options {
+                                    let name = transfer_name(&option);
+                                    if ui.selectable_label(kind == name, name).clicked()
+                                        && kind != name
+                                    {
+                                        *transfer = option;
+                                    }
+                                }
+                            });
+                        if let TransferFunction::Gamma(gamma) = transfer {
+                            ui.add(DragValue::new(gamma).speed(0.01).clamp_range(1.0..=3.0));
+                        }
+                    });
+                    ui.end_row();
+                });
+            });
+
+            ui.separator();
+
+            match &self.job {
+                Some(job) => {
+                    let done = f32::from_bits(job.progress.load(Ordering::Relaxed));
+                    let elapsed = job.started.elapsed();
+                    let eta = if done > 0.0 {
+                        format_duration(elapsed.mul_f32((1.0 - done) / done))
+                    } else {
+                        "...".to_string()
+                    };
+
+                    ui.add(ProgressBar::new(done).show_percentage());
+                    ui.label(format!("Elapsed {}, ETA {}", format_duration(elapsed), eta));
+
+                    if ui.button("Cancel").clicked() {
+                        job.cancel.store(true, Ordering::Relaxed);
+                    }
+                }
+
+                None => {
+                    if ui.button("Render").clicked() {
+                        self.start(scene, camera, animation);
+                    }
+                }
+            }
+
+            if let Some(rendered) = &self.rendered {
+                ui.separator();
+
+                ui.horizontal(|ui| {
+                    ui.text_edit_singleline(&mut self.path);
+                    if ui.button("Save").clicked() {
+                        self.status = Some(
+                            match save(&rendered.image, &self.path, self.format, self.display) {
+                                Ok(()) => format!("Saved to {}", self.path),
+                                Err(e) => format!("Couldn't save image: {}", e),
+                            },
+                        );
+                    }
+                });
+            }
+
+            if let Some(status) = &self.status {
+                ui.label(status.as_str());
+            }
+        });
+
+        // The job is kept until `poll` joins it, since the thread holds on to the scene until
+        // it notices the cancellation. Editing stays locked until then.
+        if !open {
+            if let Some(job) = &self.job {
+                job.cancel.store(true, Ordering::Relaxed);
+            }
+        }
+
+        self.open = open;
+    }
+
+    /// Draws the latest render into the available space and lets a crop window be dragged out
+    /// on top of it.
+    pub fn viewport(&mut self, ui: &mut Ui) {
+        let (width, height) = (self.settings.width as f32, self.settings.height as f32);
+        let available = ui.available_rect_before_wrap();
+        let scale = (available.width() / width).min(available.height() / height);
+        let frame = Rect::from_center_size(available.center(), vec2(width, height) * scale);
+
+        let to_screen = |crop: &Crop| {
+            let min = vec2(crop.x as f32, crop.y as f32);
+            let size = vec2(crop.width as f32, crop.height as f32);
+            Rect::from_min_max(frame.min + min * scale, frame.min + (min + size) * scale)
+        };
+        let to_pixel = |pos: Pos2| {
+            let pos = (frame.clamp(pos) - frame.min) / scale;
+            (pos.x.round() as usize, pos.y.round() as usize)
+        };
+
+        let response = ui.allocate_rect(frame, Sense::drag());
+        let painter = ui.painter();
+        painter.rect_filled(frame, 0.0, Color32::BLACK);
+
+        if let Some(rendered) = &self.rendered {
+            painter.add(Shape::image(
+                rendered.texture.id(),
+                to_screen(&rendered.region),
+                Rect::from_min_max(pos2(0.0, 0.0), pos2(1.0, 1.0)),
+                Color32::WHITE,
+            ));
+        }
+
+        if !self.is_running() {
+            if response.drag_started() {
+                self.drag_start = response.interact_pointer_pos();
+            }
+
+            if let (true, Some(start), Some(end)) = (
+                response.dragged(),
+                self.drag_start,
+                response.interact_pointer_pos(),
+            ) {
+                let (x0, y0) = to_pixel(start);
+                let (x1, y1) = to_pixel(end);
+                if x0 != x1 && y0 != y1 {
+                    self.settings.crop = Some(Crop {
+                        x: x0.min(x1),
+                        y: y0.min(y1),
+                        width: x0.max(x1) - x0.min(x1),
+                        height: y0.max(y1) - y0.min(y1),
+                    });
+                }
+            }
+        }
+
+        if let Some(crop) = &self.settings.crop {
+            painter.rect_stroke(
+                to_screen(&crop.clamp(self.settings.width, self.settings.height)),
+                0.0,
+                Stroke::new(1.0, ui.visuals().hyperlink_color),
+            );
+        }
+    }
+
+    // Renders the first frame of the animation, posed the same way the headless binary poses it.
+    // Animated properties are set in the editor's scene too, which is what any render of it
+    // would see.
+    fn start(&mut self, scene: &Arc<RwLock<Scene>>, camera: &CameraConfig, animation: &Animation) {
+        let camera = match animation.pose(0.0, &mut scene.write(), camera) {
+            Ok(camera) => camera,
+            Err(e) => {
+                self.status = Some(format!("Couldn't render: {}", e));
+                return;
+            }
+        };
+
+        let progress = Arc::new(AtomicU32::new(0.0f32.to_bits()));
+        let cancel = Arc::new(AtomicBool::new(false));
+
+        let handle = {
+            let scene = scene.clone();
+            let camera = Camera::new(camera);
+            let settings = self.settings;
+            let progress = progress.clone();
+            let cancel = cancel.clone();
+
+            thread::spawn(move || {
+                render(&scene.read(), &camera, &settings, |done| {
+                    progress.store(done.to_bits(), Ordering::Relaxed);
+                    !cancel.load(Ordering::Relaxed)
+                })
+            })
+        };
+
+        self.status = None;
+        self.job = Some(Job {
+            progress,
+            cancel,
+            started: Instant::now(),
+            handle,
+            region: self.settings.region(),
+        });
+    }
+
+    fn poll(&mut self, ctx: &Context) {
+        match &self.job {
+            Some(job) if job.handle.is_finished() => {}
+            Some(_) => {
+                ctx.request_repaint();
+                return;
+            }
+            None => return,
+        }
+
+        let job = self.job.take().unwrap();
+        let elapsed = job.started.elapsed();
+
+        let mut image = match job.handle.join() {
+            Ok(Some(output)) => output.image,
+            Ok(None) => {
+                self.status = Some("Render cancelled".to_string());
+                return;
+            }
+            Err(payload) => {
+                self.status = Some(format!("Render failed: {}", panic_message(&*payload)));
+                return;
+            }
+        };
+        let mut region = job.region;
+
+        // Pasting only makes sense if the previous render covered the whole frame at the same
+        // resolution.
+        if let Some(previous) = self.rendered.take() {
+            let full = previous.region.x == 0
+                && previous.region.y == 0
+                && previous.image.width == self.settings.width
+                && previous.image.height == self.settings.height;
+
+            if self.paste && self.settings.crop.is_some() && full {
+                let mut frame = previous.image;
+                frame.paste(&image, region.x, region.y);
+                image = frame;
+                region = previous.region;
+            }
+        }
+
+        let rgba: Vec<u8> = image
+            .to_rgb8(&DisplayTransform::default())
+            .chunks(3)
+            .flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 255])
+            .collect();
+        let texture = ctx.load_texture(
+            "render",
+            ColorImage::from_rgba_unmultiplied([image.width, image.height], &rgba),
+        );
+
+        self.status = Some(format!("Rendered in {}", format_duration(elapsed)));
+        self.rendered = Some(Rendered {
+            image,
+            texture,
+            region,
+        });
+    }
+}
+
+fn save(
+    image: &Image,
+    path: &str,
+    format: ImageFormat,
+    display: DisplayTransform,
+) -> std::io::Result<()> {
+    ImageWriter::new(File::create(path)?, format, display).write(image)
+}
+
+fn transfer_name(transfer: &TransferFunction) -> &'static str {
+    match transfer {
+        TransferFunction::Linear => "Linear",
+        TransferFunction::Srgb => "sRGB",
+        TransferFunction::Gamma(_) => "Gamma",
+    }
+}
+
+// What a thread panicked with, which is a string unless something panicked with another value.
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "the render thread panicked"
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    if seconds >= 60 {
+        format!("{}m {}s", seconds / 60, seconds % 60)
+    } else {
+        format!("{:.1}s", duration.as_secs_f32())
+    }
+}
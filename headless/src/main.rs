@@ -1,98 +1,508 @@
-use std::fs::File;
+use std::fs::{self, File};
 use std::io;
-use std::time::Instant;
-
-use rand::{Rng, SeedableRng};
-use rand_xorshift::XorShiftRng;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
 
 use raytracer::{
-    color, vector3, Camera, CameraConfig, ImageWriter, Material, Number, Scene, Sky, Sphere,
+    color, render, vector3, Adaptive, Animation, Camera, CameraConfig, ColorSpace, Crop,
+    DisplayTransform, Filter, FilterKind, Float4, Hit, ImageFormat, ImageReader, ImageWriter,
+    Lambertian, Lens, Metal, Number, Profile, Projection, RayPacket, RenderSettings, SamplerKind,
+    Scene, SceneFile, Sky, Sphere, TransferFunction, LANES,
 };
 
-fn main() -> io::Result<()> {
-    let scene = Scene {
+const USAGE: &str = "usage: headless [scene file] [--output image.ppm] [--width n] [--height n] \
+[--samples n] [--max-depth n] [--seed n] \
+[--sampler independent|stratified|halton|sobol|blue-noise] \
+[--filter box|tent|gaussian|mitchell|lanczos [--filter-radius r]] [--denoise] [--spectral] [--crop x,y,width,height [--paste]] \
+[--adaptive threshold [--min-samples n] [--max-samples n] [--heatmap heatmap.ppm]] \
+[--color-space srgb|acescg|rec2020|display_p3|xyz [--transfer linear|srgb|<gamma>]] \
+[--frames first-last] [--stats-json stats.json] [--benchmark]
+
+`--paste` reads the rest of the frame back from the output. 8 bit outputs give back pixels that \
+were already quantized when they were written, which writing them again doesn't change, but the \
+renderer's full precision is lost. Paste into a .pfm output to keep it.
+
+`--benchmark` doesn't render anything. It times finding the first hit of a ray through every \
+pixel, one ray at a time and then in packets. Renders still trace every ray on its own, so packets \
+don't make them any faster yet.";
+
+struct Options {
+    scene: Option<String>,
+    output: String,
+    width: Option<usize>,
+    height: Option<usize>,
+    samples: Option<usize>,
+    max_depth: Option<usize>,
+    seed: Option<u64>,
+    sampler: Option<SamplerKind>,
+    filter: Option<Filter>,
+    denoise: bool,
+    spectral: bool,
+    crop: Option<Crop>,
+    paste: bool,
+    adaptive: Option<Adaptive>,
+    heatmap: Option<String>,
+    display: DisplayTransform,
+    frames: Option<(usize, usize)>,
+    stats_json: Option<String>,
+    benchmark: bool,
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut options = Options {
+            scene: None,
+            output: "image.ppm".to_string(),
+            width: None,
+            height: None,
+            samples: None,
+            max_depth: None,
+            seed: None,
+            sampler: None,
+            filter: None,
+            denoise: false,
+            spectral: false,
+            crop: None,
+            paste: false,
+            adaptive: None,
+            heatmap: None,
+            display: DisplayTransform::default(),
+            frames: None,
+            stats_json: None,
+            benchmark: false,
+        };
+        let mut min_samples = None;
+        let mut max_samples = None;
+        let mut filter_radius = None;
+        let mut transfer = None;
+
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("missing value for `{}`", arg));
+
+            match arg.as_str() {
+                "--output" => options.output = value()?,
+                "--width" => options.width = Some(parse(&value()?)?),
+                "--height" => options.height = Some(parse(&value()?)?),
+                "--samples" => options.samples = Some(parse(&value()?)?),
+                "--max-depth" => options.max_depth = Some(parse(&value()?)?),
+                "--seed" => options.seed = Some(parse(&value()?)?),
+                "--sampler" => {
+                    let name = value()?;
+                    options.sampler = Some(
+                        SamplerKind::from_name(&name)
+                            .ok_or(format!("unknown sampler `{}`", name))?,
+                    )
+                }
+                "--filter" => {
+                    let name = value()?;
+                    options.filter = Some(Filter::new(
+                        FilterKind::from_name(&name).ok_or(format!("unknown filter `{}`", name))?,
+                    ))
+                }
+                "--filter-radius" => filter_radius = Some(parse(&value()?)?),
+                "--denoise" => options.denoise = true,
+                "--spectral" => options.spectral = true,
+                "--crop" => options.crop = Some(parse_crop(&value()?)?),
+                "--paste" => options.paste = true,
+                "--adaptive" => {
+                    options.adaptive = Some(Adaptive {
+                        threshold: parse(&value()?)?,
+                        ..Adaptive::default()
+                    })
+                }
+                "--min-samples" => min_samples = Some(parse(&value()?)?),
+                "--max-samples" => max_samples = Some(parse(&value()?)?),
+                "--heatmap" => options.heatmap = Some(value()?),
+                "--color-space" => {
+                    let name = value()?;
+                    options.display = DisplayTransform::new(
+                        ColorSpace::from_name(&name)
+                            .ok_or(format!("unknown color space `{}`", name))?,
+                    )
+                }
+                "--transfer" => transfer = Some(parse_transfer(&value()?)?),
+                "--frames" => options.frames = Some(parse_frames(&value()?)?),
+                "--stats-json" => options.stats_json = Some(value()?),
+                "--benchmark" => options.benchmark = true,
+                _ if arg.starts_with("--") => return Err(format!("unknown option `{}`", arg)),
+                _ if options.scene.is_none() => options.scene = Some(arg),
+                _ => return Err(format!("unexpected argument `{}`", arg)),
+            }
+        }
+
+        if options.paste && options.crop.is_none() {
+            return Err("`--paste` needs a `--crop` window".to_string());
+        }
+
+        if options.paste && options.frames.is_some() {
+            return Err("`--paste` only works on a single image".to_string());
+        }
+
+        if let Some(transfer) = transfer {
+            options.display.transfer = transfer;
+        }
+
+        match (&mut options.filter, filter_radius) {
+            (Some(filter), Some(radius)) => filter.radius = radius,
+            (None, Some(_)) => return Err("`--filter-radius` needs a `--filter`".to_string()),
+            _ => {}
+        }
+
+        match &mut options.adaptive {
+            Some(adaptive) => {
+                adaptive.min_samples = min_samples.unwrap_or(adaptive.min_samples).max(2);
+                adaptive.max_samples = max_samples.unwrap_or(adaptive.max_samples);
+            }
+            None if min_samples.is_some() || max_samples.is_some() => {
+                return Err("sample limits need `--adaptive`".to_string());
+            }
+            None => {}
+        }
+
+        Ok(options)
+    }
+}
+
+fn parse<T: FromStr>(value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value `{}`", value))
+}
+
+fn image_format(path: &str) -> io::Result<ImageFormat> {
+    ImageFormat::from_path(path).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("unknown image format for `{}`", path),
+        )
+    })
+}
+
+fn parse_crop(value: &str) -> Result<Crop, String> {
+    let parts = value
+        .split(',')
+        .map(parse)
+        .collect::<Result<Vec<usize>, _>>()?;
+
+    match parts[..] {
+        [x, y, width, height] => Ok(Crop {
+            x,
+            y,
+            width,
+            height,
+        }),
+        _ => Err(format!("invalid crop window `{}`", value)),
+    }
+}
+
+// A gamma is given as just its exponent.
+fn parse_transfer(value: &str) -> Result<TransferFunction, String> {
+    match value {
+        "linear" => Ok(TransferFunction::Linear),
+        "srgb" => Ok(TransferFunction::Srgb),
+        _ => value
+            .parse()
+            .map(TransferFunction::Gamma)
+            .map_err(|_| format!("unknown transfer function `{}`", value)),
+    }
+}
+
+fn parse_frames(value: &str) -> Result<(usize, usize), String> {
+    let (first, last) = match value.split_once('-') {
+        Some((first, last)) => (parse(first)?, parse(last)?),
+        None => {
+            let frame = parse(value)?;
+            (frame, frame)
+        }
+    };
+
+    if first > last {
+        return Err(format!("invalid frame range `{}`", value));
+    }
+    Ok((first, last))
+}
+
+// Numbers `path` with `frame`, in place of a run of `#`s padded to their length, or else before
+// the extension padded to 4 digits.
+fn frame_path(path: &str, frame: usize) -> String {
+    if let Some(start) = path.find('#') {
+        let width = path[start..].chars().take_while(|&c| c == '#').count();
+        return format!(
+            "{}{:0width$}{}",
+            &path[..start],
+            frame,
+            &path[start + width..],
+            width = width
+        );
+    }
+
+    let path = Path::new(path);
+    let stem = path.with_extension("");
+    match path.extension() {
+        Some(extension) => format!(
+            "{}_{:04}.{}",
+            stem.display(),
+            frame,
+            extension.to_string_lossy()
+        ),
+        None => format!("{}_{:04}", stem.display(), frame),
+    }
+}
+
+// Traces a ray through the center of every pixel, once per sample, on its own and then in
+// packets, and reports how many rays per second get through the scene either way. Pixels map to
+// the camera the same way they do in a render.
+fn benchmark(scene: &Scene, camera: &Camera, settings: &RenderSettings) {
+    let mut rays = (0..settings.height)
+        .flat_map(|y| (0..settings.width).map(move |x| (x, y)))
+        .filter_map(|(x, y)| {
+            camera.get_ray(
+                (x as Number + 0.5) / (settings.width - 1) as Number,
+                (y as Number + 0.5) / (settings.height - 1) as Number,
+                (0.5, 0.5),
+                0.5,
+            )
+        })
+        .collect::<Vec<_>>();
+    rays.truncate(rays.len() / LANES * LANES);
+
+    let report = |name: &str, hits: usize, elapsed: Duration| {
+        let count = rays.len() * settings.samples;
+        println!(
+            "{}: {} of {} rays hit in {}ms, {:.2} Mrays/s",
+            name,
+            hits,
+            count,
+            elapsed.as_millis(),
+            count as f64 / elapsed.as_secs_f64() / 1e6
+        );
+    };
+
+    let start = Instant::now();
+    let mut hits = 0;
+    for _ in 0..settings.samples {
+        hits += rays
+            .iter()
+            .filter(|ray| scene.hit(ray, 0.0, Number::INFINITY).is_some())
+            .count();
+    }
+    report("Single rays", hits, start.elapsed());
+
+    let packets = rays
+        .chunks_exact(LANES)
+        .map(|chunk| RayPacket::new(chunk.try_into().unwrap()))
+        .collect::<Vec<_>>();
+
+    let start = Instant::now();
+    let mut hits = 0;
+    for _ in 0..settings.samples {
+        for packet in &packets {
+            hits += scene
+                .hit_packet(packet, 0.0, Float4::splat(Number::INFINITY))
+                .iter()
+                .filter(|hit| hit.is_some())
+                .count();
+        }
+    }
+    report(&format!("Packets of {}", LANES), hits, start.elapsed());
+}
+
+fn default_scene() -> Scene {
+    Scene {
         sky: Sky {
             top: color(0.5, 0.7, 1.0),
             bottom: color(1.0, 1.0, 1.0),
         },
+        fog: None,
         objects: vec![
             Box::new(Sphere {
                 center: vector3(0.0, -100.5, -1.0),
                 radius: 100.0,
-                material: Material::Lambertian {
+                material: Box::new(Lambertian {
                     albedo: color(0.8, 0.8, 0.0),
-                },
+                }),
             }),
             Box::new(Sphere {
                 center: vector3(0.0, 0.0, -1.0),
                 radius: 0.5,
-                material: Material::Lambertian {
+                material: Box::new(Lambertian {
                     albedo: color(1.0, 0.3, 0.3),
-                },
+                }),
             }),
             Box::new(Sphere {
                 center: vector3(-1.0, 0.0, -1.0),
                 radius: 0.5,
-                material: Material::Metal {
+                material: Box::new(Metal {
                     albedo: color(0.3, 0.3, 0.3),
                     fuzz: 0.5,
-                },
+                }),
             }),
             Box::new(Sphere {
                 center: vector3(1.0, 0.0, -1.0),
                 radius: 0.5,
-                material: Material::Metal {
+                material: Box::new(Metal {
                     albedo: color(0.8, 0.0, 0.0),
                     fuzz: 0.5,
-                },
+                }),
             }),
         ],
-    };
+    }
+}
 
-    let aspect_ratio = 16.0 / 9.0;
-    let samples = 500;
-    let max_depth = 5;
+fn main() -> io::Result<()> {
+    let options = Options::parse(std::env::args().skip(1)).unwrap_or_else(|e| {
+        eprintln!("{}\n{}", e, USAGE);
+        std::process::exit(1);
+    });
 
-    let image_width = 400;
-    let image_height = (image_width as f32 / aspect_ratio) as usize;
+    let mut profile = Profile::default();
+    let start = Instant::now();
 
-    let viewport_height = 2.0;
-    let viewport_width = viewport_height * aspect_ratio;
-    let focal_length = 1.0;
+    let SceneFile {
+        mut scene,
+        camera,
+        animation,
+    } = match &options.scene {
+        Some(path) => SceneFile::load(path)?,
+        None => {
+            let aspect_ratio = 16.0 / 9.0;
+            let viewport_height = 2.0;
 
-    let camera = Camera::new(CameraConfig {
-        position: vector3(0.0, 0.0, 0.0),
-        viewport_width,
-        viewport_height,
-        focal_length,
-    });
+            SceneFile {
+                scene: default_scene(),
+                camera: CameraConfig {
+                    position: vector3(0.0, 0.0, 0.0),
+                    viewport_width: viewport_height * aspect_ratio,
+                    viewport_height,
+                    focal_length: 1.0,
+                    target: None,
+                    projection: Projection::Perspective,
+                    lens: Lens::Pinhole,
+                    shutter_open: 0.0,
+                    shutter_close: 0.0,
+                    stereo: None,
+                },
+                animation: Animation::default(),
+            }
+        }
+    };
 
-    let mut writer = ImageWriter::new(File::create("image.ppm")?, image_width, image_height);
+    let format = image_format(&options.output)?;
 
-    let start = Instant::now();
+    let defaults = RenderSettings::default();
+    let width = options.width.unwrap_or(defaults.width);
+    let settings = RenderSettings {
+        width,
+        height: options
+            .height
+            .unwrap_or((width as Number / camera.aspect_ratio()) as usize),
+        samples: options.samples.unwrap_or(defaults.samples),
+        max_depth: options.max_depth.unwrap_or(defaults.max_depth),
+        seed: options.seed.unwrap_or(defaults.seed),
+        crop: options.crop,
+        adaptive: options.adaptive,
+        sampler: options.sampler.unwrap_or(defaults.sampler),
+        filter: options.filter.unwrap_or(defaults.filter),
+        denoise: options.denoise,
+        spectral: options.spectral,
+    };
 
-    let thread_rand = rand::thread_rng();
-    let mut rand = XorShiftRng::from_rng(thread_rand).unwrap();
-
-    for j in (0..image_height).rev() {
-        for i in 0..image_width {
-            writer.write_pixel(
-                (0..samples)
-                    .map(|_| {
-                        let u = (i as Number + rand.gen::<Number>()) / (image_width - 1) as Number;
-                        let v = (j as Number + rand.gen::<Number>()) / (image_height - 1) as Number;
-                        scene.ray_color(&camera.get_ray(u, v), max_depth, &mut rand)
-                    })
-                    .reduce(|accum, sample| accum + sample)
-                    .unwrap(),
-                samples,
-            );
+    // The previous frame has to be read before the output file gets truncated. Decoding and
+    // encoding 8 bit pixels gives back the same bytes, so only the crop gets quantized anew.
+    let previous = if options.paste {
+        let frame =
+            ImageReader::new(File::open(&options.output)?, format, options.display).read()?;
+        if frame.width != settings.width || frame.height != settings.height {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "`{}` is {}x{}, not {}x{}",
+                    options.output, frame.width, frame.height, settings.width, settings.height
+                ),
+            ));
         }
+        Some(frame)
+    } else {
+        None
+    };
+
+    profile.add_phase("load", start.elapsed());
+
+    if options.benchmark {
+        let camera = animation.pose(0.0, &mut scene, &camera)?;
+        benchmark(&scene, &Camera::new(camera), &settings);
+        return Ok(());
     }
 
-    println!("Image rendered in {}ms", start.elapsed().as_millis());
+    let frames = match options.frames {
+        Some((first, last)) => (first..=last).map(Some).collect(),
+        None => vec![None],
+    };
+
+    for frame in frames {
+        // Frames that are already there are skipped, so a stopped sequence picks up where it was.
+        let (output, time) = match frame {
+            Some(frame) => {
+                let output = frame_path(&options.output, frame);
+                if Path::new(&output).exists() {
+                    println!("Skipping {}, it already exists", output);
+                    continue;
+                }
+                (output, animation.frame_time(frame))
+            }
+            None => (options.output.clone(), 0.0),
+        };
+
+        let start = Instant::now();
+        let camera = Camera::new(animation.pose(time, &mut scene, &camera)?);
+        profile.add_phase("build", start.elapsed());
+
+        let output_image =
+            render(&scene, &camera, &settings, |_| true).expect("render isn't cancelled");
+        let stats = output_image.stats;
+        profile.add_phase("render", stats.render_time);
+        profile.stats += stats;
+
+        println!("Image rendered in {}ms", stats.render_time.as_millis());
 
-    writer.flush();
+        let start = Instant::now();
+
+        if let Some(path) = &options.heatmap {
+            let path = match frame {
+                Some(frame) => frame_path(path, frame),
+                None => path.clone(),
+            };
+            ImageWriter::new(File::create(&path)?, image_format(&path)?, options.display)
+                .write(&output_image.heatmap(&options.display))?;
+        }
+
+        let mut image = output_image.image;
+
+        if let Some(frame) = &previous {
+            let mut frame = frame.clone();
+            let region = settings.region();
+            frame.paste(&image, region.x, region.y);
+            image = frame;
+        }
+
+        ImageWriter::new(File::create(&output)?, format, options.display).write(&image)?;
+        profile.add_phase("write", start.elapsed());
+    }
+
+    profile.peak_memory = peak_memory();
+    print!("{}", profile.table());
+    if let Some(path) = &options.stats_json {
+        fs::write(path, profile.json())?;
+    }
 
     Ok(())
 }
+
+// Linux keeps the high water mark of the resident set in /proc. Elsewhere it stays unknown.
+fn peak_memory() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    let kilobytes: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kilobytes * 1024)
+}
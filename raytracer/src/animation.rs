@@ -0,0 +1,602 @@
+use std::io;
+use std::ops::{Add, Mul};
+
+use super::camera::CameraConfig;
+use super::color::{color, Color};
+use super::conductor::Conductor;
+use super::dielectric::{Dielectric, Ior};
+use super::hit::Hit;
+use super::material::{Lambertian, Material, Metal};
+use super::principled::Principled;
+use super::scene::Scene;
+use super::sphere::{MovingSphere, Sphere};
+use super::texture::Texture;
+use super::transform::{Instance, Transform};
+use super::vector::Vector3;
+use super::Number;
+
+// Animations change the scene and the camera from one frame to the next. Each channel follows a
+// track of keyframes and sets a single property to its value at the time of the frame, so
+// applying them again for another frame starts over cleanly. Motion within a frame is left to
+// moving spheres and instances, which follow the time of each ray through the shutter.
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Interpolation {
+    /// Straight lines between keyframes.
+    Linear,
+    /// A smooth curve through every keyframe, with each one's tangent pointing from the keyframe
+    /// before it to the one after.
+    CatmullRom,
+}
+
+impl Interpolation {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Interpolation::Linear => "linear",
+            Interpolation::CatmullRom => "catmull_rom",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "linear" => Some(Interpolation::Linear),
+            "catmull_rom" => Some(Interpolation::CatmullRom),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub struct Keyframe<T> {
+    pub time: Number,
+    pub value: T,
+}
+
+#[derive(Clone, PartialEq)]
+pub struct Track<T> {
+    pub interpolation: Interpolation,
+    /// Sorted by time.
+    pub keyframes: Vec<Keyframe<T>>,
+}
+
+impl<T: Copy + Add<Output = T> + Mul<Number, Output = T>> Track<T> {
+    /// The value at `time`, which holds still before the first keyframe and after the last one.
+    /// `None` without any keyframes.
+    pub fn value(&self, time: Number) -> Option<T> {
+        let keyframes = &self.keyframes;
+        let next = keyframes.iter().position(|keyframe| keyframe.time > time);
+
+        let index = match next {
+            None => return keyframes.last().map(|keyframe| keyframe.value),
+            Some(0) => return Some(keyframes[0].value),
+            Some(index) => index,
+        };
+
+        let (a, b) = (&keyframes[index - 1], &keyframes[index]);
+        let s = (time - a.time) / (b.time - a.time);
+
+        Some(match self.interpolation {
+            Interpolation::Linear => a.value * (1.0 - s) + b.value * s,
+            Interpolation::CatmullRom => {
+                // The ends repeat themselves, which flattens the curve out towards them.
+                let before = &keyframes[index.saturating_sub(2)];
+                let after = &keyframes[(index + 1).min(keyframes.len() - 1)];
+
+                // Tangents are scaled to the length of this segment, so uneven spacing between
+                // keyframes doesn't make the curve overshoot.
+                let span = b.time - a.time;
+                let tangent = |from: &Keyframe<T>, to: &Keyframe<T>| {
+                    (to.value + from.value * -1.0) * (span / (to.time - from.time))
+                };
+                let (m0, m1) = (tangent(before, b), tangent(a, after));
+
+                let (s2, s3) = (s * s, s * s * s);
+                a.value * (2.0 * s3 - 3.0 * s2 + 1.0)
+                    + m0 * (s3 - 2.0 * s2 + s)
+                    + b.value * (-2.0 * s3 + 3.0 * s2)
+                    + m1 * (s3 - s2)
+            }
+        })
+    }
+}
+
+/// A property of the scene or the camera, and the track it follows. Objects are picked by their
+/// index in the scene, and their material by the name of its parameter, as in scene files.
+#[derive(Clone, PartialEq)]
+pub enum Channel {
+    CameraPosition(Track<Vector3>),
+    /// Makes the camera look at a point, see `CameraConfig::target`.
+    CameraTarget(Track<Vector3>),
+    /// The horizontal field of view of the perspective projection in degrees, set by resizing the
+    /// viewport at the same aspect ratio.
+    CameraFov(Track<Number>),
+    /// The center of a sphere, or where a moving sphere starts from.
+    Center {
+        object: usize,
+        track: Track<Vector3>,
+    },
+    /// The transform of an instance. Its own keyframes move along with it, so it keeps moving the
+    /// same way within the frame.
+    Transform {
+        object: usize,
+        track: Track<Transform>,
+    },
+    MaterialColor {
+        object: usize,
+        parameter: String,
+        track: Track<Color>,
+    },
+    MaterialNumber {
+        object: usize,
+        parameter: String,
+        track: Track<Number>,
+    },
+}
+
+impl Channel {
+    /// Material parameters holding a color, where every other one holds a number.
+    pub const COLOR_PARAMETERS: [&'static str; 2] = ["albedo", "base_color"];
+}
+
+#[derive(Clone, PartialEq)]
+pub struct Animation {
+    /// Frame `n` of the animation shows time `n / fps`.
+    pub fps: Number,
+    pub channels: Vec<Channel>,
+}
+
+impl Default for Animation {
+    fn default() -> Self {
+        Self {
+            fps: 24.0,
+            channels: Vec::new(),
+        }
+    }
+}
+
+impl Animation {
+    pub fn frame_time(&self, frame: usize) -> Number {
+        frame as Number / self.fps
+    }
+
+    /// Makes sure every channel has something to animate in `scene`, without changing it.
+    pub fn check(&self, scene: &Scene) -> io::Result<()> {
+        let object = |index: usize| match scene.objects.get(index) {
+            Some(object) => Ok(object.as_ref()),
+            None => Err(invalid(format!("there's no object {} to animate", index))),
+        };
+
+        for channel in &self.channels {
+            match channel {
+                Channel::Center { object: index, .. } => {
+                    let object = object(*index)?;
+                    let any = object.as_any();
+                    if !any.is::<Sphere>() && !any.is::<MovingSphere>() {
+                        return Err(invalid(format!("`{}` has no center", object.name())));
+                    }
+                }
+                Channel::Transform { object: index, .. } => {
+                    let object = object(*index)?;
+                    if !object.as_any().is::<Instance>() {
+                        return Err(invalid(format!("`{}` has no transform", object.name())));
+                    }
+                }
+                Channel::MaterialColor {
+                    object: index,
+                    parameter,
+                    ..
+                } => {
+                    let mut material = material_of(object(*index)?)?;
+                    if !set_color(material.as_mut(), parameter, color(0.0, 0.0, 0.0)) {
+                        return Err(no_parameter(material.as_ref(), parameter));
+                    }
+                }
+                Channel::MaterialNumber {
+                    object: index,
+                    parameter,
+                    ..
+                } => {
+                    let mut material = material_of(object(*index)?)?;
+                    if !set_number(material.as_mut(), parameter, 0.0) {
+                        return Err(no_parameter(material.as_ref(), parameter));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sets every animated property to its value at `time`. Fails when a channel points at an
+    /// object that doesn't exist or a parameter its material doesn't have.
+    pub fn apply(
+        &self,
+        time: Number,
+        scene: &mut Scene,
+        camera: &mut CameraConfig,
+    ) -> io::Result<()> {
+        for channel in &self.channels {
+            match channel {
+                Channel::CameraPosition(track) => {
+                    if let Some(position) = track.value(time) {
+                        camera.position = position;
+                    }
+                }
+                Channel::CameraTarget(track) => {
+                    if let Some(target) = track.value(time) {
+                        camera.target = Some(target);
+                    }
+                }
+                Channel::CameraFov(track) => {
+                    if let Some(fov) = track.value(time) {
+                        let aspect_ratio = camera.viewport_width / camera.viewport_height;
+                        camera.viewport_width =
+                            2.0 * camera.focal_length * (fov.to_radians() / 2.0).tan();
+                        camera.viewport_height = camera.viewport_width / aspect_ratio;
+                    }
+                }
+                Channel::Center { object, track } => {
+                    let object = find(scene, *object)?;
+                    let center = match track.value(time) {
+                        Some(center) => center,
+                        None => continue,
+                    };
+
+                    let name = object.name();
+                    let any = object.as_any_mut();
+                    if let Some(sphere) = any.downcast_mut::<Sphere>() {
+                        sphere.center = center;
+                    } else if let Some(sphere) = any.downcast_mut::<MovingSphere>() {
+                        sphere.center1 = sphere.center1 - sphere.center0 + center;
+                        sphere.center0 = center;
+                    } else {
+                        return Err(invalid(format!("`{}` has no center", name)));
+                    }
+                }
+                Channel::Transform { object, track } => {
+                    let object = find(scene, *object)?;
+                    let transform = match track.value(time) {
+                        Some(transform) => transform,
+                        None => continue,
+                    };
+
+                    let name = object.name();
+                    let instance = match object.as_any_mut().downcast_mut::<Instance>() {
+                        Some(instance) => instance,
+                        None => return Err(invalid(format!("`{}` has no transform", name))),
+                    };
+
+                    // Every keyframe shifts by the same amount, which moves the whole track to
+                    // pass through `transform` at `time`, since blending keeps the weights
+                    // adding up to 1. Without any keyframes, the instance stands still where
+                    // the default transform puts it.
+                    let shift = transform + instance.transform(time) * -1.0;
+                    if instance.motion.keyframes.is_empty() {
+                        instance.motion.keyframes.push(Keyframe {
+                            time,
+                            value: Transform::default(),
+                        });
+                    }
+                    for keyframe in &mut instance.motion.keyframes {
+                        keyframe.value = keyframe.value + shift;
+                    }
+                }
+                Channel::MaterialColor {
+                    object,
+                    parameter,
+                    track,
+                } => {
+                    let material = material(find(scene, *object)?)?;
+                    if let Some(value) = track.value(time) {
+                        if !set_color(material, parameter, value) {
+                            return Err(no_parameter(material, parameter));
+                        }
+                    }
+                }
+                Channel::MaterialNumber {
+                    object,
+                    parameter,
+                    track,
+                } => {
+                    let material = material(find(scene, *object)?)?;
+                    if let Some(value) = track.value(time) {
+                        if !set_number(material, parameter, value) {
+                            return Err(no_parameter(material, parameter));
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Poses the scene and the camera for the frame at `time`. Every animated property gets set,
+    /// and the returned camera's shutter opens and closes relative to the time of the frame.
+    pub fn pose(
+        &self,
+        time: Number,
+        scene: &mut Scene,
+        camera: &CameraConfig,
+    ) -> io::Result<CameraConfig> {
+        let mut camera = camera.clone();
+        self.apply(time, scene, &mut camera)?;
+        camera.shutter_open += time;
+        camera.shutter_close += time;
+        Ok(camera)
+    }
+}
+
+fn find(scene: &mut Scene, index: usize) -> io::Result<&mut dyn Hit> {
+    match scene.objects.get_mut(index) {
+        Some(object) => Ok(object.as_mut()),
+        None => Err(invalid(format!("there's no object {} to animate", index))),
+    }
+}
+
+fn material(object: &mut dyn Hit) -> io::Result<&mut dyn Material> {
+    let name = object.name();
+    let any = object.as_any_mut();
+
+    // Checked first, since the borrow of a successful downcast would outlive the next attempt.
+    if any.is::<Sphere>() {
+        Ok(any.downcast_mut::<Sphere>().unwrap().material.as_mut())
+    } else if let Some(sphere) = any.downcast_mut::<MovingSphere>() {
+        Ok(sphere.material.as_mut())
+    } else {
+        Err(invalid(format!("`{}` has no material to animate", name)))
+    }
+}
+
+// A copy of the object's material, to try parameters on.
+fn material_of(object: &dyn Hit) -> io::Result<Box<dyn Material>> {
+    let any = object.as_any();
+
+    if let Some(sphere) = any.downcast_ref::<Sphere>() {
+        Ok(sphere.material.clone())
+    } else if let Some(sphere) = any.downcast_ref::<MovingSphere>() {
+        Ok(sphere.material.clone())
+    } else {
+        Err(invalid(format!(
+            "`{}` has no material to animate",
+            object.name()
+        )))
+    }
+}
+
+fn set_color(material: &mut dyn Material, parameter: &str, value: Color) -> bool {
+    let any = material.as_any_mut();
+
+    match parameter {
+        "albedo" => {
+            if let Some(lambertian) = any.downcast_mut::<Lambertian>() {
+                lambertian.albedo = value;
+            } else if let Some(metal) = any.downcast_mut::<Metal>() {
+                metal.albedo = value;
+            } else {
+                return false;
+            }
+        }
+        "base_color" => match any.downcast_mut::<Principled>() {
+            Some(principled) => principled.base_color = Texture::Constant(value),
+            None => return false,
+        },
+        _ => return false,
+    }
+
+    true
+}
+
+// Roughness sets both directions of anisotropic materials, and textured parameters become
+// constant.
+fn set_number(material: &mut dyn Material, parameter: &str, value: Number) -> bool {
+    let any = material.as_any_mut();
+
+    if let Some(metal) = any.downcast_mut::<Metal>() {
+        match parameter {
+            "fuzz" => metal.fuzz = value,
+            _ => return false,
+        }
+    } else if let Some(conductor) = any.downcast_mut::<Conductor>() {
+        match parameter {
+            "roughness" => {
+                conductor.roughness_u = value;
+                conductor.roughness_v = value;
+            }
+            _ => return false,
+        }
+    } else if let Some(dielectric) = any.downcast_mut::<Dielectric>() {
+        match parameter {
+            "roughness" => {
+                dielectric.roughness_u = value;
+                dielectric.roughness_v = value;
+            }
+            "ior" => dielectric.ior = Ior::Constant(value),
+            _ => return false,
+        }
+    } else if let Some(principled) = any.downcast_mut::<Principled>() {
+        let texture = match parameter {
+            "metallic" => &mut principled.metallic,
+            "roughness" => &mut principled.roughness,
+            "specular" => &mut principled.specular,
+            "specular_tint" => &mut principled.specular_tint,
+            "sheen" => &mut principled.sheen,
+            "clearcoat" => &mut principled.clearcoat,
+            "transmission" => &mut principled.transmission,
+            "ior" => &mut principled.ior,
+            _ => return false,
+        };
+        *texture = Texture::Constant(color(value, value, value));
+    } else {
+        return false;
+    }
+
+    true
+}
+
+fn no_parameter(material: &dyn Material, parameter: &str) -> io::Error {
+    invalid(format!(
+        "`{}` has no `{}` to animate",
+        material.name(),
+        parameter
+    ))
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::camera::{Lens, Projection};
+    use super::super::scene::Sky;
+    use super::super::vector::vector3;
+    use super::*;
+
+    fn track<T>(interpolation: Interpolation, keyframes: &[(Number, T)]) -> Track<T>
+    where
+        T: Copy,
+    {
+        Track {
+            interpolation,
+            keyframes: keyframes
+                .iter()
+                .map(|&(time, value)| Keyframe { time, value })
+                .collect(),
+        }
+    }
+
+    // Unevenly spaced, and going back and forth.
+    const KEYS: [(Number, Number); 5] =
+        [(0.0, 1.0), (0.5, 3.0), (2.0, -1.0), (2.5, 0.0), (6.0, 4.0)];
+
+    #[test]
+    fn catmull_rom_passes_through_its_keys() {
+        let numbers = track(Interpolation::CatmullRom, &KEYS);
+        let vectors = track(
+            Interpolation::CatmullRom,
+            &KEYS.map(|(time, value)| (time, vector3(value, -value, 2.0 * value))),
+        );
+
+        for (time, value) in KEYS {
+            assert!((numbers.value(time).unwrap() - value).abs() < 1e-5);
+            let vector = vectors.value(time).unwrap();
+            assert!((vector - vector3(value, -value, 2.0 * value)).length() < 1e-5);
+        }
+
+        // And doesn't just jump between them.
+        let between = numbers.value(1.0).unwrap();
+        assert!(between > -1.0 && between < 3.0);
+    }
+
+    #[test]
+    fn values_hold_before_the_first_key_and_after_the_last() {
+        for interpolation in [Interpolation::Linear, Interpolation::CatmullRom] {
+            let track = track(interpolation, &KEYS);
+            assert_eq!(track.value(-3.0), Some(1.0));
+            assert_eq!(track.value(6.0), Some(4.0));
+            assert_eq!(track.value(100.0), Some(4.0));
+        }
+
+        let empty: Track<Number> = track(Interpolation::Linear, &[]);
+        assert_eq!(empty.value(1.0), None);
+    }
+
+    #[test]
+    fn linear_tracks_are_linear_between_keys() {
+        let track = track(Interpolation::Linear, &KEYS);
+
+        for pair in KEYS.windows(2) {
+            let ((t0, v0), (t1, v1)) = (pair[0], pair[1]);
+            for step in 0..=10 {
+                let s = step as Number / 10.0;
+                let value = track.value(t0 + (t1 - t0) * s).unwrap();
+                assert!((value - (v0 + (v1 - v0) * s)).abs() < 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn transform_channels_move_instances_along_with_their_motion() {
+        let at = |x: Number| Transform {
+            translation: vector3(x, 0.0, 0.0),
+            ..Transform::default()
+        };
+
+        // Drifting along x by itself, one unit per second.
+        let mut scene = Scene {
+            objects: vec![Box::new(Instance {
+                object: Box::new(Sphere {
+                    center: vector3(0.0, 0.0, 0.0),
+                    radius: 1.0,
+                    material: Box::new(Lambertian {
+                        albedo: color(1.0, 1.0, 1.0),
+                    }),
+                }),
+                motion: track(Interpolation::Linear, &[(0.0, at(0.0)), (100.0, at(100.0))]),
+            })],
+            sky: Sky {
+                top: color(1.0, 1.0, 1.0),
+                bottom: color(1.0, 1.0, 1.0),
+            },
+            fog: None,
+        };
+        let camera = CameraConfig {
+            position: vector3(0.0, 0.0, 0.0),
+            viewport_width: 2.0,
+            viewport_height: 2.0,
+            focal_length: 1.0,
+            target: None,
+            projection: Projection::Perspective,
+            lens: Lens::Pinhole,
+            shutter_open: 0.0,
+            shutter_close: 0.5,
+            stereo: None,
+        };
+
+        // While the channel moves it down and up again.
+        let lift = |y: Number| Transform {
+            translation: vector3(0.0, y, -5.0),
+            ..Transform::default()
+        };
+        let animation = Animation {
+            fps: 1.0,
+            channels: vec![Channel::Transform {
+                object: 0,
+                track: track(
+                    Interpolation::Linear,
+                    &[(0.0, lift(-2.0)), (10.0, lift(2.0))],
+                ),
+            }],
+        };
+        animation.check(&scene).unwrap();
+
+        for (time, y) in [(5.0, 0.0), (2.5, -1.0), (10.0, 2.0)] {
+            let camera = animation.pose(time, &mut scene, &camera).unwrap();
+            let instance = scene.objects[0]
+                .as_any()
+                .downcast_ref::<Instance>()
+                .unwrap();
+
+            // Posed at the start of the shutter, and still drifting at the end of it.
+            let start = instance.transform(camera.shutter_open).translation;
+            let end = instance.transform(camera.shutter_close).translation;
+            assert!((start - vector3(0.0, y, -5.0)).length() < 1e-4);
+            assert!((end - vector3(0.5, y, -5.0)).length() < 1e-4);
+        }
+
+        // Only instances have a transform to animate.
+        let sphere = Scene {
+            objects: vec![Box::new(Sphere {
+                center: vector3(0.0, 0.0, 0.0),
+                radius: 1.0,
+                material: Box::new(Lambertian {
+                    albedo: color(1.0, 1.0, 1.0),
+                }),
+            })],
+            ..scene
+        };
+        assert!(animation.check(&sphere).is_err());
+    }
+}
@@ -1,19 +1,276 @@
-use crate::ray::Ray;
-use crate::vector::{vector3, Vector3};
-use crate::Number;
+use std::fs;
+use std::io;
+use std::path::Path;
 
+use super::ray::Ray;
+use super::vector::{vector3, Vector3};
+use super::Number;
+
+const PI: Number = std::f64::consts::PI as Number;
+
+// Lens prescriptions are in millimetres, while scenes are in metres.
+const MILLIMETRE: Number = 0.001;
+
+/// How directions around the camera are laid out on the image. The camera always looks down -z,
+/// with +y up.
+#[derive(Copy, Clone, PartialEq)]
+pub enum Projection {
+    /// A pinhole, looking through the viewport `focal_length` in front of the camera.
+    Perspective,
+    /// Parallel rays through the viewport, which sits at the camera's position. Objects keep the
+    /// same size at any distance.
+    Orthographic,
+    /// An equidistant fisheye, where the angle away from the view direction grows evenly with the
+    /// distance from the center of the image. `fov` in degrees spans the width of the image.
+    Fisheye { fov: Number },
+    /// All directions, longitude across the image and latitude up it. Best with a viewport twice
+    /// as wide as it is tall.
+    Equirectangular,
+    /// All directions on the six faces of a cube, in a 3 by 2 grid: +x, -x and +y on the top row,
+    /// then -y, +z and -z. Best with a viewport 3 by 2.
+    Cubemap,
+}
+
+impl Projection {
+    pub const ALL: [Projection; 5] = [
+        Projection::Perspective,
+        Projection::Orthographic,
+        Projection::Fisheye { fov: 180.0 },
+        Projection::Equirectangular,
+        Projection::Cubemap,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Projection::Perspective => "perspective",
+            Projection::Orthographic => "orthographic",
+            Projection::Fisheye { .. } => "fisheye",
+            Projection::Equirectangular => "equirectangular",
+            Projection::Cubemap => "cubemap",
+        }
+    }
+}
+
+/// How the two eyes of a stereo render share the image.
+#[derive(Copy, Clone, PartialEq)]
+pub enum StereoLayout {
+    /// Left eye on the left half, right eye on the right half.
+    SideBySide,
+    /// Left eye on the top half, right eye on the bottom half.
+    OverUnder,
+}
+
+/// Renders a view for each eye into the same image. The eyes sit `eye_separation` apart and
+/// turn in towards each other to meet at `convergence` away, where objects end up at the depth of
+/// the screen. Equirectangular panoramas get omni-directional stereo, with the eyes circling
+/// around the camera as they look in each direction; every other projection keeps them side by
+/// side along x.
+#[derive(Copy, Clone, PartialEq)]
+pub struct Stereo {
+    pub eye_separation: Number,
+    pub convergence: Number,
+    pub layout: StereoLayout,
+}
+
+/// The shape of the opening in a lens, which out of focus highlights take on. Fewer than 3
+/// `blades` give a round opening, more give a regular polygon turned by `rotation` degrees.
+#[derive(Copy, Clone, PartialEq)]
+pub struct Aperture {
+    pub blades: u32,
+    pub rotation: Number,
+}
+
+impl Aperture {
+    pub const ROUND: Aperture = Aperture {
+        blades: 0,
+        rotation: 0.0,
+    };
+
+    /// A point spread evenly over the opening, scaled to fit in the unit circle.
+    pub fn sample(&self, (u, v): (Number, Number)) -> (Number, Number) {
+        if self.blades < 3 {
+            let (radius, angle) = (u.sqrt(), 2.0 * PI * v);
+            return (radius * angle.cos(), radius * angle.sin());
+        }
+
+        // Pick one of the triangles between the center and each edge, then a point inside it.
+        let blades = self.blades as Number;
+        let index = (u * blades).floor().min(blades - 1.0);
+        let u = u * blades - index;
+        let (a, b) = (self.corner(index), self.corner(index + 1.0));
+
+        let (s, t) = if u + v > 1.0 {
+            (1.0 - u, 1.0 - v)
+        } else {
+            (u, v)
+        };
+        (a.0 * s + b.0 * t, a.1 * s + b.1 * t)
+    }
+
+    /// Whether `(x, y)` is inside the opening scaled to fit in the unit circle.
+    pub fn contains(&self, x: Number, y: Number) -> bool {
+        if self.blades < 3 {
+            return x * x + y * y <= 1.0;
+        }
+
+        // Distance from the center along the middle of the nearest edge, against the distance to
+        // that edge.
+        let sector = 2.0 * PI / self.blades as Number;
+        let angle = (y.atan2(x) - self.rotation.to_radians()).rem_euclid(sector) - sector / 2.0;
+        (x * x + y * y).sqrt() * angle.cos() <= (sector / 2.0).cos()
+    }
+
+    fn corner(&self, index: Number) -> (Number, Number) {
+        let angle = self.rotation.to_radians() + 2.0 * PI * index / self.blades as Number;
+        (angle.cos(), angle.sin())
+    }
+}
+
+/// One surface of a compound lens, in millimetres. Surfaces are listed from the front of the
+/// lens to the back, each one with the medium behind it.
+#[derive(Copy, Clone, PartialEq)]
+pub struct LensElement {
+    /// Radius of curvature, positive when the surface bulges towards the front. A radius of 0
+    /// makes this the aperture stop, a flat opening shaped by the lens' `Aperture`.
+    pub radius: Number,
+    /// Distance along the axis to the next surface.
+    pub thickness: Number,
+    /// Index of refraction behind the surface. 0 is taken as air.
+    pub ior: Number,
+    /// Diameter of the surface, past which rays are blocked.
+    pub diameter: Number,
+}
+
+impl LensElement {
+    /// Reads a prescription table with a surface on each line, giving its radius, thickness,
+    /// index of refraction and diameter. Blank lines and lines starting with `#` are ignored.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Vec<Self>> {
+        let mut elements = Vec::new();
+
+        for (index, line) in fs::read_to_string(path)?.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let values = line
+                .split_whitespace()
+                .map(|token| token.parse::<Number>())
+                .collect::<Result<Vec<_>, _>>();
+
+            match values.as_deref() {
+                Ok(&[radius, thickness, ior, diameter]) => elements.push(Self {
+                    radius,
+                    thickness,
+                    ior,
+                    diameter,
+                }),
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "line {}: expected radius, thickness, ior and diameter",
+                            index + 1
+                        ),
+                    ))
+                }
+            }
+        }
+
+        if elements.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "lens has no surfaces".to_string(),
+            ));
+        }
+
+        Ok(elements)
+    }
+}
+
+/// What the camera sees through. Tilting turns the plane in focus by `tilt` degrees around the y
+/// and x axes, sending its right and top sides further away for positive angles. Lenses only work
+/// with the perspective projection; the others stay pinholes.
+#[derive(Clone, PartialEq)]
+pub enum Lens {
+    /// Everything in focus.
+    Pinhole,
+    /// An ideal lens of `radius`, focused `focus_distance` away.
+    Thin {
+        radius: Number,
+        focus_distance: Number,
+        aperture: Aperture,
+        tilt: (Number, Number),
+    },
+    /// Rays traced through each surface of a real lens, on their way from a film of
+    /// `film_diagonal` millimetres. Rays stopped by the edges of the elements darken the corners
+    /// and give out of focus highlights their cat's eye shape there. The film is tilted rather
+    /// than the plane in focus, which turns that plane the same way but a lot further.
+    Compound {
+        /// Where the prescription was loaded from, kept as written.
+        path: String,
+        elements: Vec<LensElement>,
+        film_diagonal: Number,
+        focus_distance: Number,
+        aperture: Aperture,
+        tilt: (Number, Number),
+    },
+}
+
+impl Lens {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Lens::Pinhole => "pinhole",
+            Lens::Thin { .. } => "thin",
+            Lens::Compound { .. } => "compound",
+        }
+    }
+}
+
+#[derive(Clone, PartialEq)]
 pub struct CameraConfig {
     pub position: Vector3,
     pub viewport_width: Number,
     pub viewport_height: Number,
     pub focal_length: Number,
+    /// A point for the camera to turn towards, keeping +y up. `None` looks down -z.
+    pub target: Option<Vector3>,
+    pub projection: Projection,
+    pub lens: Lens,
+    /// Rays are cast at times spread evenly between these two, so anything moving in between
+    /// gets blurred. Equal times freeze the scene at that moment.
+    pub shutter_open: Number,
+    pub shutter_close: Number,
+    pub stereo: Option<Stereo>,
+}
+
+impl CameraConfig {
+    /// Width over height of the whole image, with both eyes for stereo.
+    pub fn aspect_ratio(&self) -> Number {
+        let aspect_ratio = self.viewport_width / self.viewport_height;
+        match self.stereo.map(|stereo| stereo.layout) {
+            None => aspect_ratio,
+            Some(StereoLayout::SideBySide) => aspect_ratio * 2.0,
+            Some(StereoLayout::OverUnder) => aspect_ratio / 2.0,
+        }
+    }
 }
 
 pub struct Camera {
+    projection: Projection,
+    lens: Lens,
+    /// Where the film sits behind the back of a compound lens, in millimetres, and its size.
+    film_distance: Number,
+    film_size: (Number, Number),
     position: Vector3,
     bottom_left: Vector3,
     horizontal: Vector3,
     vertical: Vector3,
+    shutter_open: Number,
+    shutter_close: Number,
+    stereo: Option<Stereo>,
+    /// Right, up and backwards for the camera in the scene.
+    orientation: [Vector3; 3],
 }
 
 impl Camera {
@@ -25,18 +282,665 @@ impl Camera {
             - vertical / 2.0
             - vector3(0.0, 0.0, config.focal_length);
 
+        let (film_distance, film_size) = match &config.lens {
+            Lens::Compound {
+                elements,
+                film_diagonal,
+                focus_distance,
+                ..
+            } => {
+                let aspect = config.viewport_width / config.viewport_height;
+                let height = film_diagonal / (aspect * aspect + 1.0).sqrt();
+                (
+                    focus(elements, focus_distance / MILLIMETRE),
+                    (height * aspect, height),
+                )
+            }
+            _ => (0.0, (0.0, 0.0)),
+        };
+
+        let orientation = match config.target {
+            Some(target) if !(target - config.position).near_zero() => {
+                let back = (config.position - target).normalize();
+                let right = vector3(0.0, 1.0, 0.0).cross(&back);
+
+                // Looking straight up or down, any direction will do for right.
+                let right = if right.near_zero() {
+                    vector3(1.0, 0.0, 0.0)
+                } else {
+                    right.normalize()
+                };
+                [right, back.cross(&right), back]
+            }
+            _ => [
+                vector3(1.0, 0.0, 0.0),
+                vector3(0.0, 1.0, 0.0),
+                vector3(0.0, 0.0, 1.0),
+            ],
+        };
+
         Self {
+            projection: config.projection,
+            lens: config.lens,
+            film_distance,
+            film_size,
             position: config.position,
             bottom_left,
             horizontal,
             vertical,
+            shutter_open: config.shutter_open,
+            shutter_close: config.shutter_close,
+            stereo: config.stereo,
+            orientation,
+        }
+    }
+
+    /// The ray through `(u, v)` on the viewport, passing through the lens at `lens_sample` and
+    /// cast at `time_sample` of the way through the shutter interval. `None` when the lens blocks
+    /// it, which leaves that sample black.
+    pub fn get_ray(
+        &self,
+        u: Number,
+        v: Number,
+        lens_sample: (Number, Number),
+        time_sample: Number,
+    ) -> Option<Ray> {
+        let time = self.shutter_open + (self.shutter_close - self.shutter_open) * time_sample;
+        let (origin, direction) = self.eye_ray(u, v, lens_sample)?;
+
+        // Rays start out looking down -z, and get turned around the camera's position.
+        let [right, up, back] = self.orientation;
+        let turn = |v: Vector3| right * v.x + up * v.y + back * v.z;
+
+        Some(Ray {
+            origin: self.position + turn(origin - self.position),
+            direction: turn(direction),
+            time,
+        })
+    }
+
+    // The ray through `(u, v)`, taking stereo into account, as an origin and a direction.
+    fn eye_ray(
+        &self,
+        u: Number,
+        v: Number,
+        lens_sample: (Number, Number),
+    ) -> Option<(Vector3, Vector3)> {
+        let stereo = match self.stereo {
+            Some(stereo) => stereo,
+            None => return self.project(u, v, lens_sample),
+        };
+
+        // Which eye, with -1 for the left one, and where the pixel is in its half of the image.
+        let (eye, u, v) = match stereo.layout {
+            StereoLayout::SideBySide if u < 0.5 => (-1.0, u * 2.0, v),
+            StereoLayout::SideBySide => (1.0, u * 2.0 - 1.0, v),
+            StereoLayout::OverUnder if v >= 0.5 => (-1.0, u, v * 2.0 - 1.0),
+            StereoLayout::OverUnder => (1.0, u, v * 2.0),
+        };
+
+        let (origin, direction) = self.project(u, v, lens_sample)?;
+        let right = match self.projection {
+            Projection::Equirectangular => {
+                let (sin, cos) = ((u - 0.5) * 2.0 * PI).sin_cos();
+                vector3(cos, 0.0, sin)
+            }
+            _ => vector3(1.0, 0.0, 0.0),
+        };
+
+        let target = origin + direction.normalize() * stereo.convergence;
+        let origin = origin + right * (eye * stereo.eye_separation / 2.0);
+
+        Some((origin, target - origin))
+    }
+
+    // The ray through `(u, v)` for a single eye in the middle, as an origin and a direction.
+    fn project(
+        &self,
+        u: Number,
+        v: Number,
+        lens_sample: (Number, Number),
+    ) -> Option<(Vector3, Vector3)> {
+        let on_viewport = self.bottom_left + self.horizontal * u + self.vertical * v;
+        Some(match self.projection {
+            Projection::Perspective => match &self.lens {
+                Lens::Pinhole => (self.position, on_viewport - self.position),
+                Lens::Thin {
+                    radius,
+                    focus_distance,
+                    aperture,
+                    tilt,
+                } => {
+                    let direction = on_viewport - self.position;
+                    let (x, y) = aperture.sample(lens_sample);
+                    let origin = self.position + vector3(x, y, 0.0) * *radius;
+
+                    // How far along `direction` the ray through the center meets the plane in
+                    // focus. Past the horizon of a tilted plane, it focuses at infinity instead.
+                    let (tan_x, tan_y) = (tilt.0.to_radians().tan(), tilt.1.to_radians().tan());
+                    let slope = -direction.z - direction.x * tan_x - direction.y * tan_y;
+                    if slope <= 0.0 {
+                        (origin, direction)
+                    } else {
+                        let focus = self.position + direction * (focus_distance / slope);
+                        (origin, focus - origin)
+                    }
+                }
+                Lens::Compound {
+                    elements,
+                    aperture,
+                    tilt,
+                    ..
+                } => self.compound(elements, aperture, *tilt, u, v, lens_sample)?,
+            },
+            Projection::Orthographic => {
+                let center = self.bottom_left + (self.horizontal + self.vertical) / 2.0;
+                (self.position + on_viewport - center, center - self.position)
+            }
+            Projection::Fisheye { fov } => (self.position, self.fisheye(u, v, fov)),
+            Projection::Equirectangular => (self.position, equirectangular(u, v)),
+            Projection::Cubemap => (self.position, cubemap(u, v)),
+        })
+    }
+
+    // Traces from a point on the film towards a point on the back element, through to the front.
+    // The image lands on the film upside down, so the film is flipped to put it the right way up.
+    fn compound(
+        &self,
+        elements: &[LensElement],
+        aperture: &Aperture,
+        tilt: (Number, Number),
+        u: Number,
+        v: Number,
+        lens_sample: (Number, Number),
+    ) -> Option<(Vector3, Vector3)> {
+        let (width, height) = self.film_size;
+        let x = -(u - 0.5) * width;
+        let y = -(v - 0.5) * height;
+        let film = vector3(
+            x,
+            y,
+            -x * tilt.0.to_radians().tan() - y * tilt.1.to_radians().tan(),
+        );
+
+        let back = elements[elements.len() - 1];
+        let (px, py) = Aperture::ROUND.sample(lens_sample);
+        let on_back =
+            vector3(px, py, 0.0) * (back.diameter / 2.0) + vector3(0.0, 0.0, self.film_distance);
+
+        let (origin, direction) =
+            trace(elements, aperture, self.film_distance, film, on_back - film)?;
+
+        // Lens space has +z pointing out the front, where the camera looks down -z.
+        let flip = |v: Vector3| vector3(v.x, v.y, -v.z);
+        Some((self.position + flip(origin) * MILLIMETRE, flip(direction)))
+    }
+
+    // Corners outside the circle spanning the width keep going with the same mapping.
+    fn fisheye(&self, u: Number, v: Number, fov: Number) -> Vector3 {
+        let aspect = self.vertical.y / self.horizontal.x;
+        let (x, y) = (2.0 * u - 1.0, (2.0 * v - 1.0) * aspect);
+        let radius = (x * x + y * y).sqrt();
+        if radius == 0.0 {
+            return vector3(0.0, 0.0, -1.0);
+        }
+
+        let (sin, cos) = (radius * fov.to_radians() / 2.0).sin_cos();
+        vector3(sin * x / radius, sin * y / radius, -cos)
+    }
+}
+
+fn equirectangular(u: Number, v: Number) -> Vector3 {
+    let longitude = (u - 0.5) * 2.0 * PI;
+    let latitude = (v - 0.5) * PI;
+    vector3(
+        longitude.sin() * latitude.cos(),
+        latitude.sin(),
+        -longitude.cos() * latitude.cos(),
+    )
+}
+
+fn cubemap(u: Number, v: Number) -> Vector3 {
+    let column = ((u * 3.0).floor() as usize).min(2);
+    let row = if v >= 0.5 { 0 } else { 1 };
+
+    // Position on the face from -1 to 1, with `a` going right and `b` going up.
+    let a = (u * 3.0 - column as Number) * 2.0 - 1.0;
+    let b = (v * 2.0 - (1 - row) as Number) * 2.0 - 1.0;
+
+    // Faces are seen from the inside of the cube, with +y up on the four around the sides. The top
+    // and bottom faces are turned the way they'd be seen by tilting the default view up or down.
+    match (row, column) {
+        (0, 0) => vector3(1.0, b, a),
+        (0, 1) => vector3(-1.0, b, -a),
+        (0, 2) => vector3(a, 1.0, b),
+        (1, 0) => vector3(a, -1.0, -b),
+        (1, 1) => vector3(-a, b, 1.0),
+        _ => vector3(a, b, -1.0),
+    }
+}
+
+// Follows a ray through the surfaces of a compound lens, front to back when it travels towards
+// -z and back to front otherwise. Lens space has the film at z = 0 and the front of the lens
+// towards +z, with the back surface `film_distance` from the film.
+fn trace(
+    elements: &[LensElement],
+    aperture: &Aperture,
+    film_distance: Number,
+    mut origin: Vector3,
+    direction: Vector3,
+) -> Option<(Vector3, Vector3)> {
+    let mut direction = direction.normalize();
+    let forwards = direction.z > 0.0;
+
+    // Where each surface crosses the axis.
+    let mut vertices = vec![film_distance; elements.len()];
+    for index in (0..elements.len() - 1).rev() {
+        vertices[index] = vertices[index + 1] + elements[index].thickness;
+    }
+
+    let air = |ior: Number| if ior == 0.0 { 1.0 } else { ior };
+    let order: Vec<usize> = if forwards {
+        (0..elements.len()).rev().collect()
+    } else {
+        (0..elements.len()).collect()
+    };
+
+    for index in order {
+        let element = &elements[index];
+        let vertex = vertices[index];
+        let half = element.diameter / 2.0;
+
+        if element.radius == 0.0 {
+            let t = (vertex - origin.z) / direction.z;
+            if t < 0.0 {
+                return None;
+            }
+            origin += direction * t;
+            if !aperture.contains(origin.x / half, origin.y / half) {
+                return None;
+            }
+            continue;
+        }
+
+        // The surface is the cap of its sphere nearest the vertex.
+        let center = vector3(0.0, 0.0, vertex - element.radius);
+        let offset = origin - center;
+        let b = direction.dot(&offset);
+        let c = offset.length_squared() - element.radius * element.radius;
+        let discriminant = b * b - c;
+        if discriminant < 0.0 {
+            return None;
+        }
+
+        let t = if forwards != (element.radius > 0.0) {
+            -b - discriminant.sqrt()
+        } else {
+            -b + discriminant.sqrt()
+        };
+        if t < 0.0 {
+            return None;
+        }
+
+        origin += direction * t;
+        if origin.x * origin.x + origin.y * origin.y > half * half {
+            return None;
+        }
+
+        let mut normal = (origin - center).normalize();
+        if normal.dot(&direction) > 0.0 {
+            normal = -normal;
+        }
+
+        let behind = air(element.ior);
+        let in_front = if index == 0 {
+            1.0
+        } else {
+            air(elements[index - 1].ior)
+        };
+        let eta = if forwards {
+            behind / in_front
+        } else {
+            in_front / behind
+        };
+
+        direction = refract(&direction, &normal, eta)?;
+    }
+
+    Some((origin, direction))
+}
+
+// Bends a unit `direction` through a surface with `normal` facing against it, for the ratio `eta`
+// of the index of refraction it comes from over the one it goes into. `None` on total internal
+// reflection.
+fn refract(direction: &Vector3, normal: &Vector3, eta: Number) -> Option<Vector3> {
+    let cos_i = -direction.dot(normal);
+    let sin2_t = eta * eta * (1.0 - cos_i * cos_i);
+    if sin2_t > 1.0 {
+        return None;
+    }
+
+    let cos_t = (1.0 - sin2_t).sqrt();
+    Some(*direction * eta + *normal * (eta * cos_i - cos_t))
+}
+
+// How far behind the back of the lens the film has to be for a point `distance` millimetres in
+// front of it to be in focus. A ray from that point close to the axis gets traced through to
+// where it crosses the axis again; if it never does, the film sits where the last thickness says.
+fn focus(elements: &[LensElement], distance: Number) -> Number {
+    let fallback = elements[elements.len() - 1].thickness;
+    let front = elements
+        .iter()
+        .map(|element| element.thickness)
+        .sum::<Number>()
+        - fallback;
+    let height = elements[0].diameter / 2.0 * 0.01;
+
+    let point = vector3(0.0, 0.0, front + distance);
+    let towards = vector3(height, 0.0, front);
+
+    match trace(elements, &Aperture::ROUND, 0.0, point, towards - point) {
+        Some((origin, direction)) if direction.x < 0.0 => {
+            let crossing = origin.z - origin.x / direction.x * direction.z;
+            if crossing < 0.0 {
+                -crossing
+            } else {
+                fallback
+            }
+        }
+        _ => fallback,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::sampler::SamplerKind;
+    use super::*;
+
+    fn assert_close(a: Vector3, b: Vector3) {
+        assert!((a - b).length() < 1e-4, "{:?} != {:?}", a, b);
+    }
+
+    fn config(
+        projection: Projection,
+        viewport_width: Number,
+        viewport_height: Number,
+    ) -> CameraConfig {
+        CameraConfig {
+            position: vector3(1.0, 2.0, 3.0),
+            viewport_width,
+            viewport_height,
+            focal_length: 1.0,
+            target: None,
+            projection,
+            lens: Lens::Pinhole,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+            stereo: None,
+        }
+    }
+
+    fn ray(camera: &Camera, u: Number, v: Number) -> Ray {
+        camera.get_ray(u, v, (0.5, 0.5), 0.0).unwrap()
+    }
+
+    #[test]
+    fn equirectangular_pixels_look_along_their_latitude_and_longitude() {
+        let camera = Camera::new(config(Projection::Equirectangular, 2.0, 1.0));
+        let (width, height) = (16, 8);
+
+        for j in 0..height {
+            for i in 0..width {
+                let u = (i as Number + 0.5) / width as Number;
+                let v = (j as Number + 0.5) / height as Number;
+                let longitude = (u - 0.5) * 2.0 * PI;
+                let latitude = (v - 0.5) * PI;
+
+                // Longitude turns right from -z, latitude up from the horizon.
+                let direction = ray(&camera, u, v).direction.normalize();
+                assert!((direction.y.asin() - latitude).abs() < 1e-4);
+                assert!((direction.x.atan2(-direction.z) - longitude).abs() < 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn cubemap_faces_look_along_their_axes() {
+        let camera = Camera::new(config(Projection::Cubemap, 3.0, 2.0));
+
+        // Each face with the direction at its middle and the way up on it, in the order of the
+        // grid.
+        let faces = [
+            (vector3(1.0, 0.0, 0.0), vector3(0.0, 1.0, 0.0)),
+            (vector3(-1.0, 0.0, 0.0), vector3(0.0, 1.0, 0.0)),
+            (vector3(0.0, 1.0, 0.0), vector3(0.0, 0.0, 1.0)),
+            (vector3(0.0, -1.0, 0.0), vector3(0.0, 0.0, -1.0)),
+            (vector3(0.0, 0.0, 1.0), vector3(0.0, 1.0, 0.0)),
+            (vector3(0.0, 0.0, -1.0), vector3(0.0, 1.0, 0.0)),
+        ];
+
+        for (index, (axis, up)) in faces.into_iter().enumerate() {
+            let u = ((index % 3) as Number + 0.5) / 3.0;
+            let v = if index < 3 { 0.75 } else { 0.25 };
+
+            let middle = ray(&camera, u, v).direction;
+            assert_close(middle.normalize(), axis);
+
+            // Up the face, and right of it, which is the way a viewer looking along `axis` with
+            // that way up sees it.
+            let above = ray(&camera, u, v + 0.1).direction;
+            let beside = ray(&camera, u + 0.1, v).direction;
+            assert_close((above - middle).normalize(), up);
+            assert_close((beside - middle).normalize(), axis.cross(&up));
         }
     }
 
-    pub fn get_ray(&self, u: Number, v: Number) -> Ray {
-        Ray {
-            origin: self.position,
-            direction: self.bottom_left + self.horizontal * u + self.vertical * v - self.position,
+    #[test]
+    fn orthographic_rays_are_parallel() {
+        let mut config = config(Projection::Orthographic, 4.0, 3.0);
+        config.target = Some(vector3(3.0, 1.0, 0.0));
+        let camera = Camera::new(config.clone());
+        let forward = (config.target.unwrap() - config.position).normalize();
+
+        let center = ray(&camera, 0.5, 0.5);
+        assert_close(center.origin, config.position);
+
+        for (u, v) in [(0.0, 0.0), (1.0, 0.0), (0.3, 0.9), (1.0, 1.0)] {
+            let ray = ray(&camera, u, v);
+            assert_close(ray.direction.normalize(), forward);
+
+            // Origins spread over the viewport, which is square to the rays.
+            let offset = ray.origin - config.position;
+            assert!(offset.dot(&forward).abs() < 1e-4);
+            assert!(offset.length() > 0.5);
+        }
+    }
+
+    #[test]
+    fn stereo_eyes_sit_either_side_along_the_cameras_right() {
+        let mut config = config(Projection::Perspective, 2.0, 2.0);
+        config.target = Some(vector3(4.0, 2.0, -1.0));
+        let right = vector3(0.0, 1.0, 0.0)
+            .cross(&(config.position - config.target.unwrap()))
+            .normalize();
+
+        for layout in [StereoLayout::SideBySide, StereoLayout::OverUnder] {
+            let stereo = Stereo {
+                eye_separation: 0.065,
+                convergence: 3.0,
+                layout,
+            };
+            config.stereo = Some(stereo);
+            let camera = Camera::new(config.clone());
+
+            // The same pixel of each eye's half, left eye first.
+            let [(left_u, left_v), (right_u, right_v)] = match layout {
+                StereoLayout::SideBySide => [(0.2, 0.4), (0.7, 0.4)],
+                StereoLayout::OverUnder => [(0.4, 0.7), (0.4, 0.2)],
+            };
+            let left = ray(&camera, left_u, left_v);
+            let right_eye = ray(&camera, right_u, right_v);
+
+            let offset = right * (stereo.eye_separation / 2.0);
+            assert_close(left.origin, config.position - offset);
+            assert_close(right_eye.origin, config.position + offset);
+
+            // Both eyes look at the same point, `convergence` away from the middle of them.
+            let (left_target, right_target) = (
+                left.origin + left.direction,
+                right_eye.origin + right_eye.direction,
+            );
+            assert_close(left_target, right_target);
+            assert!(((left_target - config.position).length() - stereo.convergence).abs() < 1e-4);
+        }
+    }
+
+    // A 50 mm biconvex singlet, with the aperture stop behind it.
+    fn singlet(stop_diameter: Number) -> Vec<LensElement> {
+        vec![
+            LensElement {
+                radius: 50.0,
+                thickness: 5.0,
+                ior: 1.5,
+                diameter: 20.0,
+            },
+            LensElement {
+                radius: -50.0,
+                thickness: 2.0,
+                ior: 0.0,
+                diameter: 20.0,
+            },
+            LensElement {
+                radius: 0.0,
+                thickness: 45.0,
+                ior: 0.0,
+                diameter: stop_diameter,
+            },
+        ]
+    }
+
+    #[test]
+    fn compound_lenses_focus_a_point_onto_a_spot() {
+        let elements = singlet(4.0);
+        let distance = 2000.0;
+        let film_distance = focus(&elements, distance);
+
+        // Where rays from a point on the axis land on the film, through all of the stop, and how
+        // far they spread with the film where `focus` puts it and a little further back.
+        let front = film_distance + 7.0;
+        let point = vector3(0.0, 0.0, front + distance);
+        let spread = |film_distance: Number| {
+            let mut spread: Number = 0.0;
+            for i in 0..16 {
+                let (x, y) = Aperture::ROUND.sample(((i as Number + 0.5) / 16.0, 0.3));
+                let towards = vector3(x * 2.0, y * 2.0, front);
+                let (origin, direction) = trace(
+                    &elements,
+                    &Aperture::ROUND,
+                    film_distance,
+                    point,
+                    towards - point,
+                )
+                .unwrap();
+                let on_film = origin - direction * (origin.z / direction.z);
+                spread = spread.max((on_film.x * on_film.x + on_film.y * on_film.y).sqrt());
+            }
+            spread
+        };
+
+        assert!(spread(film_distance) < 0.01);
+        assert!(spread(film_distance + 1.0) > 0.03);
+    }
+
+    #[test]
+    fn aperture_samples_land_inside_the_opening() {
+        let mut sampler = SamplerKind::Independent.create(3, 1);
+        sampler.start_pixel_sample(0, 0, 0);
+
+        for blades in [0, 3, 5, 6, 9] {
+            for rotation in [0.0, 15.0, 100.0] {
+                let aperture = Aperture { blades, rotation };
+                for _ in 0..2000 {
+                    let (x, y) = aperture.sample(sampler.get_2d());
+                    assert!(aperture.contains(x, y), "{} blades, {} {}", blades, x, y);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn malformed_lens_files_are_rejected() {
+        let path = std::env::temp_dir().join(format!(
+            "camera_lens_{}_{}.txt",
+            std::any::type_name::<Number>(),
+            std::process::id()
+        ));
+        let load = |contents: &str| {
+            fs::write(&path, contents).unwrap();
+            LensElement::load(&path)
+        };
+
+        assert_eq!(
+            load("# radius thickness ior diameter\n50 5 1.5 20\n\n-50 40 0 20\n")
+                .unwrap()
+                .len(),
+            2
+        );
+        for contents in [
+            "50 5 1.5 20\n-50 40 0\n",
+            "50 5 1.5 20\n-50 40 air 20\n",
+            "# nothing\n",
+        ] {
+            let error = load(contents).err().unwrap();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+        assert!(load("50 5 1.5 20\n-50 40 0\n")
+            .err()
+            .unwrap()
+            .to_string()
+            .starts_with("line 2:"));
+
+        fs::remove_file(path).unwrap();
+    }
+
+    // Lens samples are spread over the round back of the lens, which is a pentagonal stop here, so
+    // the ones outside the pentagon are blocked.
+    #[test]
+    fn rays_blocked_by_the_lens_are_none() {
+        let mut config = config(Projection::Perspective, 1.5, 1.0);
+        config.lens = Lens::Compound {
+            path: String::new(),
+            elements: singlet(4.0),
+            film_diagonal: 36.0,
+            focus_distance: 2.0,
+            aperture: Aperture {
+                blades: 5,
+                rotation: 0.0,
+            },
+            tilt: (0.0, 0.0),
+        };
+        let camera = Camera::new(config);
+
+        let (mut passed, mut blocked) = (0, 0);
+        for i in 0..16 {
+            for j in 0..16 {
+                let lens_sample = ((i as Number + 0.5) / 16.0, (j as Number + 0.5) / 16.0);
+                let (x, y) = Aperture::ROUND.sample(lens_sample);
+                let inside = Aperture {
+                    blades: 5,
+                    rotation: 0.0,
+                }
+                .contains(x, y);
+
+                let ray = camera.get_ray(0.5, 0.5, lens_sample, 0.0);
+                assert_eq!(ray.is_some(), inside);
+                if inside {
+                    passed += 1;
+                } else {
+                    blocked += 1;
+                }
+            }
         }
+        assert!(passed > 0 && blocked > 0);
     }
 }
@@ -1,7 +1,9 @@
-use crate::Number;
+use super::color_space::{ColorSpace, DisplayTransform};
+use super::spectrum::{cie_xyz, planck};
+use super::Number;
 use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Sub, SubAssign};
 
-#[derive(Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Color {
     pub r: Number,
     pub g: Number,
@@ -62,3 +64,131 @@ operator_impl!(
     (Mul, Mul<Number>, mul, *, MulAssign, MulAssign<Number>, mul_assign, *=),
     (Div, Div<Number>, div, /, DivAssign, DivAssign<Number>, div_assign, /=)
 );
+
+impl Color {
+    pub fn luminance(&self) -> Number {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
+
+    /// Color of a black body at `temperature` kelvin, scaled to a luminance of 1. Planck's law
+    /// gets integrated against the CIE 1931 observer, approximated by the multi-lobe fit of
+    /// Wyman et al., and converted to linear sRGB. Colors sRGB can't show are clipped.
+    #[allow(clippy::unnecessary_cast)]
+    pub fn blackbody(temperature: Number) -> Self {
+        if temperature <= 0.0 {
+            return color(0.0, 0.0, 0.0);
+        }
+
+        let (mut x, mut y, mut z) = (0.0, 0.0, 0.0);
+
+        for step in 0..=80 {
+            let wavelength = 380.0 + 5.0 * step as f64;
+            let radiance = planck(wavelength, temperature as f64);
+            let [cie_x, cie_y, cie_z] = cie_xyz(wavelength as Number);
+
+            x += radiance * cie_x as f64;
+            y += radiance * cie_y as f64;
+            z += radiance * cie_z as f64;
+        }
+
+        if y <= 0.0 || y.is_nan() {
+            return color(0.0, 0.0, 0.0);
+        }
+
+        let rgb = color((x / y) as Number, 1.0, (z / y) as Number)
+            .convert(ColorSpace::Xyz, ColorSpace::Srgb);
+        let rgb = color(rgb.r.max(0.0), rgb.g.max(0.0), rgb.b.max(0.0));
+
+        rgb / rgb.luminance()
+    }
+
+    /// Inverse of `to_rgb8`, taking the center of each quantization step.
+    pub fn from_rgb8(rgb: [u8; 3], display: &DisplayTransform) -> Self {
+        let dequantize = |c: u8| (c as Number + 0.5) / 256.0;
+        display.decode(color(
+            dequantize(rgb[0]),
+            dequantize(rgb[1]),
+            dequantize(rgb[2]),
+        ))
+    }
+
+    /// Encodes the color with `display` and quantizes it to 8 bits per channel.
+    pub fn to_rgb8(&self, display: &DisplayTransform) -> [u8; 3] {
+        let encoded = display.encode(*self);
+        [
+            (encoded.r * 256.0) as u8,
+            (encoded.g * 256.0) as u8,
+            (encoded.b * 256.0) as u8,
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::color_space::TransferFunction;
+    use super::*;
+
+    #[test]
+    fn operators_work_per_channel() {
+        let a = color(0.5, 1.0, 2.0);
+        let b = color(2.0, 0.5, 0.25);
+
+        assert_eq!(a + b, color(2.5, 1.5, 2.25));
+        assert_eq!(a - b, color(-1.5, 0.5, 1.75));
+        assert_eq!(a * b, color(1.0, 0.5, 0.5));
+        assert_eq!(a / b, color(0.25, 2.0, 8.0));
+        assert_eq!(a * 2.0, color(1.0, 2.0, 4.0));
+
+        let mut c = a;
+        c *= b;
+        c += 1.0;
+        assert_eq!(c, color(2.0, 1.5, 1.5));
+    }
+
+    #[test]
+    fn white_has_a_luminance_of_one() {
+        assert!((color(1.0, 1.0, 1.0).luminance() - 1.0).abs() < 1e-6);
+        assert_eq!(color(0.0, 0.0, 0.0).luminance(), 0.0);
+    }
+
+    // Black bodies go from red through white to blue as they heat up.
+    #[test]
+    fn black_bodies_have_unit_luminance_and_shift_to_blue() {
+        let warm = Color::blackbody(2000.0);
+        let cool = Color::blackbody(10000.0);
+
+        for glow in [warm, cool, Color::blackbody(6500.0)] {
+            assert!((glow.luminance() - 1.0).abs() < 1e-4, "{:?}", glow);
+        }
+        assert!(warm.r > warm.g && warm.g > warm.b, "{:?}", warm);
+        assert!(cool.b > cool.g && cool.g > cool.r, "{:?}", cool);
+        assert_eq!(Color::blackbody(0.0), color(0.0, 0.0, 0.0));
+    }
+
+    // Pasting a crop into an 8 bit image decodes the rest of it and encodes it again, which
+    // mustn't change it.
+    #[test]
+    fn rgb8_survives_decoding_and_encoding_again() {
+        let transfers = [
+            TransferFunction::Linear,
+            TransferFunction::Srgb,
+            TransferFunction::Gamma(2.2),
+        ];
+
+        for space in ColorSpace::ALL {
+            for transfer in transfers {
+                let display = DisplayTransform { space, transfer };
+
+                for r in (0..=255).step_by(5) {
+                    for g in (0..=255).step_by(3) {
+                        for b in (0..=255).step_by(7) {
+                            let rgb = [r, g, b];
+                            let decoded = Color::from_rgb8(rgb, &display);
+                            assert_eq!(decoded.to_rgb8(&display), rgb, "{:?}", display);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,195 @@
+use super::color::{color, Color};
+use super::Number;
+
+// The renderer works in linear sRGB: every color in a scene, from albedos to the sky, is a linear
+// mix of the Rec. 709 primaries with a D65 white. Other spaces only come in at the edges, when
+// images get read or written. Their matrices convert to and from CIE XYZ, with ACEScg's D60 white
+// adapted to D65 by the Bradford transform so that white stays white across all of them.
+
+type Matrix = [[Number; 3]; 3];
+
+/// A set of linear RGB primaries, or XYZ itself.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ColorSpace {
+    /// Linear sRGB, which shares its primaries with Rec. 709. The working space of the renderer.
+    Srgb,
+    /// The wide gamut space of the ACES pipeline, made for rendering and compositing.
+    AcesCg,
+    Rec2020,
+    DisplayP3,
+    Xyz,
+}
+
+impl ColorSpace {
+    pub const ALL: [ColorSpace; 5] = [
+        ColorSpace::Srgb,
+        ColorSpace::AcesCg,
+        ColorSpace::Rec2020,
+        ColorSpace::DisplayP3,
+        ColorSpace::Xyz,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ColorSpace::Srgb => "srgb",
+            ColorSpace::AcesCg => "acescg",
+            ColorSpace::Rec2020 => "rec2020",
+            ColorSpace::DisplayP3 => "display_p3",
+            ColorSpace::Xyz => "xyz",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|space| space.name() == name)
+    }
+
+    /// The transfer function images in this space are usually encoded with. Spaces meant for
+    /// rendering and compositing stay linear.
+    pub fn default_transfer(&self) -> TransferFunction {
+        match self {
+            ColorSpace::Srgb | ColorSpace::DisplayP3 => TransferFunction::Srgb,
+            ColorSpace::Rec2020 => TransferFunction::Gamma(2.4),
+            ColorSpace::AcesCg | ColorSpace::Xyz => TransferFunction::Linear,
+        }
+    }
+
+    fn rgb_to_xyz(self) -> Matrix {
+        match self {
+            ColorSpace::Srgb => [
+                [0.412391, 0.357584, 0.180481],
+                [0.212639, 0.715169, 0.072192],
+                [0.019331, 0.119195, 0.950532],
+            ],
+            ColorSpace::AcesCg => [
+                [0.652238, 0.128236, 0.169982],
+                [0.267672, 0.674340, 0.057988],
+                [-0.005382, 0.001369, 1.093071],
+            ],
+            ColorSpace::Rec2020 => [
+                [0.636958, 0.144617, 0.168881],
+                [0.262700, 0.677998, 0.059302],
+                [0.000000, 0.028073, 1.060985],
+            ],
+            ColorSpace::DisplayP3 => [
+                [0.486571, 0.265668, 0.198217],
+                [0.228975, 0.691739, 0.079287],
+                [0.000000, 0.045113, 1.043944],
+            ],
+            ColorSpace::Xyz => IDENTITY,
+        }
+    }
+
+    fn xyz_to_rgb(self) -> Matrix {
+        match self {
+            ColorSpace::Srgb => [
+                [3.24097, -1.537383, -0.498611],
+                [-0.969244, 1.875968, 0.041555],
+                [0.055630, -0.203977, 1.056972],
+            ],
+            ColorSpace::AcesCg => [
+                [1.660585, -0.315296, -0.241509],
+                [-0.659926, 1.608391, 0.017299],
+                [0.009003, -0.003567, 0.913643],
+            ],
+            ColorSpace::Rec2020 => [
+                [1.716651, -0.355671, -0.253366],
+                [-0.666684, 1.616481, 0.015769],
+                [0.017640, -0.042771, 0.942103],
+            ],
+            ColorSpace::DisplayP3 => [
+                [2.493497, -0.931384, -0.402711],
+                [-0.829489, 1.762664, 0.023625],
+                [0.035846, -0.076172, 0.956885],
+            ],
+            ColorSpace::Xyz => IDENTITY,
+        }
+    }
+}
+
+const IDENTITY: Matrix = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+
+fn transform(matrix: &Matrix, c: Color) -> Color {
+    let row = |row: &[Number; 3]| row[0] * c.r + row[1] * c.g + row[2] * c.b;
+    color(row(&matrix[0]), row(&matrix[1]), row(&matrix[2]))
+}
+
+impl Color {
+    /// The same color with its channels in `to` instead of `from`. Colors outside of `to`'s gamut
+    /// end up with negative channels rather than getting clipped.
+    pub fn convert(&self, from: ColorSpace, to: ColorSpace) -> Color {
+        if from == to {
+            return *self;
+        }
+
+        transform(&to.xyz_to_rgb(), transform(&from.rgb_to_xyz(), *self))
+    }
+}
+
+/// How linear values get encoded for storage or display, usually to spend more of the 8 bits of
+/// an image on dark tones, where eyes are more sensitive.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum TransferFunction {
+    Linear,
+    /// The piecewise curve of sRGB and Display P3, a gamma of about 2.2 with a linear toe.
+    Srgb,
+    /// A pure power curve, such as the 2.4 of Rec. 2020 displays.
+    Gamma(Number),
+}
+
+impl TransferFunction {
+    pub fn encode(&self, value: Number) -> Number {
+        match *self {
+            TransferFunction::Linear => value,
+            TransferFunction::Srgb if value <= 0.0031308 => value * 12.92,
+            TransferFunction::Srgb => 1.055 * value.powf(1.0 / 2.4) - 0.055,
+            TransferFunction::Gamma(gamma) => value.max(0.0).powf(1.0 / gamma),
+        }
+    }
+
+    pub fn decode(&self, value: Number) -> Number {
+        match *self {
+            TransferFunction::Linear => value,
+            TransferFunction::Srgb if value <= 0.04045 => value / 12.92,
+            TransferFunction::Srgb => ((value + 0.055) / 1.055).powf(2.4),
+            TransferFunction::Gamma(gamma) => value.max(0.0).powf(gamma),
+        }
+    }
+}
+
+/// Turns rendered colors into the values stored in an output image: first into the primaries of
+/// `space`, then through `transfer`. Float images only take the first step, and images read back
+/// go through the same steps in reverse.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct DisplayTransform {
+    pub space: ColorSpace,
+    pub transfer: TransferFunction,
+}
+
+impl DisplayTransform {
+    /// For `space` with its usual transfer function.
+    pub fn new(space: ColorSpace) -> Self {
+        Self {
+            space,
+            transfer: space.default_transfer(),
+        }
+    }
+
+    pub fn encode(&self, linear: Color) -> Color {
+        let c = linear.convert(ColorSpace::Srgb, self.space);
+        let encode = |value| self.transfer.encode(value);
+        color(encode(c.r), encode(c.g), encode(c.b))
+    }
+
+    pub fn decode(&self, encoded: Color) -> Color {
+        let decode = |value| self.transfer.decode(value);
+        color(decode(encoded.r), decode(encoded.g), decode(encoded.b))
+            .convert(self.space, ColorSpace::Srgb)
+    }
+}
+
+/// sRGB, what most monitors and image viewers expect.
+impl Default for DisplayTransform {
+    fn default() -> Self {
+        Self::new(ColorSpace::Srgb)
+    }
+}
@@ -0,0 +1,118 @@
+use std::ops::Mul;
+
+use super::color::{color, Color};
+use super::hit::HitData;
+use super::material::{Material, ScatterResult};
+use super::microfacet::{reflect, ComplexIor, Frame, TrowbridgeReitz};
+use super::ray::Ray;
+use super::sampler::Sampler;
+use super::spectrum::{Spectrum, Wavelengths};
+use super::vector::{vector3, Vector3};
+use super::Number;
+
+/// A GGX microfacet metal. Roughness goes from 0 (a mirror) to 1 and can differ along the tangent
+/// (`u`) and the bitangent (`v`) of the surface for a brushed look.
+#[derive(Copy, Clone, PartialEq)]
+pub struct Conductor {
+    pub ior: ComplexIor,
+    pub roughness_u: Number,
+    pub roughness_v: Number,
+}
+
+impl Conductor {
+    fn distribution(&self) -> TrowbridgeReitz {
+        TrowbridgeReitz::new(self.roughness_u, self.roughness_v)
+    }
+
+    // Shared by `scatter` and `scatter_spectral`, which only differ in how the Fresnel term is
+    // evaluated at the cosine it's given.
+    fn scatter_with<A: Mul<Number, Output = A>>(
+        &self,
+        ray: &Ray,
+        hit_data: &HitData,
+        sampler: &mut dyn Sampler,
+        fresnel: impl Fn(Number) -> A,
+    ) -> ScatterResult<A> {
+        let frame = Frame::new(hit_data.normal);
+        let wo = frame.to_local(&-ray.direction.normalize());
+        let distribution = self.distribution();
+
+        if wo.z <= 0.0 {
+            return ScatterResult::Absorbed;
+        }
+
+        if distribution.is_smooth() {
+            let wi = vector3(-wo.x, -wo.y, wo.z);
+            return ScatterResult::new(ray, hit_data, frame.to_world(&wi), fresnel(wo.z), None);
+        }
+
+        let wm = distribution.sample_wm(&wo, sampler.get_2d());
+        let wi = reflect(&wo, &wm);
+        if wi.z <= 0.0 {
+            return ScatterResult::Absorbed;
+        }
+
+        // With visible normal sampling, the BRDF times the cosine over the pdf reduces to the
+        // Fresnel term times the masking that the sampling didn't account for.
+        let pdf = distribution.pdf(&wo, &wm) / (4.0 * wo.dot(&wm));
+        let attenuation = fresnel(wo.dot(&wm)) * (distribution.g(&wo, &wi) / distribution.g1(&wo));
+
+        ScatterResult::new(ray, hit_data, frame.to_world(&wi), attenuation, Some(pdf))
+    }
+}
+
+impl Material for Conductor {
+    fn scatter(&self, ray: &Ray, hit_data: &HitData, sampler: &mut dyn Sampler) -> ScatterResult {
+        self.scatter_with(ray, hit_data, sampler, |cos| self.ior.fresnel(cos))
+    }
+
+    fn scatter_spectral(
+        &self,
+        ray: &Ray,
+        hit_data: &HitData,
+        sampler: &mut dyn Sampler,
+        wavelengths: &mut Wavelengths,
+    ) -> ScatterResult<Spectrum> {
+        let wavelengths = *wavelengths;
+        self.scatter_with(ray, hit_data, sampler, |cos| {
+            self.ior.fresnel_spectral(cos, &wavelengths)
+        })
+    }
+
+    fn eval(&self, hit_data: &HitData, wo: &Vector3, wi: &Vector3) -> Color {
+        let frame = Frame::new(hit_data.normal);
+        let (wo, wi) = (frame.to_local(wo), frame.to_local(wi));
+        let distribution = self.distribution();
+
+        let half = wo + wi;
+        if distribution.is_smooth() || wo.z <= 0.0 || wi.z <= 0.0 || half.near_zero() {
+            return color(0.0, 0.0, 0.0);
+        }
+
+        let wm = half.normalize();
+        self.ior.fresnel(wo.dot(&wm))
+            * (distribution.d(&wm) * distribution.g(&wo, &wi) / (4.0 * wo.z * wi.z))
+    }
+
+    fn pdf(&self, hit_data: &HitData, wo: &Vector3, wi: &Vector3) -> Number {
+        let frame = Frame::new(hit_data.normal);
+        let (wo, wi) = (frame.to_local(wo), frame.to_local(wi));
+        let distribution = self.distribution();
+
+        let half = wo + wi;
+        if distribution.is_smooth() || wo.z <= 0.0 || wi.z <= 0.0 || half.near_zero() {
+            return 0.0;
+        }
+
+        let wm = half.normalize();
+        distribution.pdf(&wo, &wm) / (4.0 * wo.dot(&wm))
+    }
+
+    fn albedo(&self, _uv: (Number, Number)) -> Color {
+        self.ior.fresnel(1.0)
+    }
+
+    fn name(&self) -> &'static str {
+        "Conductor"
+    }
+}
@@ -0,0 +1,362 @@
+use super::color::{color, Color};
+use super::image::Image;
+use super::ray::Ray;
+use super::scene::Scene;
+use super::stats::RenderStats;
+use super::vector::{vector3, Vector3};
+use super::Number;
+
+// An edge-avoiding à-trous wavelet filter, after SVGF ("Spatiotemporal Variance-Guided
+// Filtering", Schied et al. 2017) without the temporal part. Each pass blurs with a 5x5 kernel
+// whose taps spread out twice as far as in the previous pass. Taps are weighted down when their
+// normal or depth doesn't match, or when their luminance differs by more than the noise of the
+// pixel explains, so edges stay sharp while flat regions get smoothed a lot.
+//
+// Lighting is filtered on its own, with the albedo divided out, so texture detail survives even
+// where the lighting gets blurred heavily. Channels with next to no albedo are left as they are,
+// since dividing by it would blow their noise up along with them.
+
+const ITERATIONS: usize = 5;
+const KERNEL: [Number; 3] = [3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+const SIGMA_LUMINANCE: Number = 4.0;
+const SIGMA_DEPTH: Number = 1.0;
+const NORMAL_EXPONENT: i32 = 128;
+const MIN_ALBEDO: Number = 0.01;
+
+/// What a camera ray sees first, before any lighting.
+#[derive(Copy, Clone)]
+pub struct Features {
+    pub albedo: Color,
+    pub normal: Vector3,
+    pub depth: Number,
+}
+
+impl Features {
+    /// Rays that escape see the sky, which acts as its own albedo and has no normal or depth.
+    /// The ray gets counted in `stats`.
+    pub fn new(scene: &Scene, ray: &Ray, stats: &mut RenderStats) -> Self {
+        let (hit, tests) = scene.closest(Number::INFINITY, |object, t_max| {
+            object.hit(ray, 0.0, t_max)
+        });
+        stats.feature_rays += 1;
+        stats.primitive_tests += tests;
+
+        match hit {
+            Some(hit_data) => Self {
+                albedo: hit_data.material.albedo(hit_data.uv),
+                normal: hit_data.normal,
+                depth: hit_data.t * ray.direction.length(),
+            },
+            None => Self {
+                albedo: scene.sky.get_ray_color(ray),
+                normal: vector3(0.0, 0.0, 0.0),
+                depth: 0.0,
+            },
+        }
+    }
+
+    /// Samples stopped inside the lens see nothing at all.
+    pub fn blocked() -> Self {
+        Self {
+            albedo: color(0.0, 0.0, 0.0),
+            normal: vector3(0.0, 0.0, 0.0),
+            depth: 0.0,
+        }
+    }
+}
+
+/// Per pixel buffers guiding the denoiser, in the same layout as the image they belong to.
+pub struct Guide {
+    pub albedo: Vec<Color>,
+    pub normal: Vec<Vector3>,
+    pub depth: Vec<Number>,
+    /// Variance of the mean luminance of each pixel.
+    pub variance: Vec<Number>,
+}
+
+pub fn denoise(image: &Image, guide: &Guide) -> Image {
+    let (width, height) = (image.width, image.height);
+
+    let albedo: Vec<Color> = guide
+        .albedo
+        .iter()
+        .map(|a| {
+            let demodulate = |a: Number| if a < MIN_ALBEDO { 1.0 } else { a };
+            color(demodulate(a.r), demodulate(a.g), demodulate(a.b))
+        })
+        .collect();
+    // Averaging over a pixel shortens the normals, so they're brought back to unit length for the
+    // normal weights to stay meaningful.
+    let normal: Vec<Vector3> = guide
+        .normal
+        .iter()
+        .map(|n| if n.near_zero() { *n } else { n.normalize() })
+        .collect();
+    let mut lighting: Vec<Color> = image
+        .pixels
+        .iter()
+        .zip(&albedo)
+        .map(|(&c, &a)| c / a)
+        .collect();
+    let mut variance: Vec<Number> = guide
+        .variance
+        .iter()
+        .zip(&albedo)
+        .map(|(&v, a)| v / (a.luminance() * a.luminance()))
+        .collect();
+
+    // How fast depth changes around each pixel, so depth differences are judged relative to the
+    // slope of the surface instead of in absolute terms.
+    let depth = &guide.depth;
+    let gradient: Vec<Number> = (0..height)
+        .flat_map(|y| {
+            (0..width).map(move |x| {
+                let at = |x: usize, y: usize| depth[y * width + x];
+                let dx = at((x + 1).min(width - 1), y) - at(x.saturating_sub(1), y);
+                let dy = at(x, (y + 1).min(height - 1)) - at(x, y.saturating_sub(1));
+                dx.abs().max(dy.abs()) / 2.0
+            })
+        })
+        .collect();
+
+    for iteration in 0..ITERATIONS {
+        let step = 1 << iteration;
+        let blurred = blur_variance(&variance, width, height);
+        let mut next_lighting = lighting.clone();
+        let mut next_variance = variance.clone();
+
+        for y in 0..height {
+            for x in 0..width {
+                let p = y * width + x;
+                let luminance = lighting[p].luminance();
+                let luminance_scale = SIGMA_LUMINANCE * blurred[p].max(0.0).sqrt() + 1e-4;
+
+                let mut sum = color(0.0, 0.0, 0.0);
+                let mut sum_variance = 0.0;
+                let mut total = 0.0;
+
+                for dy in -2isize..=2 {
+                    for dx in -2isize..=2 {
+                        let qx = x as isize + dx * step;
+                        let qy = y as isize + dy * step;
+                        if qx < 0 || qy < 0 || qx >= width as isize || qy >= height as isize {
+                            continue;
+                        }
+                        let q = qy as usize * width + qx as usize;
+
+                        let (np, nq) = (normal[p], normal[q]);
+                        let normal_weight = if np.near_zero() && nq.near_zero() {
+                            1.0
+                        } else {
+                            np.dot(&nq).max(0.0).powi(NORMAL_EXPONENT)
+                        };
+
+                        let distance = ((dx * dx + dy * dy) as Number).sqrt() * step as Number;
+                        let depth_weight = (-(depth[p] - depth[q]).abs()
+                            / (SIGMA_DEPTH * gradient[p] * distance + 1e-4))
+                            .exp();
+
+                        let luminance_weight =
+                            (-(luminance - lighting[q].luminance()).abs() / luminance_scale).exp();
+
+                        let weight = KERNEL[dx.unsigned_abs()]
+                            * KERNEL[dy.unsigned_abs()]
+                            * normal_weight
+                            * depth_weight
+                            * luminance_weight;
+
+                        sum += lighting[q] * weight;
+                        sum_variance += weight * weight * variance[q];
+                        total += weight;
+                    }
+                }
+
+                // The center tap always has a positive weight, so `total` can't be zero.
+                next_lighting[p] = sum / total;
+                next_variance[p] = sum_variance / (total * total);
+            }
+        }
+
+        lighting = next_lighting;
+        variance = next_variance;
+    }
+
+    Image {
+        width,
+        height,
+        pixels: lighting.iter().zip(&albedo).map(|(&l, &a)| l * a).collect(),
+    }
+}
+
+// A small gaussian blur of the variance, which on its own is too noisy to steer the luminance
+// weights.
+fn blur_variance(variance: &[Number], width: usize, height: usize) -> Vec<Number> {
+    const WEIGHTS: [Number; 2] = [1.0 / 2.0, 1.0 / 4.0];
+
+    (0..height)
+        .flat_map(|y| {
+            (0..width).map(move |x| {
+                let mut sum = 0.0;
+                let mut total = 0.0;
+
+                for dy in -1isize..=1 {
+                    for dx in -1isize..=1 {
+                        let (qx, qy) = (x as isize + dx, y as isize + dy);
+                        if qx < 0 || qy < 0 || qx >= width as isize || qy >= height as isize {
+                            continue;
+                        }
+
+                        let weight = WEIGHTS[dx.unsigned_abs()] * WEIGHTS[dy.unsigned_abs()];
+                        sum += variance[qy as usize * width + qx as usize] * weight;
+                        total += weight;
+                    }
+                }
+
+                sum / total
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::sampler::SamplerKind;
+    use super::*;
+
+    const SIZE: usize = 32;
+
+    // A square image split down the middle, with `left` and `right` giving the color, albedo,
+    // normal and depth on either side.
+    fn split(
+        left: (Color, Color, Vector3, Number),
+        right: (Color, Color, Vector3, Number),
+        noise: Number,
+    ) -> (Image, Guide) {
+        let mut sampler = SamplerKind::Independent.create(5, 1);
+        sampler.start_pixel_sample(0, 0, 0);
+
+        let mut image = Image::new(SIZE, SIZE);
+        let mut guide = Guide {
+            albedo: Vec::new(),
+            normal: Vec::new(),
+            depth: Vec::new(),
+            variance: Vec::new(),
+        };
+
+        for y in 0..SIZE {
+            for x in 0..SIZE {
+                let (pixel, albedo, normal, depth) = if x < SIZE / 2 { left } else { right };
+                let scale = 1.0 + noise * (2.0 * sampler.get_1d() - 1.0);
+                image.set(x, y, pixel * scale);
+                guide.albedo.push(albedo);
+                guide.normal.push(normal);
+                guide.depth.push(depth);
+                // Uniform noise over ±noise has a variance of noise² / 3.
+                let luminance = pixel.luminance();
+                guide
+                    .variance
+                    .push(luminance * luminance * noise * noise / 3.0);
+            }
+        }
+
+        (image, guide)
+    }
+
+    // Spread of the luminance over the columns from `from` to `to`, and its mean.
+    fn columns(image: &Image, from: usize, to: usize) -> (Number, Number) {
+        let values: Vec<Number> = (0..SIZE)
+            .flat_map(|y| (from..to).map(move |x| (x, y)))
+            .map(|(x, y)| image.get(x, y).luminance())
+            .collect();
+        let mean = values.iter().sum::<Number>() / values.len() as Number;
+        let variance = values
+            .iter()
+            .map(|v| (v - mean) * (v - mean))
+            .sum::<Number>()
+            / values.len() as Number;
+        (variance.sqrt(), mean)
+    }
+
+    #[test]
+    fn noiseless_images_come_back_unchanged() {
+        let up = vector3(0.0, 0.0, 1.0);
+        let pixel = color(0.3, 0.6, 0.9);
+
+        // Half of it without any albedo, like a black surface lit only by what it reflects.
+        let (image, guide) = split(
+            (pixel, color(0.5, 0.25, 1.0), up, 2.0),
+            (pixel, color(0.0, 0.005, 0.0), up, 2.0),
+            0.0,
+        );
+        let denoised = denoise(&image, &guide);
+
+        for (a, b) in denoised.pixels.iter().zip(&image.pixels) {
+            let difference = *a - *b;
+            assert!(
+                difference
+                    .r
+                    .abs()
+                    .max(difference.g.abs())
+                    .max(difference.b.abs())
+                    < 1e-4
+            );
+        }
+    }
+
+    #[test]
+    fn flat_regions_get_smoother_and_edges_stay_sharp() {
+        let up = vector3(0.0, 0.0, 1.0);
+        let (dark, bright) = (color(0.5, 0.5, 0.5), color(2.0, 2.0, 2.0));
+        let white = color(1.0, 1.0, 1.0);
+        let black = color(0.0, 0.0, 0.0);
+
+        // A step in depth, one in the normal, and the same again without any albedo.
+        for albedo in [white, black] {
+            for (right_normal, right_depth) in [(up, 5.0), (vector3(1.0, 0.0, 0.0), 1.0)] {
+                let (image, guide) = split(
+                    (dark, albedo, up, 1.0),
+                    (bright, albedo, right_normal, right_depth),
+                    0.5,
+                );
+                let denoised = denoise(&image, &guide);
+
+                // Well inside each half, the noise mostly goes away.
+                for (from, to) in [(2, SIZE / 2 - 2), (SIZE / 2 + 2, SIZE - 2)] {
+                    let (before, _) = columns(&image, from, to);
+                    let (after, _) = columns(&denoised, from, to);
+                    assert!(after < before / 3.0, "{} against {}", after, before);
+                }
+
+                // While the columns either side of the step keep their own brightness.
+                let (_, left) = columns(&denoised, SIZE / 2 - 1, SIZE / 2);
+                let (_, right) = columns(&denoised, SIZE / 2, SIZE / 2 + 1);
+                assert!((left - 0.5).abs() < 0.1, "{}", left);
+                assert!((right - 2.0).abs() < 0.2, "{}", right);
+            }
+        }
+    }
+
+    // A fine texture of black and white under the same light, where the black pixels still show
+    // something, as glossy or glowing surfaces do. Dividing them by a tiny albedo would make their
+    // lighting huge and noisy, and let it bleed into the white pixels around them.
+    #[test]
+    fn pixels_without_albedo_dont_bleed_into_their_neighbors() {
+        let up = vector3(0.0, 0.0, 1.0);
+        let pixel = color(0.5, 0.5, 0.5);
+        let (image, mut guide) = split((pixel, pixel, up, 1.0), (pixel, pixel, up, 1.0), 0.2);
+        for (index, albedo) in guide.albedo.iter_mut().enumerate() {
+            *albedo = if (index + index / SIZE) & 1 == 0 {
+                color(1.0, 1.0, 1.0)
+            } else {
+                color(0.0, 0.0, 0.0)
+            };
+        }
+
+        let denoised = denoise(&image, &guide);
+        let (_, mean) = columns(&denoised, 0, SIZE);
+        assert!((mean - 0.5).abs() < 0.05, "{}", mean);
+        for pixel in &denoised.pixels {
+            assert!(pixel.luminance() < 1.0, "{}", pixel.luminance());
+        }
+    }
+}
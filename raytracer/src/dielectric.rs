@@ -0,0 +1,247 @@
+use super::color::{color, Color};
+use super::hit::{Face, HitData};
+use super::material::{Material, ScatterResult};
+use super::microfacet::{
+    fresnel_dielectric, reflect, refract, refraction_half_vector, Frame, TrowbridgeReitz,
+};
+use super::ray::Ray;
+use super::sampler::Sampler;
+use super::spectrum::{Spectrum, Wavelengths};
+use super::vector::{vector3, Vector3};
+use super::Number;
+
+/// Index of refraction of a dielectric, which can change with the wavelength so that white light
+/// splits into its colors. The formulas take wavelengths in micrometres, like the coefficients
+/// found in glass catalogues.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Ior {
+    Constant(Number),
+    /// `a + b / λ²`, good enough for most glass over the visible range.
+    Cauchy {
+        a: Number,
+        b: Number,
+    },
+    /// `n² = 1 + Σ b λ² / (λ² - c)`, the fit most catalogues give.
+    Sellmeier {
+        b: [Number; 3],
+        c: [Number; 3],
+    },
+}
+
+impl Ior {
+    /// The Fraunhofer d line, where glass catalogues quote a single index.
+    pub const D_LINE: Number = 587.56;
+
+    /// The index at `lambda` nanometres.
+    pub fn at(&self, lambda: Number) -> Number {
+        let micrometres = lambda / 1000.0;
+        let lambda2 = micrometres * micrometres;
+
+        match *self {
+            Ior::Constant(ior) => ior,
+            Ior::Cauchy { a, b } => a + b / lambda2,
+            Ior::Sellmeier { b, c } => (1.0
+                + (0..3)
+                    .map(|i| b[i] * lambda2 / (lambda2 - c[i]))
+                    .sum::<Number>())
+            .max(0.0)
+            .sqrt(),
+        }
+    }
+
+    /// The index used outside of spectral renders, where there's no wavelength to go by.
+    pub fn value(&self) -> Number {
+        self.at(Self::D_LINE)
+    }
+
+    pub fn is_dispersive(&self) -> bool {
+        !matches!(self, Ior::Constant(_))
+    }
+}
+
+/// Glass, water and other transparent materials, smooth or frosted. Roughness works like it does
+/// for `Conductor`.
+#[derive(Copy, Clone, PartialEq)]
+pub struct Dielectric {
+    pub ior: Ior,
+    pub roughness_u: Number,
+    pub roughness_v: Number,
+}
+
+impl Dielectric {
+    fn distribution(&self) -> TrowbridgeReitz {
+        TrowbridgeReitz::new(self.roughness_u, self.roughness_v)
+    }
+
+    // Relative index across the surface, in the direction the ray travels.
+    fn eta(ior: Number, hit_data: &HitData) -> Number {
+        match hit_data.face {
+            Face::Outwards => ior,
+            Face::Inwards => 1.0 / ior,
+        }
+    }
+
+    // Scatters through a surface with an index of `ior`, which `scatter` and `scatter_spectral`
+    // look up at different wavelengths. Dielectrics don't tint the light, so the attenuation
+    // is the same for all of them.
+    fn scatter_with(
+        &self,
+        ior: Number,
+        ray: &Ray,
+        hit_data: &HitData,
+        sampler: &mut dyn Sampler,
+    ) -> ScatterResult {
+        let eta = Self::eta(ior, hit_data);
+        let frame = Frame::new(hit_data.normal);
+        let wo = frame.to_local(&-ray.direction.normalize());
+        let distribution = self.distribution();
+
+        if wo.z <= 0.0 {
+            return ScatterResult::Absorbed;
+        }
+
+        // Reflection and refraction are chosen in proportion to the Fresnel term, which then
+        // cancels out of the weights. Refracted radiance gets compressed into a smaller or larger
+        // solid angle, hence the division by `eta` squared.
+        if distribution.is_smooth() {
+            let normal = vector3(0.0, 0.0, 1.0);
+            let reflectance = fresnel_dielectric(wo.z, eta);
+
+            return match refract(&wo, &normal, eta) {
+                Some(wi) if sampler.get_1d() >= reflectance => ScatterResult::new(
+                    ray,
+                    hit_data,
+                    frame.to_world(&wi),
+                    color(1.0, 1.0, 1.0) / (eta * eta),
+                    None,
+                ),
+                _ => ScatterResult::new(
+                    ray,
+                    hit_data,
+                    frame.to_world(&reflect(&wo, &normal)),
+                    color(1.0, 1.0, 1.0),
+                    None,
+                ),
+            };
+        }
+
+        let wm = distribution.sample_wm(&wo, sampler.get_2d());
+        let cos_o = wo.dot(&wm);
+        let reflectance = fresnel_dielectric(cos_o, eta);
+        let masking = |wi: &Vector3| distribution.g(&wo, wi) / distribution.g1(&wo);
+
+        if sampler.get_1d() < reflectance {
+            let wi = reflect(&wo, &wm);
+            if wi.z <= 0.0 {
+                return ScatterResult::Absorbed;
+            }
+
+            let pdf = distribution.pdf(&wo, &wm) / (4.0 * cos_o) * reflectance;
+            let attenuation = color(1.0, 1.0, 1.0) * masking(&wi);
+
+            ScatterResult::new(ray, hit_data, frame.to_world(&wi), attenuation, Some(pdf))
+        } else {
+            let wi = match refract(&wo, &wm, eta) {
+                Some(wi) if wi.z < 0.0 => wi,
+                _ => return ScatterResult::Absorbed,
+            };
+
+            let denominator = (wi.dot(&wm) + cos_o / eta).powi(2);
+            let pdf =
+                distribution.pdf(&wo, &wm) * wi.dot(&wm).abs() / denominator * (1.0 - reflectance);
+            let attenuation = color(1.0, 1.0, 1.0) * (masking(&wi) / (eta * eta));
+
+            ScatterResult::new(ray, hit_data, frame.to_world(&wi), attenuation, Some(pdf))
+        }
+    }
+}
+
+impl Material for Dielectric {
+    fn scatter(&self, ray: &Ray, hit_data: &HitData, sampler: &mut dyn Sampler) -> ScatterResult {
+        self.scatter_with(self.ior.value(), ray, hit_data, sampler)
+    }
+
+    // Every wavelength would bend its own way, so a dispersive index only keeps the hero.
+    fn scatter_spectral(
+        &self,
+        ray: &Ray,
+        hit_data: &HitData,
+        sampler: &mut dyn Sampler,
+        wavelengths: &mut Wavelengths,
+    ) -> ScatterResult<Spectrum> {
+        if self.ior.is_dispersive() {
+            wavelengths.terminate_secondary();
+        }
+
+        self.scatter_with(self.ior.at(wavelengths.hero()), ray, hit_data, sampler)
+            .map(|attenuation| Spectrum::splat(attenuation.r))
+    }
+
+    fn eval(&self, hit_data: &HitData, wo: &Vector3, wi: &Vector3) -> Color {
+        let eta = Self::eta(self.ior.value(), hit_data);
+        let frame = Frame::new(hit_data.normal);
+        let (wo, wi) = (frame.to_local(wo), frame.to_local(wi));
+        let distribution = self.distribution();
+
+        if distribution.is_smooth() || wo.z <= 0.0 || wi.z == 0.0 {
+            return color(0.0, 0.0, 0.0);
+        }
+
+        let value = if wi.z > 0.0 {
+            let half = wo + wi;
+            if half.near_zero() {
+                return color(0.0, 0.0, 0.0);
+            }
+
+            let wm = half.normalize();
+            fresnel_dielectric(wo.dot(&wm), eta) * distribution.d(&wm) * distribution.g(&wo, &wi)
+                / (4.0 * wo.z * wi.z)
+        } else {
+            let (wm, denominator) = match refraction_half_vector(&wo, &wi, eta) {
+                Some(half) => half,
+                None => return color(0.0, 0.0, 0.0),
+            };
+
+            (1.0 - fresnel_dielectric(wo.dot(&wm), eta))
+                * distribution.d(&wm)
+                * distribution.g(&wo, &wi)
+                * (wi.dot(&wm) * wo.dot(&wm) / (wi.z * wo.z * denominator)).abs()
+                / (eta * eta)
+        };
+
+        color(value, value, value)
+    }
+
+    fn pdf(&self, hit_data: &HitData, wo: &Vector3, wi: &Vector3) -> Number {
+        let eta = Self::eta(self.ior.value(), hit_data);
+        let frame = Frame::new(hit_data.normal);
+        let (wo, wi) = (frame.to_local(wo), frame.to_local(wi));
+        let distribution = self.distribution();
+
+        if distribution.is_smooth() || wo.z <= 0.0 || wi.z == 0.0 {
+            return 0.0;
+        }
+
+        if wi.z > 0.0 {
+            let half = wo + wi;
+            if half.near_zero() {
+                return 0.0;
+            }
+
+            let wm = half.normalize();
+            distribution.pdf(&wo, &wm) / (4.0 * wo.dot(&wm)) * fresnel_dielectric(wo.dot(&wm), eta)
+        } else {
+            match refraction_half_vector(&wo, &wi, eta) {
+                Some((wm, denominator)) => {
+                    distribution.pdf(&wo, &wm) * wi.dot(&wm).abs() / denominator
+                        * (1.0 - fresnel_dielectric(wo.dot(&wm), eta))
+                }
+                None => 0.0,
+            }
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "Dielectric"
+    }
+}
@@ -0,0 +1,191 @@
+use super::Number;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum FilterKind {
+    Box,
+    Tent,
+    Gaussian,
+    Mitchell,
+    Lanczos,
+}
+
+impl FilterKind {
+    pub const ALL: [FilterKind; 5] = [
+        FilterKind::Box,
+        FilterKind::Tent,
+        FilterKind::Gaussian,
+        FilterKind::Mitchell,
+        FilterKind::Lanczos,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            FilterKind::Box => "box",
+            FilterKind::Tent => "tent",
+            FilterKind::Gaussian => "gaussian",
+            FilterKind::Mitchell => "mitchell",
+            FilterKind::Lanczos => "lanczos",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.name() == name)
+    }
+
+    /// The radius, in pixels, each filter is usually used with.
+    pub fn default_radius(&self) -> Number {
+        match self {
+            FilterKind::Box => 0.5,
+            FilterKind::Tent => 1.0,
+            FilterKind::Gaussian => 1.5,
+            FilterKind::Mitchell => 2.0,
+            FilterKind::Lanczos => 3.0,
+        }
+    }
+}
+
+/// Reconstruction filter used to turn samples into pixels. Every sample contributes to all the
+/// pixels whose center lies within `radius` pixels of it, weighted by the filter.
+///
+/// A box with a radius of half a pixel just averages the samples that fall inside each pixel.
+/// Mitchell and Lanczos have negative lobes, which sharpen edges but can ring around very
+/// bright ones.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Filter {
+    pub kind: FilterKind,
+    pub radius: Number,
+}
+
+impl Filter {
+    pub fn new(kind: FilterKind) -> Self {
+        Self {
+            kind,
+            radius: kind.default_radius(),
+        }
+    }
+
+    /// Weight of a sample at offset `(x, y)` from a pixel center. All filters are separable.
+    pub fn evaluate(&self, x: Number, y: Number) -> Number {
+        self.evaluate_1d(x) * self.evaluate_1d(y)
+    }
+
+    fn evaluate_1d(&self, x: Number) -> Number {
+        let radius = self.radius;
+        let x = x.abs();
+
+        if x > radius {
+            return 0.0;
+        }
+
+        match self.kind {
+            FilterKind::Box => 1.0,
+
+            FilterKind::Tent => radius - x,
+
+            // Shifted down so the filter reaches zero at the radius instead of being cut off.
+            FilterKind::Gaussian => {
+                let sigma = radius / 3.0;
+                let gaussian = |x: Number| (-x * x / (2.0 * sigma * sigma)).exp();
+                gaussian(x) - gaussian(radius)
+            }
+
+            // With B = C = 1/3, as recommended by Mitchell and Netravali.
+            FilterKind::Mitchell => {
+                let (b, c) = (1.0 / 3.0, 1.0 / 3.0);
+                let x = 2.0 * x / radius;
+
+                let weight = if x > 1.0 {
+                    (-b - 6.0 * c) * x * x * x
+                        + (6.0 * b + 30.0 * c) * x * x
+                        + (-12.0 * b - 48.0 * c) * x
+                        + (8.0 * b + 24.0 * c)
+                } else {
+                    (12.0 - 9.0 * b - 6.0 * c) * x * x * x
+                        + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+                        + (6.0 - 2.0 * b)
+                };
+
+                weight / 6.0
+            }
+
+            // A sinc windowed by a wider sinc, with as many lobes as the radius is wide.
+            FilterKind::Lanczos => sinc(x) * sinc(x / radius),
+        }
+    }
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Self::new(FilterKind::Box)
+    }
+}
+
+fn sinc(x: Number) -> Number {
+    let x = std::f64::consts::PI as Number * x;
+
+    if x.abs() < 1e-5 {
+        1.0
+    } else {
+        x.sin() / x
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The 1D weights at `steps` points across the filter's support.
+    fn weights(filter: &Filter, steps: usize) -> Vec<Number> {
+        (0..=steps)
+            .map(|step| {
+                let x = filter.radius * (2.0 * step as Number / steps as Number - 1.0);
+                filter.evaluate_1d(x)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn weights_peak_at_the_center_and_end_at_the_radius() {
+        for kind in FilterKind::ALL {
+            let filter = Filter::new(kind);
+            let center = filter.evaluate_1d(0.0);
+
+            assert!(center > 0.0, "{}", kind.name());
+            assert!(weights(&filter, 100).iter().all(|&weight| weight <= center));
+            assert_eq!(filter.evaluate(filter.radius * 1.01, 0.0), 0.0);
+            assert_eq!(filter.evaluate(0.0, -filter.radius * 1.01), 0.0);
+
+            if kind != FilterKind::Box {
+                assert!(
+                    filter.evaluate_1d(filter.radius).abs() < 1e-4,
+                    "{}",
+                    kind.name()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn weights_integrate_to_a_positive_total() {
+        for kind in FilterKind::ALL {
+            let filter = Filter::new(kind);
+            let steps = 1000;
+            let total = weights(&filter, steps).iter().sum::<Number>() * 2.0 * filter.radius
+                / steps as Number;
+
+            assert!(total > 0.0, "{}", kind.name());
+        }
+    }
+
+    #[test]
+    fn only_mitchell_and_lanczos_go_negative() {
+        for kind in FilterKind::ALL {
+            let negative = weights(&Filter::new(kind), 100)
+                .iter()
+                .any(|&weight| weight < 0.0);
+            let expected = matches!(kind, FilterKind::Mitchell | FilterKind::Lanczos);
+
+            assert_eq!(negative, expected, "{}", kind.name());
+        }
+    }
+}
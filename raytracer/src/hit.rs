@@ -1,9 +1,11 @@
-use std::fmt::Display;
+use std::any::Any;
 
-use crate::material::Material;
-use crate::ray::Ray;
-use crate::vector::Vector3;
-use crate::Number;
+use super::material::Material;
+use super::ray::{Ray, RayPacket};
+use super::sampler::Sampler;
+use super::simd::{Float4, LANES};
+use super::vector::{vector3, Vector3};
+use super::Number;
 
 #[derive(Copy, Clone)]
 pub enum Face {
@@ -27,10 +29,103 @@ pub struct HitData<'a> {
     pub normal: Vector3,
     pub point: Vector3,
     pub face: Face,
-    pub material: &'a Material,
+    /// Surface coordinates of the hit, for texture lookups.
+    pub uv: (Number, Number),
+    pub material: &'a dyn Material,
+    /// How far `point` may be off the actual surface along each axis, from rounding.
+    pub error: Vector3,
 }
 
-pub trait Hit {
-    fn hit(&self, ray: &Ray, t_min: Number, t_max: Number) -> Option<HitData>;
+impl HitData<'_> {
+    /// Where a ray leaving the surface towards `direction` starts. The hit point is pushed along
+    /// the normal just past its error bounds, and rounded away from the surface, so that the ray
+    /// can't hit the surface it leaves again. This is how PBRT does it ("Physically Based
+    /// Rendering", section 3.9), and it holds at any scale, unlike a fixed distance.
+    pub fn spawn_origin(&self, direction: &Vector3) -> Vector3 {
+        let distance = self.normal.abs().dot(&self.error);
+        let mut offset = self.normal * distance;
+        if direction.dot(&self.normal) < 0.0 {
+            offset = -offset;
+        }
+
+        let origin = self.point + offset;
+        let away = |value: Number, offset: Number| {
+            if offset > 0.0 {
+                next_float_up(value)
+            } else if offset < 0.0 {
+                next_float_down(value)
+            } else {
+                value
+            }
+        };
+
+        vector3(
+            away(origin.x, offset.x),
+            away(origin.y, offset.y),
+            away(origin.z, offset.z),
+        )
+    }
+}
+
+/// Bounds the relative error of `n` floating point operations in a row.
+pub fn gamma(n: u32) -> Number {
+    let epsilon = n as Number * Number::EPSILON * 0.5;
+    epsilon / (1.0 - epsilon)
+}
+
+/// The smallest number above `value`.
+pub fn next_float_up(value: Number) -> Number {
+    if value.is_infinite() && value > 0.0 {
+        return value;
+    }
+
+    // Negative zero has to move to the positive side too.
+    let value = if value == 0.0 { 0.0 } else { value };
+    let bits = value.to_bits();
+    Number::from_bits(if value >= 0.0 { bits + 1 } else { bits - 1 })
+}
+
+/// The largest number below `value`.
+pub fn next_float_down(value: Number) -> Number {
+    if value.is_infinite() && value < 0.0 {
+        return value;
+    }
+
+    let value = if value == 0.0 { -0.0 } else { value };
+    let bits = value.to_bits();
+    Number::from_bits(if value > 0.0 { bits - 1 } else { bits + 1 })
+}
+
+pub trait Hit: Send + Sync {
+    fn hit(&self, ray: &Ray, t_min: Number, t_max: Number) -> Option<HitData<'_>>;
+
+    /// Like `hit`, but participating media draw where the ray scatters in them from `sampler`.
+    /// Surfaces don't need any random numbers, so by default this is just `hit`.
+    fn hit_sampled(
+        &self,
+        ray: &Ray,
+        t_min: Number,
+        t_max: Number,
+        _sampler: &mut dyn Sampler,
+    ) -> Option<HitData<'_>> {
+        self.hit(ray, t_min, t_max)
+    }
+
+    /// Hits every ray of `packet`, each up to its own lane of `t_max`.
+    fn hit_packet(
+        &self,
+        packet: &RayPacket,
+        t_min: Number,
+        t_max: Float4,
+    ) -> [Option<HitData<'_>>; LANES] {
+        let mut hits = [None; LANES];
+        for ((hit, ray), t_max) in hits.iter_mut().zip(&packet.rays).zip(t_max.to_array()) {
+            *hit = self.hit(ray, t_min, t_max);
+        }
+        hits
+    }
+
     fn name(&self) -> &'static str;
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
 }
@@ -0,0 +1,44 @@
+use super::color::{color, Color};
+use super::color_space::DisplayTransform;
+
+// Final pixel values of a render, stored row by row starting at the top left corner.
+
+#[derive(Clone)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Color>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![color(0.0, 0.0, 0.0); width * height],
+        }
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Color {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, color: Color) {
+        self.pixels[y * self.width + x] = color;
+    }
+
+    /// Copies `other` into this image with its top left corner at `x`, `y`.
+    pub fn paste(&mut self, other: &Image, x: usize, y: usize) {
+        for (row, pixels) in other.pixels.chunks(other.width).enumerate() {
+            let start = (y + row) * self.width + x;
+            self.pixels[start..start + other.width].copy_from_slice(pixels);
+        }
+    }
+
+    pub fn to_rgb8(&self, display: &DisplayTransform) -> Vec<u8> {
+        self.pixels
+            .iter()
+            .flat_map(|pixel| pixel.to_rgb8(display))
+            .collect()
+    }
+}
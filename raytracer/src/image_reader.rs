@@ -0,0 +1,235 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+
+use super::color::{color, Color};
+use super::color_space::{ColorSpace, DisplayTransform};
+use super::image::Image;
+use super::image_writer::ImageFormat;
+use super::Number;
+
+// Reads back the formats `ImageWriter` produces. There is no inflate implementation, so PNG
+// support is limited to uncompressed files like the ones written here.
+
+pub struct ImageReader {
+    buffer: BufReader<File>,
+    format: ImageFormat,
+    display: DisplayTransform,
+}
+
+impl ImageReader {
+    /// Reads an image encoded with `display`, undoing it to get back linear sRGB.
+    pub fn new(file: File, format: ImageFormat, display: DisplayTransform) -> Self {
+        Self {
+            buffer: BufReader::new(file),
+            format,
+            display,
+        }
+    }
+
+    pub fn read(&mut self) -> io::Result<Image> {
+        let mut data = Vec::new();
+        self.buffer.read_to_end(&mut data)?;
+
+        match self.format {
+            ImageFormat::Ppm => read_ppm(&data, &self.display),
+            ImageFormat::Png => read_png(&data, &self.display),
+            ImageFormat::Pfm => read_pfm(&data, &self.display),
+        }
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// Splits the whitespace separated header fields of the netpbm style formats.
+struct Header<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Header<'a> {
+    fn token(&mut self) -> io::Result<&'a str> {
+        loop {
+            match self.data.get(self.position) {
+                Some(b'#') => {
+                    while !matches!(self.data.get(self.position), Some(b'\n') | None) {
+                        self.position += 1;
+                    }
+                }
+                Some(c) if c.is_ascii_whitespace() => self.position += 1,
+                Some(_) => break,
+                None => return Err(invalid("unexpected end of image header")),
+            }
+        }
+
+        let start = self.position;
+        while matches!(self.data.get(self.position), Some(c) if !c.is_ascii_whitespace()) {
+            self.position += 1;
+        }
+
+        std::str::from_utf8(&self.data[start..self.position])
+            .map_err(|_| invalid("invalid image header"))
+    }
+
+    fn value<T: std::str::FromStr>(&mut self) -> io::Result<T> {
+        self.token()?
+            .parse()
+            .map_err(|_| invalid("invalid image header"))
+    }
+
+    // Binary data starts after the single whitespace character ending the header.
+    fn body(&self) -> &'a [u8] {
+        &self.data[(self.position + 1).min(self.data.len())..]
+    }
+}
+
+fn read_ppm(data: &[u8], display: &DisplayTransform) -> io::Result<Image> {
+    let mut header = Header { data, position: 0 };
+    let magic = header.token()?;
+    let width: usize = header.value()?;
+    let height: usize = header.value()?;
+    if header.value::<usize>()? != 255 {
+        return Err(invalid("only 8 bit ppm images are supported"));
+    }
+
+    let mut image = Image::new(width, height);
+
+    match magic {
+        "P3" => {
+            for pixel in &mut image.pixels {
+                *pixel =
+                    Color::from_rgb8([header.value()?, header.value()?, header.value()?], display);
+            }
+        }
+        "P6" => {
+            let body = header.body();
+            if body.len() < width * height * 3 {
+                return Err(invalid("truncated ppm image"));
+            }
+
+            for (pixel, rgb) in image.pixels.iter_mut().zip(body.chunks(3)) {
+                *pixel = Color::from_rgb8([rgb[0], rgb[1], rgb[2]], display);
+            }
+        }
+        _ => return Err(invalid("not a ppm image")),
+    }
+
+    Ok(image)
+}
+
+fn read_pfm(data: &[u8], display: &DisplayTransform) -> io::Result<Image> {
+    let mut header = Header { data, position: 0 };
+    if header.token()? != "PF" {
+        return Err(invalid("not an rgb pfm image"));
+    }
+
+    let width: usize = header.value()?;
+    let height: usize = header.value()?;
+    let little_endian = header.value::<f32>()? < 0.0;
+
+    let body = header.body();
+    if body.len() < width * height * 12 {
+        return Err(invalid("truncated pfm image"));
+    }
+
+    let channel = |bytes: &[u8]| {
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        (if little_endian {
+            f32::from_le_bytes(bytes)
+        } else {
+            f32::from_be_bytes(bytes)
+        }) as Number
+    };
+
+    let mut image = Image::new(width, height);
+    for (row, pixels) in body.chunks(width * 12).take(height).enumerate() {
+        for (x, rgb) in pixels.chunks(12).enumerate() {
+            let pixel = color(channel(&rgb[0..]), channel(&rgb[4..]), channel(&rgb[8..]));
+            image.set(
+                x,
+                height - 1 - row,
+                pixel.convert(display.space, ColorSpace::Srgb),
+            );
+        }
+    }
+
+    Ok(image)
+}
+
+fn read_png(data: &[u8], display: &DisplayTransform) -> io::Result<Image> {
+    if !data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        return Err(invalid("not a png image"));
+    }
+
+    let mut position = 8;
+    let mut header = None;
+    let mut stream = Vec::new();
+
+    while position + 12 <= data.len() {
+        let length = u32::from_be_bytes(data[position..position + 4].try_into().unwrap()) as usize;
+        let kind = &data[position + 4..position + 8];
+        let chunk = data
+            .get(position + 8..position + 8 + length)
+            .ok_or_else(|| invalid("truncated png image"))?;
+
+        match kind {
+            b"IHDR" => header = Some(chunk),
+            b"IDAT" => stream.extend_from_slice(chunk),
+            b"IEND" => break,
+            _ => {}
+        }
+
+        position += length + 12;
+    }
+
+    let header = header.ok_or_else(|| invalid("png image has no header"))?;
+    let width = u32::from_be_bytes(header[0..4].try_into().unwrap()) as usize;
+    let height = u32::from_be_bytes(header[4..8].try_into().unwrap()) as usize;
+    if header[8..13] != [8, 2, 0, 0, 0] {
+        return Err(invalid("only 8 bit rgb png images are supported"));
+    }
+
+    // Skip the zlib header and walk the deflate blocks, which all have to be stored as is.
+    let mut raw = Vec::new();
+    let mut position = 2;
+    loop {
+        let block = stream
+            .get(position..position + 5)
+            .ok_or_else(|| invalid("truncated png image"))?;
+        if block[0] & 0b110 != 0 {
+            return Err(invalid("compressed png images are not supported"));
+        }
+
+        let length = u16::from_le_bytes([block[1], block[2]]) as usize;
+        position += 5;
+        raw.extend_from_slice(
+            stream
+                .get(position..position + length)
+                .ok_or_else(|| invalid("truncated png image"))?,
+        );
+        position += length;
+
+        if block[0] & 1 != 0 {
+            break;
+        }
+    }
+
+    let stride = width * 3 + 1;
+    if raw.len() < stride * height {
+        return Err(invalid("truncated png image"));
+    }
+
+    let mut image = Image::new(width, height);
+    for (y, row) in raw.chunks(stride).take(height).enumerate() {
+        if row[0] != 0 {
+            return Err(invalid("filtered png images are not supported"));
+        }
+
+        for (x, rgb) in row[1..].chunks(3).enumerate() {
+            image.set(x, y, Color::from_rgb8([rgb[0], rgb[1], rgb[2]], display));
+        }
+    }
+
+    Ok(image)
+}
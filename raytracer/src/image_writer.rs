@@ -1,62 +1,164 @@
 use std::fs::File;
-use std::io::{BufWriter, Write};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
 
-use crate::color::Color;
-use crate::Number;
+use super::color_space::{ColorSpace, DisplayTransform};
+use super::image::Image;
 
-// Simple writer for the ppm image file format. I know it's terrible, but it's dead simple.
+// Writers for a few formats simple enough to not need any dependencies. PNG is written without
+// compression, which keeps the encoder tiny at the cost of bigger files.
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ImageFormat {
+    Ppm,
+    Png,
+    Pfm,
+}
+
+impl ImageFormat {
+    pub const ALL: [ImageFormat; 3] = [ImageFormat::Ppm, ImageFormat::Png, ImageFormat::Pfm];
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ImageFormat::Ppm => "ppm",
+            ImageFormat::Png => "png",
+            ImageFormat::Pfm => "pfm",
+        }
+    }
+
+    pub fn from_path(path: impl AsRef<Path>) -> Option<Self> {
+        let extension = path.as_ref().extension()?.to_str()?.to_lowercase();
+        Self::ALL
+            .into_iter()
+            .find(|format| format.extension() == extension)
+    }
+}
 
 pub struct ImageWriter {
     buffer: BufWriter<File>,
+    format: ImageFormat,
+    display: DisplayTransform,
 }
 
 impl ImageWriter {
-    pub fn new(file: File, width: usize, height: usize) -> Self {
-        let mut buffer = BufWriter::new(file);
-        buffer
-            .write(format!("P3 {} {} 255\n", width, height).as_bytes())
-            .expect("write image header");
+    pub fn new(file: File, format: ImageFormat, display: DisplayTransform) -> Self {
+        Self {
+            buffer: BufWriter::new(file),
+            format,
+            display,
+        }
+    }
 
-        Self { buffer }
+    pub fn write(&mut self, image: &Image) -> io::Result<()> {
+        match self.format {
+            ImageFormat::Ppm => self.write_ppm(image)?,
+            ImageFormat::Png => self.write_png(image)?,
+            ImageFormat::Pfm => self.write_pfm(image)?,
+        }
+
+        self.buffer.flush()
     }
 
-    pub fn write_pixel(&mut self, mut color: Color, samples: usize) {
-        color *= 1.0 / samples as Number;
+    fn write_ppm(&mut self, image: &Image) -> io::Result<()> {
+        writeln!(self.buffer, "P3 {} {} 255", image.width, image.height)?;
 
-        let r = color.r.sqrt();
-        let g = color.g.sqrt();
-        let b = color.b.sqrt();
+        for pixel in &image.pixels {
+            let [r, g, b] = pixel.to_rgb8(&self.display);
+            writeln!(self.buffer, "{} {} {}", r, g, b)?;
+        }
 
-        let channel_to_digits = |mut c: u8| -> (usize, [u8; 3]) {
-            let s = 0;
-            let mut v = [0, 0, 0];
+        Ok(())
+    }
 
-            for i in 0..3 {
-                let digit = b'0' + (c % 10);
+    // Linear, unclamped 32 bit floats in the display's color space, without its transfer
+    // function. Rows are stored bottom to top and a negative scale marks the data as little
+    // endian.
+    #[allow(clippy::unnecessary_cast)]
+    fn write_pfm(&mut self, image: &Image) -> io::Result<()> {
+        write!(self.buffer, "PF\n{} {}\n-1.0\n", image.width, image.height)?;
 
-                v[2 - i] = digit;
-                c /= 10;
+        for row in image.pixels.chunks(image.width).rev() {
+            for pixel in row {
+                let pixel = pixel.convert(ColorSpace::Srgb, self.display.space);
+                for channel in [pixel.r, pixel.g, pixel.b] {
+                    self.buffer.write_all(&(channel as f32).to_le_bytes())?;
+                }
             }
+        }
 
-            (s, v)
-        };
+        Ok(())
+    }
+
+    fn write_png(&mut self, image: &Image) -> io::Result<()> {
+        let mut header = Vec::with_capacity(13);
+        header.extend_from_slice(&(image.width as u32).to_be_bytes());
+        header.extend_from_slice(&(image.height as u32).to_be_bytes());
+        // 8 bit RGB, default compression and filtering, no interlacing.
+        header.extend_from_slice(&[8, 2, 0, 0, 0]);
 
-        let (s, v) = channel_to_digits((r * 256.0) as u8);
-        let r = &v[s..];
-        let (s, v) = channel_to_digits((g * 256.0) as u8);
-        let g = &v[s..];
-        let (s, v) = channel_to_digits((b * 256.0) as u8);
-        let b = &v[s..];
+        // Every scanline starts with its filter type, which is always "none" here.
+        let rgb = image.to_rgb8(&self.display);
+        let mut raw = Vec::with_capacity(image.height * (image.width * 3 + 1));
+        for row in rgb.chunks(image.width * 3) {
+            raw.push(0);
+            raw.extend_from_slice(row);
+        }
 
-        self.buffer.write(r).expect("write pixel");
-        self.buffer.write(b" ").expect("write pixel");
-        self.buffer.write(g).expect("write pixel");
-        self.buffer.write(b" ").expect("write pixel");
-        self.buffer.write(b).expect("write pixel");
-        self.buffer.write(b"\n").expect("write pixel");
+        self.buffer.write_all(b"\x89PNG\r\n\x1a\n")?;
+        self.write_png_chunk(b"IHDR", &header)?;
+        self.write_png_chunk(b"IDAT", &zlib_stored(&raw))?;
+        self.write_png_chunk(b"IEND", &[])
     }
 
-    pub fn flush(&mut self) {
-        self.buffer.flush().unwrap();
+    fn write_png_chunk(&mut self, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+        self.buffer.write_all(&(data.len() as u32).to_be_bytes())?;
+        self.buffer.write_all(kind)?;
+        self.buffer.write_all(data)?;
+        self.buffer
+            .write_all(&crc32(kind.iter().chain(data)).to_be_bytes())
     }
 }
+
+// A zlib stream made of uncompressed deflate blocks.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(u16::MAX as usize).peekable();
+
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+
+    while let Some(block) = blocks.next() {
+        let len = block.len() as u16;
+        out.push(blocks.peek().is_none() as u8);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    out.extend_from_slice(&((b << 16) | a).to_be_bytes());
+
+    out
+}
+
+fn crc32<'a>(data: impl Iterator<Item = &'a u8>) -> u32 {
+    let mut crc = !0u32;
+
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb88320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+
+    !crc
+}
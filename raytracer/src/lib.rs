@@ -1,28 +1,91 @@
-mod camera;
-mod color;
-mod hit;
-mod image_writer;
-mod material;
-mod ray;
-mod scene;
-mod sphere;
-mod vector;
+// The renderer is compiled once for each precision, so `f32` and `f64` scenes can be rendered
+// side by side in the same program. Its modules only refer to each other through `super`, which
+// is whichever of the two they were compiled into. Casts between `Number` and a fixed type are
+// bound to be unnecessary in one of them, so the functions making them allow
+// `clippy::unnecessary_cast`.
+macro_rules! renderer {
+    ($number:ty) => {
+        pub type Number = $number;
 
-pub use camera::*;
-pub use color::*;
-pub use hit::*;
-pub use image_writer::*;
-pub use material::*;
-pub use ray::*;
-pub use scene::*;
-pub use sphere::*;
-pub use vector::*;
+        mod animation;
+        mod camera;
+        mod color;
+        mod color_space;
+        mod conductor;
+        mod denoise;
+        mod dielectric;
+        mod filter;
+        mod hit;
+        mod image;
+        mod image_reader;
+        mod image_writer;
+        mod material;
+        mod medium;
+        mod microfacet;
+        mod principled;
+        mod ray;
+        mod render;
+        mod sampler;
+        mod scene;
+        mod scene_file;
+        mod simd;
+        mod spectrum;
+        mod sphere;
+        mod stats;
+        mod texture;
+        mod transform;
+        mod vector;
+        mod volume;
 
-#[cfg(all(feature = "f32", feature = "f64"))]
-compile_error!("f32 and f64 are mutually exclusive");
+        pub use animation::*;
+        pub use camera::*;
+        pub use color::*;
+        pub use color_space::*;
+        pub use conductor::*;
+        pub use denoise::*;
+        pub use dielectric::*;
+        pub use filter::*;
+        pub use hit::*;
+        pub use image::*;
+        pub use image_reader::*;
+        pub use image_writer::*;
+        pub use material::*;
+        pub use medium::*;
+        pub use microfacet::*;
+        pub use principled::*;
+        pub use ray::*;
+        pub use render::*;
+        pub use sampler::*;
+        pub use scene::*;
+        pub use scene_file::*;
+        pub use simd::*;
+        pub use spectrum::*;
+        pub use sphere::*;
+        pub use stats::*;
+        pub use texture::*;
+        pub use transform::*;
+        pub use vector::*;
+        pub use volume::*;
+    };
+}
 
-#[cfg(all(feature = "f32", not(feature = "f64")))]
-pub type Number = f32;
+/// The renderer with single precision numbers.
+#[path = "."]
+#[allow(clippy::duplicate_mod)]
+pub mod f32 {
+    renderer!(f32);
+}
 
-#[cfg(all(feature = "f64", not(feature = "f32")))]
-pub type Number = f64;
+/// The renderer with double precision numbers.
+#[path = "."]
+#[allow(clippy::duplicate_mod)]
+pub mod f64 {
+    renderer!(f64);
+}
+
+// The `f64` feature picks which one is used without naming it.
+#[cfg(not(feature = "f64"))]
+pub use self::f32::*;
+
+#[cfg(feature = "f64")]
+pub use self::f64::*;
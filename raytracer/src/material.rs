@@ -1,55 +1,410 @@
-use rand::Rng;
+use std::any::Any;
 
-use crate::color::Color;
-use crate::hit::HitData;
-use crate::ray::Ray;
-use crate::vector::Vector3;
-use crate::Number;
+use super::color::{color, Color};
+use super::hit::HitData;
+use super::ray::Ray;
+use super::sampler::Sampler;
+use super::spectrum::{Spectrum, Wavelengths};
+use super::vector::Vector3;
+use super::Number;
 
-pub enum Material {
-    Lambertian { albedo: Color },
-    Metal { albedo: Color, fuzz: Number },
+const PI: Number = std::f64::consts::PI as Number;
+
+// Directions passed to `eval` and `pdf` are in world space, normalized and pointing away from the
+// hit: `wo` back along the incoming ray and `wi` towards where the light comes from. The normal of
+// the hit always faces `wo`'s side.
+
+pub trait Material: MaterialObject + Send + Sync {
+    /// Picks a direction for the path to continue in.
+    fn scatter(&self, ray: &Ray, hit_data: &HitData, sampler: &mut dyn Sampler) -> ScatterResult;
+
+    /// The BSDF for light arriving from `wi` and leaving towards `wo`, without the cosine term.
+    /// Perfectly specular directions can only be reached through `scatter`, so they evaluate to
+    /// black here.
+    fn eval(&self, hit_data: &HitData, wo: &Vector3, wi: &Vector3) -> Color;
+
+    /// Density by solid angle with which `scatter` picks `wi`, leaving out specular directions.
+    fn pdf(&self, hit_data: &HitData, wo: &Vector3, wi: &Vector3) -> Number;
+
+    /// Light given off by the surface towards the origin of `ray`.
+    fn emitted(&self, _ray: &Ray, _hit_data: &HitData) -> Color {
+        color(0.0, 0.0, 0.0)
+    }
+
+    /// `scatter` for spectral renders. Materials whose response depends on the wavelength
+    /// override it, and may drop all but the hero wavelength when the path can't carry the
+    /// others along. The rest get their RGB attenuation upsampled.
+    fn scatter_spectral(
+        &self,
+        ray: &Ray,
+        hit_data: &HitData,
+        sampler: &mut dyn Sampler,
+        wavelengths: &mut Wavelengths,
+    ) -> ScatterResult<Spectrum> {
+        self.scatter(ray, hit_data, sampler)
+            .map(|attenuation| Spectrum::from_rgb(attenuation, wavelengths))
+    }
+
+    /// `emitted` for spectral renders.
+    fn emitted_spectral(
+        &self,
+        ray: &Ray,
+        hit_data: &HitData,
+        wavelengths: &Wavelengths,
+    ) -> Spectrum {
+        Spectrum::from_illuminant(self.emitted(ray, hit_data), wavelengths)
+    }
+
+    /// Color of the surface at `uv`, without any lighting. The denoiser divides it out, so
+    /// materials without a meaningful color can leave it white.
+    fn albedo(&self, _uv: (Number, Number)) -> Color {
+        color(1.0, 1.0, 1.0)
+    }
+
+    fn name(&self) -> &'static str;
+}
+
+/// What editors need to copy, compare and inspect materials behind a `dyn Material`. It comes for
+/// free with any material that is `Clone` and `PartialEq`.
+pub trait MaterialObject {
+    fn clone_box(&self) -> Box<dyn Material>;
+    fn equals(&self, other: &dyn Material) -> bool;
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<T: Material + Clone + PartialEq + 'static> MaterialObject for T {
+    fn clone_box(&self) -> Box<dyn Material> {
+        Box::new(self.clone())
+    }
+
+    fn equals(&self, other: &dyn Material) -> bool {
+        other.as_any().downcast_ref::<T>() == Some(self)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+impl Clone for Box<dyn Material> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
 }
 
-pub enum ScatterResult {
+impl PartialEq for dyn Material {
+    fn eq(&self, other: &Self) -> bool {
+        self.equals(other)
+    }
+}
+
+/// Where a path goes after a hit. The attenuation is a `Color`, or a `Spectrum` in spectral
+/// renders.
+pub enum ScatterResult<A = Color> {
     Absorbed,
-    Scattered { attenuation: Color, scattered: Ray },
+    Scattered {
+        /// The BSDF times the cosine term, divided by `pdf`.
+        attenuation: A,
+        scattered: Ray,
+        /// Density of the scattered direction by solid angle. `None` for perfectly specular
+        /// directions, which can't be reached by any other way of sampling.
+        pdf: Option<Number>,
+    },
 }
 
-impl Material {
-    pub fn scatter(&self, ray: &Ray, hit_data: &HitData, rand: &mut impl Rng) -> ScatterResult {
+impl<A> ScatterResult<A> {
+    /// Continues `ray` from the hit towards `direction`, at the same moment in time.
+    pub fn new(
+        ray: &Ray,
+        hit_data: &HitData,
+        direction: Vector3,
+        attenuation: A,
+        pdf: Option<Number>,
+    ) -> Self {
+        ScatterResult::Scattered {
+            attenuation,
+            scattered: Ray {
+                origin: hit_data.spawn_origin(&direction),
+                direction,
+                time: ray.time,
+            },
+            pdf,
+        }
+    }
+
+    pub fn map<B>(self, f: impl FnOnce(A) -> B) -> ScatterResult<B> {
         match self {
-            Material::Lambertian { albedo } => {
-                let mut direction = hit_data.normal + Vector3::random_normalized(rand);
+            ScatterResult::Absorbed => ScatterResult::Absorbed,
+            ScatterResult::Scattered {
+                attenuation,
+                scattered,
+                pdf,
+            } => ScatterResult::Scattered {
+                attenuation: f(attenuation),
+                scattered,
+                pdf,
+            },
+        }
+    }
+}
 
-                if direction.near_zero() {
-                    direction = hit_data.normal;
-                }
+#[derive(Copy, Clone, PartialEq)]
+pub struct Lambertian {
+    pub albedo: Color,
+}
+
+impl Material for Lambertian {
+    fn scatter(&self, ray: &Ray, hit_data: &HitData, sampler: &mut dyn Sampler) -> ScatterResult {
+        let mut direction = hit_data.normal + Vector3::on_unit_sphere(sampler.get_2d());
+
+        if direction.near_zero() {
+            direction = hit_data.normal;
+        }
+
+        let cos = direction.normalize().dot(&hit_data.normal);
+        ScatterResult::new(ray, hit_data, direction, self.albedo, Some(cos / PI))
+    }
+
+    fn eval(&self, hit_data: &HitData, _wo: &Vector3, wi: &Vector3) -> Color {
+        if wi.dot(&hit_data.normal) > 0.0 {
+            self.albedo / PI
+        } else {
+            color(0.0, 0.0, 0.0)
+        }
+    }
+
+    fn pdf(&self, hit_data: &HitData, _wo: &Vector3, wi: &Vector3) -> Number {
+        wi.dot(&hit_data.normal).max(0.0) / PI
+    }
+
+    fn albedo(&self, _uv: (Number, Number)) -> Color {
+        self.albedo
+    }
 
-                ScatterResult::Scattered {
-                    attenuation: *albedo,
-                    scattered: Ray {
-                        origin: hit_data.point,
-                        direction,
-                    },
+    fn name(&self) -> &'static str {
+        "Lambertian"
+    }
+}
+
+/// Reflection jittered inside a sphere of radius `fuzz`. Not energy conserving, and its lobe has
+/// no closed form, so it's treated as specular. `Conductor` is the physically based alternative.
+#[derive(Copy, Clone, PartialEq)]
+pub struct Metal {
+    pub albedo: Color,
+    pub fuzz: Number,
+}
+
+impl Material for Metal {
+    fn scatter(&self, ray: &Ray, hit_data: &HitData, sampler: &mut dyn Sampler) -> ScatterResult {
+        let direction = ray.direction.normalize().reflect(&hit_data.normal)
+            + Vector3::in_unit_sphere(sampler.get_2d(), sampler.get_1d()) * self.fuzz;
+        if direction.dot(&hit_data.normal) > 0.0 {
+            ScatterResult::new(ray, hit_data, direction, self.albedo, None)
+        } else {
+            ScatterResult::Absorbed
+        }
+    }
+
+    fn eval(&self, _hit_data: &HitData, _wo: &Vector3, _wi: &Vector3) -> Color {
+        color(0.0, 0.0, 0.0)
+    }
+
+    fn pdf(&self, _hit_data: &HitData, _wo: &Vector3, _wi: &Vector3) -> Number {
+        0.0
+    }
+
+    fn albedo(&self, _uv: (Number, Number)) -> Color {
+        self.albedo
+    }
+
+    fn name(&self) -> &'static str {
+        "Metal"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::conductor::Conductor;
+    use super::super::dielectric::{Dielectric, Ior};
+    use super::super::hit::Face;
+    use super::super::microfacet::ComplexIor;
+    use super::super::principled::Principled;
+    use super::super::sampler::SamplerKind;
+    use super::super::texture::Texture;
+    use super::super::vector::vector3;
+    use super::*;
+
+    // Bins of equal solid angle over the sphere, by cosine and azimuth.
+    const Z_BINS: usize = 16;
+    const PHI_BINS: usize = 32;
+
+    fn bin(direction: &Vector3) -> usize {
+        let z = ((direction.z + 1.0) * 0.5 * Z_BINS as Number) as usize;
+        let phi = direction.y.atan2(direction.x) / (2.0 * PI) + 0.5;
+        let phi = (phi * PHI_BINS as Number) as usize;
+        z.min(Z_BINS - 1) * PHI_BINS + phi.min(PHI_BINS - 1)
+    }
+
+    // Checks `scatter` against `eval` and `pdf` for light leaving a surface facing +z towards
+    // `wo`. Each sample must carry the density `pdf` gives its direction and the weight `eval`
+    // gives it, and the samples must really be distributed by that density: a chi-square test
+    // compares how many land in each bin with the density integrated over the bin.
+    #[allow(clippy::unnecessary_cast)]
+    fn check_sampling(material: &dyn Material, face: Face, wo: Vector3) {
+        let name = material.name();
+        let hit_data = HitData {
+            t: 1.0,
+            normal: vector3(0.0, 0.0, 1.0),
+            point: vector3(0.0, 0.0, 0.0),
+            face,
+            uv: (0.5, 0.5),
+            material,
+            error: vector3(0.0, 0.0, 0.0),
+        };
+        let wo = wo.normalize();
+        let ray = Ray {
+            origin: wo,
+            direction: -wo,
+            time: 0.0,
+        };
+
+        let mut sampler = SamplerKind::Independent.create(3, 1);
+        let count = 200000;
+        let mut observed = vec![0.0; Z_BINS * PHI_BINS];
+
+        for index in 0..count {
+            sampler.start_pixel_sample(index, 0, 0);
+
+            if let ScatterResult::Scattered {
+                attenuation,
+                scattered,
+                pdf,
+            } = material.scatter(&ray, &hit_data, sampler.as_mut())
+            {
+                let pdf = pdf.expect("rough materials have a density");
+                let wi = scattered.direction.normalize();
+
+                let expected = material.pdf(&hit_data, &wo, &wi);
+                assert!((pdf - expected).abs() <= 1e-3 * expected, "{}", name);
+
+                let expected = material.eval(&hit_data, &wo, &wi) * (wi.z.abs() / pdf);
+                for (value, expected) in [
+                    (attenuation.r, expected.r),
+                    (attenuation.g, expected.g),
+                    (attenuation.b, expected.b),
+                ] {
+                    assert!(
+                        (value - expected).abs() <= 1e-3 * expected.max(1.0),
+                        "{}",
+                        name
+                    );
                 }
+
+                observed[bin(&wi)] += 1.0;
             }
+        }
 
-            Material::Metal { albedo, fuzz } => {
-                let direction = ray.direction.normalize().reflect(&hit_data.normal)
-                    + Vector3::random_in_unit_sphere(rand) * *fuzz;
-                if direction.dot(&hit_data.normal) > 0.0 {
-                    ScatterResult::Scattered {
-                        attenuation: *albedo,
-                        scattered: Ray {
-                            origin: hit_data.point,
-                            direction,
-                        },
-                    }
-                } else {
-                    ScatterResult::Absorbed
-                }
+        // The density integrated over each bin with the midpoint rule, in steps of cosine and
+        // azimuth, which are area preserving.
+        let steps = 12;
+        let dz = 2.0 / (Z_BINS * steps) as f64;
+        let dphi = 2.0 * std::f64::consts::PI / (PHI_BINS * steps) as f64;
+        let mut expected = vec![0.0; Z_BINS * PHI_BINS];
+
+        for zi in 0..Z_BINS * steps {
+            let z = -1.0 + (zi as f64 + 0.5) * dz;
+            for phii in 0..PHI_BINS * steps {
+                let phi = -std::f64::consts::PI + (phii as f64 + 0.5) * dphi;
+                let r = (1.0 - z * z).sqrt();
+                let wi = vector3(
+                    (r * phi.cos()) as Number,
+                    (r * phi.sin()) as Number,
+                    z as Number,
+                );
+                let index = (zi / steps) * PHI_BINS + phii / steps;
+                expected[index] +=
+                    material.pdf(&hit_data, &wo, &wi) as f64 * dz * dphi * count as f64;
+            }
+        }
+
+        // Bins expected to get only a few samples get pooled, as usual for the test.
+        let (mut chi_square, mut degrees, mut pooled_observed, mut pooled_expected) =
+            (0.0, 0, 0.0, 0.0);
+        for (&observed, &expected) in observed.iter().zip(&expected) {
+            if expected < 5.0 {
+                pooled_observed += observed;
+                pooled_expected += expected;
+            } else {
+                chi_square += (observed - expected) * (observed - expected) / expected;
+                degrees += 1;
             }
         }
+        if pooled_expected > 0.0 {
+            chi_square += (pooled_observed - pooled_expected).powi(2) / pooled_expected;
+            degrees += 1;
+        }
+
+        let limit = degrees as f64 + 5.0 * (2.0 * degrees as f64).sqrt();
+        assert!(
+            chi_square < limit,
+            "{}: chi-square {} over {} bins",
+            name,
+            chi_square,
+            degrees
+        );
+    }
+
+    #[test]
+    fn lambertian_samples_match_eval_and_pdf() {
+        let material = Lambertian {
+            albedo: color(0.9, 0.5, 0.1),
+        };
+
+        check_sampling(&material, Face::Outwards, vector3(0.3, -0.2, 0.9));
+    }
+
+    #[test]
+    fn conductor_samples_match_eval_and_pdf() {
+        let material = Conductor {
+            ior: ComplexIor::GOLD,
+            roughness_u: 0.3,
+            roughness_v: 0.6,
+        };
+
+        for wo in [vector3(0.3, -0.2, 0.9), vector3(0.9, 0.1, 0.2)] {
+            check_sampling(&material, Face::Outwards, wo);
+        }
+    }
+
+    #[test]
+    fn dielectric_samples_match_eval_and_pdf() {
+        let material = Dielectric {
+            ior: Ior::Constant(1.5),
+            roughness_u: 0.4,
+            roughness_v: 0.4,
+        };
+
+        for face in [Face::Outwards, Face::Inwards] {
+            check_sampling(&material, face, vector3(0.3, -0.2, 0.9));
+        }
+    }
+
+    #[test]
+    fn principled_samples_match_eval_and_pdf() {
+        let material = Principled {
+            base_color: Texture::Constant(color(0.8, 0.4, 0.2)),
+            metallic: Texture::scalar(0.2),
+            sheen: Texture::scalar(0.5),
+            clearcoat: Texture::scalar(1.0),
+            transmission: Texture::scalar(0.5),
+            ..Principled::default()
+        };
+
+        check_sampling(&material, Face::Outwards, vector3(0.3, -0.2, 0.9));
     }
 }
@@ -0,0 +1,348 @@
+use std::any::Any;
+
+use super::color::Color;
+use super::hit::{next_float_up, Face, Hit, HitData};
+use super::material::{Material, ScatterResult};
+use super::microfacet::Frame;
+use super::ray::Ray;
+use super::sampler::{hash, Sampler, SamplerKind};
+use super::vector::{vector3, Vector3};
+use super::Number;
+
+const PI: Number = std::f64::consts::PI as Number;
+
+// Homogeneous participating media. Light travelling through them gets scattered after a distance
+// drawn from an exponential distribution with the density as its rate, so the chance of crossing a
+// stretch unscattered falls off with Beer's law. Where it scatters, the phase function takes over
+// from the BSDF, and the scattering point is handed to the integrator as a regular hit.
+
+/// The Henyey-Greenstein phase function. `anisotropy` goes from -1, where light bounces back, to
+/// 1, where it keeps going forward; 0 scatters the same way in every direction. `albedo` is the
+/// fraction of light that gets scattered instead of absorbed.
+#[derive(Copy, Clone, PartialEq)]
+pub struct HenyeyGreenstein {
+    pub albedo: Color,
+    pub anisotropy: Number,
+}
+
+impl HenyeyGreenstein {
+    // Density of turning by an angle with cosine `cos` away from the direction of travel.
+    fn phase(&self, cos: Number) -> Number {
+        let g = self.anisotropy;
+        let denominator = 1.0 + g * g - 2.0 * g * cos;
+        (1.0 - g * g) / (4.0 * PI * denominator * denominator.max(0.0).sqrt())
+    }
+}
+
+// Volumes have no surface, so the phase function stands in for the BSDF without any cosine term.
+impl Material for HenyeyGreenstein {
+    fn scatter(&self, ray: &Ray, hit_data: &HitData, sampler: &mut dyn Sampler) -> ScatterResult {
+        let forward = ray.direction.normalize();
+        let g = self.anisotropy.clamp(-0.999, 0.999);
+        let (u, v) = sampler.get_2d();
+
+        let cos = if g.abs() < 1e-3 {
+            1.0 - 2.0 * u
+        } else {
+            let s = (1.0 - g * g) / (1.0 - g + 2.0 * g * u);
+            ((1.0 + g * g - s * s) / (2.0 * g)).clamp(-1.0, 1.0)
+        };
+        let sin = (1.0 - cos * cos).max(0.0).sqrt();
+        let phi = 2.0 * PI * v;
+
+        let local = vector3(sin * phi.cos(), sin * phi.sin(), cos);
+        let direction = Frame::new(forward).to_world(&local);
+
+        ScatterResult::new(ray, hit_data, direction, self.albedo, Some(self.phase(cos)))
+    }
+
+    fn eval(&self, _hit_data: &HitData, wo: &Vector3, wi: &Vector3) -> Color {
+        self.albedo * self.phase(-wo.dot(wi))
+    }
+
+    fn pdf(&self, _hit_data: &HitData, wo: &Vector3, wi: &Vector3) -> Number {
+        self.phase(-wo.dot(wi))
+    }
+
+    fn albedo(&self, _uv: (Number, Number)) -> Color {
+        self.albedo
+    }
+
+    fn name(&self) -> &'static str {
+        "Henyey-Greenstein"
+    }
+}
+
+/// Turns the inside of a closed object into a volume of constant density, such as smoke or a
+/// murky liquid. Only the shape of `boundary` matters, its material is never seen.
+pub struct ConstantMedium {
+    pub boundary: Box<dyn Hit>,
+    pub density: Number,
+    pub phase: HenyeyGreenstein,
+}
+
+impl Hit for ConstantMedium {
+    fn hit(&self, ray: &Ray, t_min: Number, t_max: Number) -> Option<HitData<'_>> {
+        self.hit_sampled(ray, t_min, t_max, &mut *ray_sampler(ray))
+    }
+
+    fn hit_sampled(
+        &self,
+        ray: &Ray,
+        t_min: Number,
+        t_max: Number,
+        sampler: &mut dyn Sampler,
+    ) -> Option<HitData<'_>> {
+        let enter = self
+            .boundary
+            .hit(ray, -Number::INFINITY, Number::INFINITY)?;
+        let exit = self
+            .boundary
+            .hit(ray, next_float_up(enter.t), Number::INFINITY)?;
+
+        let start = enter.t.max(t_min);
+        let end = exit.t.min(t_max);
+        if start >= end {
+            return None;
+        }
+
+        let t = start + free_flight(self.density, sampler.get_1d()) / ray.direction.length();
+        (t < end).then(|| scattering(ray, t, &self.phase))
+    }
+
+    fn name(&self) -> &'static str {
+        "Constant Medium"
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// Haze filling the scene up to `height`, between the surfaces. Looking up out of it, the sky
+/// gets clearer, while rays grazing the horizon stay in it long enough to fade into it.
+#[derive(Copy, Clone, PartialEq)]
+pub struct Fog {
+    pub density: Number,
+    pub height: Number,
+    pub phase: HenyeyGreenstein,
+}
+
+impl Fog {
+    /// Where `ray` scatters before reaching `t_max`, given a uniform random number `u`.
+    pub fn hit(&self, ray: &Ray, t_max: Number, u: Number) -> Option<HitData<'_>> {
+        let (origin, direction) = (ray.origin.y, ray.direction.y);
+        let crossing = (self.height - origin) / direction;
+
+        let (start, end) = if direction > 0.0 {
+            (0.0, t_max.min(crossing))
+        } else if direction < 0.0 {
+            (crossing.max(0.0), t_max)
+        } else if origin < self.height {
+            (0.0, t_max)
+        } else {
+            return None;
+        };
+
+        if start >= end {
+            return None;
+        }
+
+        let t = start + free_flight(self.density, u) / ray.direction.length();
+        (t < end).then(|| scattering(ray, t, &self.phase))
+    }
+}
+
+/// Distance travelled before scattering in a medium of `density`, in world units.
+pub(crate) fn free_flight(density: Number, u: Number) -> Number {
+    -(1.0 - u).ln() / density
+}
+
+/// Random numbers for volumes reached through plain `hit`, as the denoiser's feature rays are,
+/// where there's no sampler to draw from. They're seeded with the ray, so tracing the same one
+/// twice gives the same answer.
+#[allow(clippy::unnecessary_cast)]
+pub(crate) fn ray_sampler(ray: &Ray) -> Box<dyn Sampler> {
+    let seed = hash(&[
+        ray.origin.x.to_bits() as u64,
+        ray.origin.y.to_bits() as u64,
+        ray.origin.z.to_bits() as u64,
+        ray.direction.x.to_bits() as u64,
+        ray.direction.y.to_bits() as u64,
+        ray.direction.z.to_bits() as u64,
+    ]);
+
+    let mut sampler = SamplerKind::Independent.create(seed, 1);
+    sampler.start_pixel_sample(0, 0, 0);
+    sampler
+}
+
+/// A hit where the ray scatters at `t` inside a volume, which faces the ray from every side.
+pub(crate) fn scattering<'a>(ray: &Ray, t: Number, material: &'a dyn Material) -> HitData<'a> {
+    HitData {
+        t,
+        point: ray.at(t),
+        normal: -ray.direction.normalize(),
+        face: Face::Outwards,
+        uv: (0.0, 0.0),
+        material,
+        // Nothing to leave behind in a volume, so rays carry on from right where they scattered.
+        error: vector3(0.0, 0.0, 0.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::color::color;
+    use super::*;
+
+    const BINS: usize = 32;
+
+    // Scatters many rays heading along `forward` and checks with a chi-square test that the
+    // cosines of the angles they turn by are spread like the phase function says: each bin of
+    // cosine should get as many as the density integrated over it, around the whole circle.
+    #[allow(clippy::unnecessary_cast)]
+    fn check_phase(phase: HenyeyGreenstein, forward: Vector3) {
+        let ray = Ray {
+            origin: vector3(0.0, 0.0, 0.0),
+            direction: forward,
+            time: 0.0,
+        };
+        let hit_data = scattering(&ray, 1.0, &phase);
+        let forward = forward.normalize();
+
+        let mut sampler = SamplerKind::Independent.create(5, 1);
+        let count = 200000;
+        let mut observed = [0.0; BINS];
+
+        for index in 0..count {
+            sampler.start_pixel_sample(index, 0, 0);
+
+            match phase.scatter(&ray, &hit_data, sampler.as_mut()) {
+                ScatterResult::Scattered {
+                    attenuation,
+                    scattered,
+                    pdf,
+                } => {
+                    let wi = scattered.direction.normalize();
+                    let expected = phase.pdf(&hit_data, &-forward, &wi);
+                    let pdf = pdf.expect("phase functions have a density");
+                    assert!((pdf - expected).abs() <= 1e-3 * expected);
+                    assert!(attenuation == phase.albedo);
+
+                    let cos = wi.dot(&forward);
+                    let bin = ((cos + 1.0) * 0.5 * BINS as Number) as usize;
+                    observed[bin.min(BINS - 1)] += 1.0;
+                }
+                ScatterResult::Absorbed => panic!("volumes always scatter"),
+            }
+        }
+
+        // Each bin's share, by the midpoint rule in steps of cosine fine enough for sharp peaks.
+        let steps = 4096;
+        let dcos = 2.0 / (BINS * steps) as f64;
+        let mut expected = [0.0; BINS];
+        for i in 0..BINS * steps {
+            let cos = -1.0 + (i as f64 + 0.5) * dcos;
+            expected[i / steps] += 2.0
+                * std::f64::consts::PI
+                * phase.phase(cos as Number) as f64
+                * dcos
+                * count as f64;
+        }
+
+        // Bins expected to get only a few samples get pooled, as in the material tests.
+        let (mut chi_square, mut degrees, mut pooled_observed, mut pooled_expected) =
+            (0.0, 0, 0.0, 0.0);
+        for (&observed, &expected) in observed.iter().zip(&expected) {
+            if expected < 5.0 {
+                pooled_observed += observed;
+                pooled_expected += expected;
+            } else {
+                chi_square += (observed - expected) * (observed - expected) / expected;
+                degrees += 1;
+            }
+        }
+        if pooled_expected > 0.0 {
+            chi_square += (pooled_observed - pooled_expected).powi(2) / pooled_expected;
+            degrees += 1;
+        }
+
+        let limit = degrees as f64 + 5.0 * (2.0 * degrees as f64).sqrt();
+        assert!(
+            chi_square < limit,
+            "g = {}: chi-square {} over {} bins",
+            phase.anisotropy,
+            chi_square,
+            degrees
+        );
+    }
+
+    #[test]
+    fn henyey_greenstein_samples_follow_the_phase_function() {
+        for anisotropy in [0.0, 0.7, -0.4, 0.95] {
+            let phase = HenyeyGreenstein {
+                albedo: color(0.8, 0.6, 0.4),
+                anisotropy,
+            };
+
+            check_phase(phase, vector3(0.3, -0.5, 2.0));
+        }
+    }
+
+    // The share of evenly spread random numbers for which `ray` gets through the fog unscattered.
+    fn fog_transmittance(fog: &Fog, ray: &Ray, t_max: Number) -> Number {
+        let count = 10000;
+        let through = (0..count)
+            .filter(|&i| {
+                let u = (i as Number + 0.5) / count as Number;
+                fog.hit(ray, t_max, u).is_none()
+            })
+            .count();
+
+        through as Number / count as Number
+    }
+
+    #[test]
+    fn fog_lets_through_what_beers_law_predicts() {
+        let fog = Fog {
+            density: 0.3,
+            height: 1.0,
+            phase: HenyeyGreenstein {
+                albedo: color(1.0, 1.0, 1.0),
+                anisotropy: 0.0,
+            },
+        };
+
+        // Level inside the fog, with a direction that isn't normalized: 4 units long up to t 2.
+        let ray = Ray {
+            origin: vector3(0.0, 0.0, 0.0),
+            direction: vector3(0.0, 0.0, -2.0),
+            time: 0.0,
+        };
+        let expected = (-0.3 * 4.0 as Number).exp();
+        assert!((fog_transmittance(&fog, &ray, 2.0) - expected).abs() < 1e-3);
+
+        // Down from above, only the 5 units below the top of the fog count.
+        let ray = Ray {
+            origin: vector3(0.0, 4.0, 0.0),
+            direction: vector3(0.0, -0.6, 0.8),
+            time: 0.0,
+        };
+        let expected = (-0.3 * 5.0 as Number).exp();
+        assert!((fog_transmittance(&fog, &ray, 10.0) - expected).abs() < 1e-3);
+
+        // Going up from below, everything past the top of the fog is clear.
+        let ray = Ray {
+            origin: vector3(0.0, -2.0, 0.0),
+            direction: vector3(0.0, 1.0, 0.0),
+            time: 0.0,
+        };
+        let expected = (-0.3 * 3.0 as Number).exp();
+        assert!((fog_transmittance(&fog, &ray, Number::INFINITY) - expected).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,473 @@
+use super::color::{color, Color};
+use super::spectrum::{Spectrum, Wavelengths};
+use super::vector::{vector3, Vector3};
+use super::Number;
+
+const PI: Number = std::f64::consts::PI as Number;
+
+/// Orthonormal basis around a surface normal. Local coordinates have the normal along `z`.
+#[derive(Copy, Clone)]
+pub struct Frame {
+    pub tangent: Vector3,
+    pub bitangent: Vector3,
+    pub normal: Vector3,
+}
+
+impl Frame {
+    /// The tangent runs horizontally around the normal where possible, so anisotropic
+    /// roughness on a sphere is brushed along its lines of latitude.
+    pub fn new(normal: Vector3) -> Self {
+        let horizontal = vector3(0.0, 1.0, 0.0).cross(&normal);
+
+        let tangent = if horizontal.length_squared() > 1e-6 {
+            horizontal.normalize()
+        } else {
+            vector3(1.0, 0.0, 0.0)
+        };
+
+        Self {
+            tangent,
+            bitangent: normal.cross(&tangent),
+            normal,
+        }
+    }
+
+    pub fn to_local(&self, v: &Vector3) -> Vector3 {
+        vector3(
+            v.dot(&self.tangent),
+            v.dot(&self.bitangent),
+            v.dot(&self.normal),
+        )
+    }
+
+    pub fn to_world(&self, v: &Vector3) -> Vector3 {
+        self.tangent * v.x + self.bitangent * v.y + self.normal * v.z
+    }
+}
+
+/// Complex index of refraction of a conductor at red, green and blue wavelengths.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct ComplexIor {
+    pub eta: Color,
+    pub k: Color,
+}
+
+impl ComplexIor {
+    pub const GOLD: ComplexIor = ComplexIor {
+        eta: Color {
+            r: 0.143119,
+            g: 0.374957,
+            b: 1.442479,
+        },
+        k: Color {
+            r: 3.98316,
+            g: 2.385721,
+            b: 1.603215,
+        },
+    };
+
+    pub const COPPER: ComplexIor = ComplexIor {
+        eta: Color {
+            r: 0.200438,
+            g: 0.924033,
+            b: 1.102212,
+        },
+        k: Color {
+            r: 3.912949,
+            g: 2.452848,
+            b: 2.142188,
+        },
+    };
+
+    pub const ALUMINIUM: ComplexIor = ComplexIor {
+        eta: Color {
+            r: 1.65746,
+            g: 0.880369,
+            b: 0.521229,
+        },
+        k: Color {
+            r: 9.223869,
+            g: 6.269523,
+            b: 4.837001,
+        },
+    };
+
+    pub const SILVER: ComplexIor = ComplexIor {
+        eta: Color {
+            r: 0.155265,
+            g: 0.116723,
+            b: 0.138342,
+        },
+        k: Color {
+            r: 4.828181,
+            g: 3.122249,
+            b: 2.146961,
+        },
+    };
+
+    pub const PRESETS: [(&'static str, ComplexIor); 4] = [
+        ("gold", Self::GOLD),
+        ("copper", Self::COPPER),
+        ("aluminium", Self::ALUMINIUM),
+        ("silver", Self::SILVER),
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::PRESETS
+            .iter()
+            .find(|(preset, _)| *preset == name)
+            .map(|(_, ior)| *ior)
+    }
+
+    pub fn name(&self) -> Option<&'static str> {
+        Self::PRESETS
+            .iter()
+            .find(|(_, ior)| ior == self)
+            .map(|(name, _)| *name)
+    }
+
+    /// Fraction of light reflected at an angle with cosine `cos_i` to the normal.
+    pub fn fresnel(&self, cos_i: Number) -> Color {
+        color(
+            fresnel_conductor(cos_i, self.eta.r, self.k.r),
+            fresnel_conductor(cos_i, self.eta.g, self.k.g),
+            fresnel_conductor(cos_i, self.eta.b, self.k.b),
+        )
+    }
+
+    /// `fresnel` at `wavelengths`. Only the RGB values are known, so the index is upsampled
+    /// from them like a reflectance, which keeps the color of the metal but not the finer
+    /// detail of its spectrum.
+    pub fn fresnel_spectral(&self, cos_i: Number, wavelengths: &Wavelengths) -> Spectrum {
+        let eta = Spectrum::from_rgb(self.eta, wavelengths);
+        let k = Spectrum::from_rgb(self.k, wavelengths);
+
+        let mut reflectance = Spectrum::splat(0.0);
+        for ((value, eta), k) in reflectance.0.iter_mut().zip(eta.0).zip(k.0) {
+            *value = fresnel_conductor(cos_i, eta, k);
+        }
+        reflectance
+    }
+}
+
+// Exact Fresnel reflectance of a conductor, coming from a medium with an index of 1.
+fn fresnel_conductor(cos_i: Number, eta: Number, k: Number) -> Number {
+    let cos_i = cos_i.clamp(0.0, 1.0);
+    let cos2 = cos_i * cos_i;
+    let sin2 = 1.0 - cos2;
+    let (eta2, k2) = (eta * eta, k * k);
+
+    let t0 = eta2 - k2 - sin2;
+    let a2b2 = (t0 * t0 + 4.0 * eta2 * k2).sqrt();
+    let a = ((a2b2 + t0) * 0.5).max(0.0).sqrt();
+
+    let t1 = a2b2 + cos2;
+    let t2 = 2.0 * a * cos_i;
+    let rs = (t1 - t2) / (t1 + t2);
+
+    let t3 = cos2 * a2b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+
+    (rs + rp) * 0.5
+}
+
+/// Fresnel reflectance of a dielectric, where `eta` is the ratio of the index on the
+/// transmitted side to the one on the incident side.
+pub fn fresnel_dielectric(cos_i: Number, eta: Number) -> Number {
+    let cos_i = cos_i.clamp(-1.0, 1.0);
+    let (cos_i, eta) = if cos_i < 0.0 {
+        (-cos_i, 1.0 / eta)
+    } else {
+        (cos_i, eta)
+    };
+
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+
+    let cos_t = (1.0 - sin2_t).max(0.0).sqrt();
+    let parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+
+    (parallel * parallel + perpendicular * perpendicular) * 0.5
+}
+
+/// Mirrors `wo` around `normal`, both pointing away from the surface.
+pub fn reflect(wo: &Vector3, normal: &Vector3) -> Vector3 {
+    -*wo + *normal * wo.dot(normal) * 2.0
+}
+
+/// Refracts `wo`, which lies on the same side as `normal`, into the other side. Returns `None`
+/// on total internal reflection.
+pub fn refract(wo: &Vector3, normal: &Vector3, eta: Number) -> Option<Vector3> {
+    let cos_i = wo.dot(normal);
+    let sin2_t = (1.0 - cos_i * cos_i).max(0.0) / (eta * eta);
+
+    if sin2_t >= 1.0 {
+        return None;
+    }
+
+    let cos_t = (1.0 - sin2_t).sqrt();
+    Some(-*wo / eta + *normal * (cos_i / eta - cos_t))
+}
+
+/// The microfacet normal that refracts `wo` into `wi` on the other side, where `eta` is the
+/// relative index across the surface. Also returns the denominator of the Jacobian between the
+/// two, which transmission lobes need for their density. `None` if that microfacet would face away
+/// from either direction.
+pub fn refraction_half_vector(
+    wo: &Vector3,
+    wi: &Vector3,
+    eta: Number,
+) -> Option<(Vector3, Number)> {
+    let sum = *wi * eta + *wo;
+    if sum.near_zero() {
+        return None;
+    }
+
+    let mut wm = sum.normalize();
+    if wm.z < 0.0 {
+        wm = -wm;
+    }
+
+    if wm.dot(wi) >= 0.0 || wm.dot(wo) <= 0.0 {
+        return None;
+    }
+
+    Some((wm, (wi.dot(&wm) + wo.dot(&wm) / eta).powi(2)))
+}
+
+/// The Trowbridge-Reitz (GGX) distribution of microfacet normals, with separate roughness along
+/// the tangent and the bitangent. Directions are in the local frame of the surface.
+#[derive(Copy, Clone)]
+pub struct TrowbridgeReitz {
+    alpha_x: Number,
+    alpha_y: Number,
+}
+
+impl TrowbridgeReitz {
+    /// Roughness is perceptual, the distribution's alpha is its square.
+    pub fn new(roughness_u: Number, roughness_v: Number) -> Self {
+        Self {
+            alpha_x: (roughness_u * roughness_u).max(1e-4),
+            alpha_y: (roughness_v * roughness_v).max(1e-4),
+        }
+    }
+
+    /// Below this the surface is treated as a perfect mirror, since the distribution becomes
+    /// too peaked to evaluate reliably.
+    pub fn is_smooth(&self) -> bool {
+        self.alpha_x.max(self.alpha_y) < 1e-3
+    }
+
+    pub fn d(&self, wm: &Vector3) -> Number {
+        let cos2 = wm.z * wm.z;
+        if cos2 < 1e-12 {
+            return 0.0;
+        }
+
+        let e = (wm.x * wm.x / (self.alpha_x * self.alpha_x)
+            + wm.y * wm.y / (self.alpha_y * self.alpha_y))
+            / cos2;
+
+        1.0 / (PI * self.alpha_x * self.alpha_y * cos2 * cos2 * (1.0 + e) * (1.0 + e))
+    }
+
+    fn lambda(&self, w: &Vector3) -> Number {
+        let cos2 = w.z * w.z;
+        if cos2 < 1e-12 {
+            return 0.0;
+        }
+
+        let alpha2_tan2 = (w.x * w.x * self.alpha_x * self.alpha_x
+            + w.y * w.y * self.alpha_y * self.alpha_y)
+            / cos2;
+
+        ((1.0 + alpha2_tan2).sqrt() - 1.0) * 0.5
+    }
+
+    /// Fraction of microfacets visible from `w`.
+    pub fn g1(&self, w: &Vector3) -> Number {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    /// Fraction of microfacets visible from both `wo` and `wi`.
+    pub fn g(&self, wo: &Vector3, wi: &Vector3) -> Number {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// Density of the microfacet normals visible from `w`, which is what `sample_wm` draws from.
+    pub fn pdf(&self, w: &Vector3, wm: &Vector3) -> Number {
+        self.g1(w) / w.z.abs() * self.d(wm) * w.dot(wm).abs()
+    }
+
+    /// Samples a microfacet normal visible from `w`, after Heitz's "Sampling the GGX
+    /// Distribution of Visible Normals".
+    pub fn sample_wm(&self, w: &Vector3, (u, v): (Number, Number)) -> Vector3 {
+        // Stretch to the hemisphere configuration, where visible normals are easy to sample.
+        let mut wh = vector3(self.alpha_x * w.x, self.alpha_y * w.y, w.z).normalize();
+        if wh.z < 0.0 {
+            wh = -wh;
+        }
+
+        let t1 = if wh.z < 0.99999 {
+            vector3(0.0, 0.0, 1.0).cross(&wh).normalize()
+        } else {
+            vector3(1.0, 0.0, 0.0)
+        };
+        let t2 = wh.cross(&t1);
+
+        // A point on the unit disk, squashed towards the part of it that's visible from `w`.
+        let r = u.sqrt();
+        let phi = 2.0 * PI * v;
+        let (px, py) = (r * phi.cos(), r * phi.sin());
+        let h = (1.0 - px * px).max(0.0).sqrt();
+        let s = (1.0 + wh.z) * 0.5;
+        let py = (1.0 - s) * h + s * py;
+        let pz = (1.0 - px * px - py * py).max(0.0).sqrt();
+
+        let nh = t1 * px + t2 * py + wh * pz;
+        vector3(self.alpha_x * nh.x, self.alpha_y * nh.y, nh.z.max(1e-6)).normalize()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn distributions() -> [TrowbridgeReitz; 3] {
+        [
+            TrowbridgeReitz::new(0.5, 0.5),
+            TrowbridgeReitz::new(0.8, 0.8),
+            TrowbridgeReitz::new(0.4, 0.9),
+        ]
+    }
+
+    // Integrates `f` over the upper hemisphere, by the midpoint rule in the polar angle and the
+    // azimuth. Sums in f64 so that f32 builds don't lose the small terms.
+    #[allow(clippy::unnecessary_cast)]
+    fn integrate_hemisphere(f: impl Fn(&Vector3) -> Number) -> f64 {
+        let (thetas, phis) = (2000, 256);
+        let d_theta = 0.5 * std::f64::consts::PI / thetas as f64;
+        let d_phi = 2.0 * std::f64::consts::PI / phis as f64;
+        let mut total = 0.0;
+
+        for i in 0..thetas {
+            let theta = (i as f64 + 0.5) * d_theta;
+            for j in 0..phis {
+                let phi = (j as f64 + 0.5) * d_phi;
+                let w = vector3(
+                    (theta.sin() * phi.cos()) as Number,
+                    (theta.sin() * phi.sin()) as Number,
+                    theta.cos() as Number,
+                );
+                total += f(&w) as f64 * theta.sin() * d_theta * d_phi;
+            }
+        }
+
+        total
+    }
+
+    fn directions() -> [Vector3; 3] {
+        [
+            vector3(0.0, 0.0, 1.0),
+            vector3(0.6, 0.0, 0.8),
+            vector3(-0.3, 0.9, 0.2).normalize(),
+        ]
+    }
+
+    // Microfacets cover exactly the area of the surface below them.
+    #[test]
+    fn projected_microfacet_area_is_one() {
+        for distribution in distributions() {
+            let area = integrate_hemisphere(|wm| distribution.d(wm) * wm.z);
+            assert!((area - 1.0).abs() < 1e-2, "{}", area);
+        }
+    }
+
+    // The visible normals from any direction form a distribution, as long as Smith's masking
+    // term is consistent with the normals.
+    #[test]
+    fn visible_normals_integrate_to_one() {
+        for distribution in distributions() {
+            for w in directions() {
+                let total = integrate_hemisphere(|wm| {
+                    if w.dot(wm) > 0.0 {
+                        distribution.pdf(&w, wm)
+                    } else {
+                        0.0
+                    }
+                });
+                assert!((total - 1.0).abs() < 1e-2, "{:?}: {}", w, total);
+            }
+        }
+    }
+
+    #[test]
+    fn masking_is_a_fraction() {
+        for distribution in distributions() {
+            assert!((distribution.g1(&vector3(0.0, 0.0, 1.0)) - 1.0).abs() < 1e-6);
+
+            for wo in directions() {
+                for wi in directions() {
+                    let (g1_o, g1_i) = (distribution.g1(&wo), distribution.g1(&wi));
+                    let g = distribution.g(&wo, &wi);
+
+                    assert!(g1_o > 0.0 && g1_o <= 1.0);
+                    assert!(g > 0.0 && g <= g1_o.min(g1_i) + 1e-6);
+                    // Uncorrelated masking and shadowing would be the product, and height
+                    // correlation only ever adds to it.
+                    assert!(g >= g1_o * g1_i - 1e-6);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn sampled_normals_are_visible() {
+        for distribution in distributions() {
+            for w in directions() {
+                for i in 0..32 {
+                    for j in 0..32 {
+                        let uv = ((i as Number + 0.5) / 32.0, (j as Number + 0.5) / 32.0);
+                        let wm = distribution.sample_wm(&w, uv);
+
+                        assert!((wm.length() - 1.0).abs() < 1e-4);
+                        assert!(wm.z > 0.0 && w.dot(&wm) > -1e-4, "{:?} from {:?}", wm, w);
+                        assert!(distribution.pdf(&w, &wm) > 0.0);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn frames_are_orthonormal() {
+        for normal in [vector3(0.0, 1.0, 0.0), vector3(0.0, 0.0, 1.0)]
+            .into_iter()
+            .chain(directions())
+        {
+            let frame = Frame::new(normal);
+            let v = vector3(0.3, -0.5, 0.8);
+
+            assert!((frame.tangent.length() - 1.0).abs() < 1e-5);
+            assert!(frame.tangent.dot(&frame.normal).abs() < 1e-5);
+            assert!((frame.to_world(&frame.to_local(&v)) - v).length() < 1e-5);
+            assert!((frame.to_local(&normal) - vector3(0.0, 0.0, 1.0)).length() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn dielectric_fresnel_matches_the_closed_forms() {
+        let eta: Number = 1.5;
+        let normal = ((eta - 1.0) / (eta + 1.0)).powi(2);
+
+        assert!((fresnel_dielectric(1.0, eta) - normal).abs() < 1e-6);
+        assert!((fresnel_dielectric(-1.0, eta) - normal).abs() < 1e-6);
+        assert!((fresnel_dielectric(0.0, eta) - 1.0).abs() < 1e-6);
+        // Past the critical angle on the inside, everything reflects.
+        assert_eq!(fresnel_dielectric(-0.5, eta), 1.0);
+    }
+}
@@ -0,0 +1,292 @@
+use super::color::{color, Color};
+use super::hit::{Face, HitData};
+use super::material::{Material, ScatterResult};
+use super::microfacet::{
+    fresnel_dielectric, reflect, refract, refraction_half_vector, Frame, TrowbridgeReitz,
+};
+use super::ray::Ray;
+use super::sampler::Sampler;
+use super::texture::Texture;
+use super::vector::{vector3, Vector3};
+use super::Number;
+
+const PI: Number = std::f64::consts::PI as Number;
+const CLEARCOAT_ROUGHNESS: Number = 0.1;
+
+/// An uber-material in the spirit of the Disney and OpenPBR models. A diffuse base and a GGX
+/// specular layer get blended towards metal by `metallic` and towards glass by `transmission`,
+/// with an optional sheen and clearcoat on top. Every parameter is a texture, and scalar ones
+/// read its luminance.
+#[derive(Clone, PartialEq)]
+pub struct Principled {
+    pub base_color: Texture,
+    pub metallic: Texture,
+    pub roughness: Texture,
+    /// Strength of the dielectric reflection. At 0.5 it matches what `ior` predicts.
+    pub specular: Texture,
+    /// Tints the dielectric reflection towards the base color.
+    pub specular_tint: Texture,
+    /// Soft retro-reflection at grazing angles, as seen on cloth.
+    pub sheen: Texture,
+    pub clearcoat: Texture,
+    pub transmission: Texture,
+    pub ior: Texture,
+}
+
+impl Default for Principled {
+    fn default() -> Self {
+        Self {
+            base_color: Texture::scalar(0.8),
+            metallic: Texture::scalar(0.0),
+            roughness: Texture::scalar(0.5),
+            specular: Texture::scalar(0.5),
+            specular_tint: Texture::scalar(0.0),
+            sheen: Texture::scalar(0.0),
+            clearcoat: Texture::scalar(0.0),
+            transmission: Texture::scalar(0.0),
+            ior: Texture::scalar(1.5),
+        }
+    }
+}
+
+impl Material for Principled {
+    fn scatter(&self, ray: &Ray, hit_data: &HitData, sampler: &mut dyn Sampler) -> ScatterResult {
+        let bsdf = Bsdf::new(self, hit_data);
+        let frame = Frame::new(hit_data.normal);
+        let wo = frame.to_local(&-ray.direction.normalize());
+
+        if wo.z <= 0.0 {
+            return ScatterResult::Absorbed;
+        }
+
+        // One lobe gets sampled, but the weight accounts for all of them, so lobes that
+        // overlap don't add noise when the less suited one happens to be picked.
+        let mut choice = sampler.get_1d();
+        let sample = sampler.get_2d();
+        let mut wi = None;
+
+        for (lobe, probability) in bsdf.lobes() {
+            if choice < probability {
+                wi = bsdf.sample(lobe, &wo, sample);
+                break;
+            }
+            choice -= probability;
+        }
+
+        let wi = match wi {
+            Some(wi) => wi,
+            None => return ScatterResult::Absorbed,
+        };
+
+        let pdf = bsdf.pdf(&wo, &wi);
+        if pdf <= 0.0 {
+            return ScatterResult::Absorbed;
+        }
+
+        let attenuation = bsdf.eval(&wo, &wi) * (wi.z.abs() / pdf);
+        ScatterResult::new(ray, hit_data, frame.to_world(&wi), attenuation, Some(pdf))
+    }
+
+    fn eval(&self, hit_data: &HitData, wo: &Vector3, wi: &Vector3) -> Color {
+        let frame = Frame::new(hit_data.normal);
+        let wo = frame.to_local(wo);
+        if wo.z <= 0.0 {
+            return color(0.0, 0.0, 0.0);
+        }
+
+        Bsdf::new(self, hit_data).eval(&wo, &frame.to_local(wi))
+    }
+
+    fn pdf(&self, hit_data: &HitData, wo: &Vector3, wi: &Vector3) -> Number {
+        let frame = Frame::new(hit_data.normal);
+        let wo = frame.to_local(wo);
+        if wo.z <= 0.0 {
+            return 0.0;
+        }
+
+        Bsdf::new(self, hit_data).pdf(&wo, &frame.to_local(wi))
+    }
+
+    /// The base color, which is what the surface looks like without any lighting.
+    fn albedo(&self, uv: (Number, Number)) -> Color {
+        self.base_color.value(uv)
+    }
+
+    fn name(&self) -> &'static str {
+        "Principled"
+    }
+}
+
+#[derive(Copy, Clone)]
+enum Lobe {
+    Diffuse,
+    Specular,
+    Clearcoat,
+    Transmission,
+}
+
+// The parameters looked up at a hit, and the lobe weights they work out to.
+struct Bsdf {
+    base_color: Color,
+    /// Specular color at normal incidence, blended between dielectric and metal.
+    f0: Color,
+    diffuse: Number,
+    sheen: Number,
+    clearcoat: Number,
+    transmission: Number,
+    /// Relative index of refraction across the surface, in the direction the ray travels.
+    eta: Number,
+    specular_distribution: TrowbridgeReitz,
+    clearcoat_distribution: TrowbridgeReitz,
+}
+
+impl Bsdf {
+    fn new(material: &Principled, hit_data: &HitData) -> Self {
+        let uv = hit_data.uv;
+        let base_color = material.base_color.value(uv);
+        let metallic = material.metallic.scalar_value(uv).clamp(0.0, 1.0);
+        let roughness = material.roughness.scalar_value(uv).clamp(0.0, 1.0);
+        let specular = material.specular.scalar_value(uv).max(0.0);
+        let specular_tint = material.specular_tint.scalar_value(uv).clamp(0.0, 1.0);
+        let transmission = material.transmission.scalar_value(uv).clamp(0.0, 1.0);
+        let ior = material.ior.scalar_value(uv).max(1.0);
+
+        let luminance = base_color.luminance();
+        let tint = if luminance > 0.0 {
+            base_color / luminance
+        } else {
+            color(1.0, 1.0, 1.0)
+        };
+        let reflectance = ((ior - 1.0) / (ior + 1.0)).powi(2) * 2.0 * specular;
+        let dielectric_f0 =
+            (color(1.0, 1.0, 1.0) * (1.0 - specular_tint) + tint * specular_tint) * reflectance;
+
+        Self {
+            base_color,
+            f0: dielectric_f0 * (1.0 - metallic) + base_color * metallic,
+            diffuse: (1.0 - metallic) * (1.0 - transmission),
+            sheen: (1.0 - metallic) * material.sheen.scalar_value(uv).max(0.0),
+            clearcoat: 0.25 * material.clearcoat.scalar_value(uv).max(0.0),
+            transmission: (1.0 - metallic) * transmission,
+            eta: match hit_data.face {
+                Face::Outwards => ior,
+                Face::Inwards => 1.0 / ior,
+            },
+            specular_distribution: TrowbridgeReitz::new(roughness, roughness),
+            clearcoat_distribution: TrowbridgeReitz::new(CLEARCOAT_ROUGHNESS, CLEARCOAT_ROUGHNESS),
+        }
+    }
+
+    // Probability of sampling each lobe. The specular layer is always there.
+    fn lobes(&self) -> [(Lobe, Number); 4] {
+        let weights = [
+            (Lobe::Diffuse, self.diffuse),
+            (Lobe::Specular, 1.0),
+            (Lobe::Clearcoat, self.clearcoat),
+            (Lobe::Transmission, self.transmission),
+        ];
+        let total: Number = weights.iter().map(|(_, weight)| weight).sum();
+
+        weights.map(|(lobe, weight)| (lobe, weight / total))
+    }
+
+    fn sample(&self, lobe: Lobe, wo: &Vector3, sample: (Number, Number)) -> Option<Vector3> {
+        let wi = match lobe {
+            Lobe::Diffuse => {
+                let direction = vector3(0.0, 0.0, 1.0) + Vector3::on_unit_sphere(sample);
+                if direction.near_zero() {
+                    return None;
+                }
+                direction.normalize()
+            }
+            Lobe::Specular => reflect(wo, &self.specular_distribution.sample_wm(wo, sample)),
+            Lobe::Clearcoat => reflect(wo, &self.clearcoat_distribution.sample_wm(wo, sample)),
+            Lobe::Transmission => {
+                let wm = self.specular_distribution.sample_wm(wo, sample);
+                return refract(wo, &wm, self.eta).filter(|wi| wi.z < 0.0);
+            }
+        };
+
+        Some(wi).filter(|wi| wi.z > 0.0)
+    }
+
+    fn eval(&self, wo: &Vector3, wi: &Vector3) -> Color {
+        if wi.z > 0.0 {
+            let wm = (*wo + *wi).normalize();
+            let cos_d = wi.dot(&wm);
+            let geometry = 4.0 * wo.z * wi.z;
+
+            let diffuse = self.base_color * (self.diffuse / PI);
+            let sheen = color(1.0, 1.0, 1.0) * (self.sheen * schlick_weight(cos_d));
+
+            let distribution = &self.specular_distribution;
+            let specular =
+                schlick(self.f0, cos_d) * (distribution.d(&wm) * distribution.g(wo, wi) / geometry);
+
+            let distribution = &self.clearcoat_distribution;
+            let clearcoat = schlick(color(0.04, 0.04, 0.04), cos_d)
+                * (self.clearcoat * distribution.d(&wm) * distribution.g(wo, wi) / geometry);
+
+            diffuse + sheen + specular + clearcoat
+        } else {
+            let (wm, denominator) = match refraction_half_vector(wo, wi, self.eta) {
+                Some(half) => half,
+                None => return color(0.0, 0.0, 0.0),
+            };
+
+            let distribution = &self.specular_distribution;
+            let transmittance = 1.0 - fresnel_dielectric(wo.dot(&wm), self.eta);
+
+            self.base_color
+                * (self.transmission
+                    * transmittance
+                    * distribution.d(&wm)
+                    * distribution.g(wo, wi)
+                    * (wi.dot(&wm) * wo.dot(&wm) / (wi.z * wo.z * denominator)).abs()
+                    / (self.eta * self.eta))
+        }
+    }
+
+    fn pdf(&self, wo: &Vector3, wi: &Vector3) -> Number {
+        let mut pdf = 0.0;
+
+        for (lobe, probability) in self.lobes() {
+            if probability == 0.0 {
+                continue;
+            }
+
+            pdf += probability
+                * match lobe {
+                    Lobe::Diffuse if wi.z > 0.0 => wi.z / PI,
+                    Lobe::Specular | Lobe::Clearcoat if wi.z > 0.0 => {
+                        let distribution = match lobe {
+                            Lobe::Specular => &self.specular_distribution,
+                            _ => &self.clearcoat_distribution,
+                        };
+                        let wm = (*wo + *wi).normalize();
+                        distribution.pdf(wo, &wm) / (4.0 * wo.dot(&wm))
+                    }
+                    Lobe::Transmission if wi.z < 0.0 => {
+                        match refraction_half_vector(wo, wi, self.eta) {
+                            Some((wm, denominator)) => {
+                                self.specular_distribution.pdf(wo, &wm) * wi.dot(&wm).abs()
+                                    / denominator
+                            }
+                            None => 0.0,
+                        }
+                    }
+                    _ => 0.0,
+                };
+        }
+
+        pdf
+    }
+}
+
+fn schlick_weight(cos: Number) -> Number {
+    (1.0 - cos.clamp(0.0, 1.0)).powi(5)
+}
+
+fn schlick(f0: Color, cos: Number) -> Color {
+    f0 + (color(1.0, 1.0, 1.0) - f0) * schlick_weight(cos)
+}
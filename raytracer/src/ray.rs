@@ -1,9 +1,14 @@
-use crate::vector::Vector3;
-use crate::Number;
+use super::simd::{Float4, Vector3x4, LANES};
+use super::vector::Vector3;
+use super::Number;
 
+#[derive(Copy, Clone)]
 pub struct Ray {
     pub origin: Vector3,
     pub direction: Vector3,
+    /// When the ray was cast, somewhere in the camera's shutter interval. Moving objects are
+    /// hit where they were at that moment.
+    pub time: Number,
 }
 
 impl Ray {
@@ -11,3 +16,25 @@ impl Ray {
         self.origin + self.direction * t
     }
 }
+
+/// `LANES` rays traced together, laid out so they can be tested against an object at once.
+/// Objects that can't do that take the rays one by one instead. Only `headless --benchmark`
+/// traces packets so far, renders still trace every ray on its own.
+#[derive(Copy, Clone)]
+pub struct RayPacket {
+    pub rays: [Ray; LANES],
+    pub origin: Vector3x4,
+    pub direction: Vector3x4,
+    pub time: Float4,
+}
+
+impl RayPacket {
+    pub fn new(rays: [Ray; LANES]) -> Self {
+        Self {
+            rays,
+            origin: Vector3x4::new(rays.map(|ray| ray.origin)),
+            direction: Vector3x4::new(rays.map(|ray| ray.direction)),
+            time: Float4::new(rays.map(|ray| ray.time)),
+        }
+    }
+}
@@ -0,0 +1,768 @@
+use std::time::Instant;
+
+use super::camera::Camera;
+use super::color::{color, Color};
+use super::color_space::DisplayTransform;
+use super::denoise::{denoise, Features, Guide};
+use super::filter::Filter;
+use super::image::Image;
+use super::sampler::{Sampler, SamplerKind};
+use super::scene::Scene;
+use super::spectrum::Wavelengths;
+use super::stats::RenderStats;
+use super::vector::vector3;
+use super::Number;
+
+/// A rectangle of pixels, measured from the top left corner of the frame.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Crop {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Crop {
+    /// Shrinks the crop so it lies within a `width` by `height` frame.
+    pub fn clamp(&self, width: usize, height: usize) -> Self {
+        let x = self.x.min(width);
+        let y = self.y.min(height);
+
+        Self {
+            x,
+            y,
+            width: self.width.min(width - x),
+            height: self.height.min(height - y),
+        }
+    }
+
+    // Widens the crop by `margin` pixels on every side, without leaving the frame.
+    fn grow(&self, margin: usize, width: usize, height: usize) -> Self {
+        let x = self.x.saturating_sub(margin);
+        let y = self.y.saturating_sub(margin);
+
+        Self {
+            x,
+            y,
+            width: (self.x + self.width + margin).min(width) - x,
+            height: (self.y + self.height + margin).min(height) - y,
+        }
+    }
+}
+
+/// With adaptive sampling, `RenderSettings::samples` becomes the average number of samples per
+/// pixel. Every pixel first gets `min_samples`, after which the rest of the budget is handed out
+/// in batches of `min_samples` to the pixels whose relative error is still above `threshold`.
+#[derive(Copy, Clone, PartialEq)]
+pub struct Adaptive {
+    pub min_samples: usize,
+    pub max_samples: usize,
+    pub threshold: Number,
+}
+
+impl Default for Adaptive {
+    fn default() -> Self {
+        Self {
+            min_samples: 16,
+            max_samples: 4096,
+            threshold: 0.01,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub struct RenderSettings {
+    pub width: usize,
+    pub height: usize,
+    pub samples: usize,
+    pub max_depth: usize,
+    pub seed: u64,
+    pub crop: Option<Crop>,
+    pub adaptive: Option<Adaptive>,
+    pub sampler: SamplerKind,
+    pub filter: Filter,
+    /// Runs the denoiser over the finished image. Since it looks at neighbouring pixels,
+    /// denoised crops don't exactly match the same pixels of a full render.
+    pub denoise: bool,
+    /// Traces light at a few wavelengths per path instead of as RGB, which is slower but lets
+    /// dispersive glass split light into its colors.
+    pub spectral: bool,
+}
+
+impl RenderSettings {
+    /// The part of the frame that actually gets traced.
+    pub fn region(&self) -> Crop {
+        let frame = Crop {
+            x: 0,
+            y: 0,
+            width: self.width,
+            height: self.height,
+        };
+
+        self.crop
+            .map_or(frame, |crop| crop.clamp(self.width, self.height))
+    }
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            width: 400,
+            height: 225,
+            samples: 500,
+            max_depth: 5,
+            seed: 0,
+            crop: None,
+            adaptive: None,
+            sampler: SamplerKind::Sobol,
+            filter: Filter::default(),
+            denoise: false,
+            spectral: false,
+        }
+    }
+}
+
+pub struct RenderOutput {
+    pub image: Image,
+    /// Number of samples taken for each pixel of `image`.
+    pub samples: Vec<usize>,
+    pub stats: RenderStats,
+}
+
+impl RenderOutput {
+    /// Visualizes the samples spent per pixel, going from black through blue and red to yellow
+    /// for the pixel that got the most, once encoded with `display`.
+    pub fn heatmap(&self, display: &DisplayTransform) -> Image {
+        let max = self.samples.iter().copied().max().unwrap_or(0).max(1) as Number;
+        let stops = [
+            color(0.0, 0.0, 0.0),
+            color(0.0, 0.0, 1.0),
+            color(1.0, 0.0, 0.0),
+            color(1.0, 1.0, 0.0),
+        ];
+
+        let mut heatmap = Image::new(self.image.width, self.image.height);
+        for (pixel, &samples) in heatmap.pixels.iter_mut().zip(&self.samples) {
+            let t = samples as Number / max * (stops.len() - 1) as Number;
+            let stop = (t as usize).min(stops.len() - 2);
+            let t = t - stop as Number;
+            let stop = stops[stop] * (1.0 - t) + stops[stop + 1] * t;
+
+            // Images hold linear values, so undo the encoding that gets applied on output.
+            *pixel = display.decode(stop);
+        }
+
+        heatmap
+    }
+}
+
+// Weighted sums of the samples splatted into each pixel of the output region. The denoiser's
+// features get splatted with the same weights, so they line up with the image. The rays traced for
+// the samples get counted along the way.
+struct Film {
+    region: Crop,
+    frame_height: usize,
+    filter: Filter,
+    sums: Vec<Color>,
+    weights: Vec<Number>,
+    guide: Option<Guide>,
+    stats: RenderStats,
+}
+
+impl Film {
+    fn new(settings: &RenderSettings, region: Crop) -> Self {
+        let count = region.width * region.height;
+
+        Self {
+            region,
+            frame_height: settings.height,
+            filter: settings.filter,
+            sums: vec![color(0.0, 0.0, 0.0); count],
+            weights: vec![0.0; count],
+            guide: settings.denoise.then(|| Guide {
+                albedo: vec![color(0.0, 0.0, 0.0); count],
+                normal: vec![vector3(0.0, 0.0, 0.0); count],
+                depth: vec![0.0; count],
+                variance: Vec::new(),
+            }),
+            stats: RenderStats::default(),
+        }
+    }
+
+    // The sample was taken at `offset` within pixel `(i, j)`, with `j` counted from the bottom
+    // of the frame like the camera does. Distances to the surrounding pixel centers are measured
+    // from that pixel rather than the frame origin, which keeps them exact in large frames.
+    fn splat(
+        &mut self,
+        i: usize,
+        j: usize,
+        (du, dv): (Number, Number),
+        sample: Color,
+        features: Option<&Features>,
+    ) {
+        let radius = self.filter.radius;
+        let reach = (radius + 0.5).ceil() as isize;
+        let region = self.region;
+
+        // Only pixel centers within `[-radius, radius)` of the sample count, so that with a box
+        // filter every sample lands in exactly the pixel it was taken for.
+        let within = |offset: Number| -radius <= offset && offset < radius;
+
+        for dj in -reach..=reach {
+            let y = dv - 0.5 - dj as Number;
+            let row = self.frame_height as isize - 1 - (j as isize + dj) - region.y as isize;
+            if !within(y) || row < 0 || row >= region.height as isize {
+                continue;
+            }
+
+            for di in -reach..=reach {
+                let x = du - 0.5 - di as Number;
+                let column = i as isize + di - region.x as isize;
+                if !within(x) || column < 0 || column >= region.width as isize {
+                    continue;
+                }
+
+                let weight = self.filter.evaluate(x, y);
+                if weight != 0.0 {
+                    let index = row as usize * region.width + column as usize;
+                    self.sums[index] += sample * weight;
+                    self.weights[index] += weight;
+
+                    if let (Some(guide), Some(features)) = (&mut self.guide, features) {
+                        guide.albedo[index] += features.albedo * weight;
+                        guide.normal[index] += features.normal * weight;
+                        guide.depth[index] += features.depth * weight;
+                    }
+                }
+            }
+        }
+    }
+
+    fn finish(mut self) -> (Image, Option<Guide>) {
+        let mut image = Image::new(self.region.width, self.region.height);
+
+        for (index, &weight) in self.weights.iter().enumerate() {
+            if weight <= 0.0 {
+                continue;
+            }
+
+            image.pixels[index] = self.sums[index] / weight;
+
+            if let Some(guide) = &mut self.guide {
+                guide.albedo[index] /= weight;
+                guide.normal[index] /= weight;
+                guide.depth[index] /= weight;
+            }
+        }
+
+        (image, self.guide)
+    }
+}
+
+// Tracks how many samples one pixel took, along with a running variance of their luminance.
+struct Pixel {
+    i: usize,
+    j: usize,
+    count: usize,
+    mean: Number,
+    m2: Number,
+}
+
+impl Pixel {
+    fn new(i: usize, j: usize) -> Self {
+        Self {
+            i,
+            j,
+            count: 0,
+            mean: 0.0,
+            m2: 0.0,
+        }
+    }
+
+    fn sample(
+        &mut self,
+        scene: &Scene,
+        camera: &Camera,
+        settings: &RenderSettings,
+        sampler: &mut dyn Sampler,
+        film: &mut Film,
+        n: usize,
+    ) {
+        let (width, height) = (settings.width, settings.height);
+
+        for _ in 0..n {
+            sampler.start_pixel_sample(self.i, self.j, self.count);
+
+            let (du, dv) = sampler.get_2d();
+            let u = (self.i as Number + du) / (width - 1) as Number;
+            let v = (self.j as Number + dv) / (height - 1) as Number;
+            let lens_sample = sampler.get_2d();
+            let ray = camera.get_ray(u, v, lens_sample, sampler.get_1d());
+            let (sample, features) = match ray {
+                Some(ray) => (
+                    if settings.spectral {
+                        let mut wavelengths = Wavelengths::sample(sampler.get_1d());
+                        scene
+                            .ray_spectrum(
+                                &ray,
+                                &mut wavelengths,
+                                settings.max_depth,
+                                sampler,
+                                &mut film.stats,
+                            )
+                            .to_rgb(&wavelengths)
+                    } else {
+                        scene.ray_color(&ray, settings.max_depth, sampler, &mut film.stats)
+                    },
+                    settings
+                        .denoise
+                        .then(|| Features::new(scene, &ray, &mut film.stats)),
+                ),
+                None => (
+                    color(0.0, 0.0, 0.0),
+                    settings.denoise.then(Features::blocked),
+                ),
+            };
+
+            film.splat(self.i, self.j, (du, dv), sample, features.as_ref());
+            self.count += 1;
+
+            let luminance = sample.luminance();
+            let delta = luminance - self.mean;
+            self.mean += delta / self.count as Number;
+            self.m2 += delta * (luminance - self.mean);
+        }
+    }
+
+    // Standard error of the mean luminance, relative to the mean. Dark pixels are clamped so
+    // they don't keep sampling to chase an error that's invisible anyway.
+    fn error(&self) -> Number {
+        if self.count < 2 {
+            return Number::INFINITY;
+        }
+
+        self.variance().sqrt() / self.mean.max(0.01)
+    }
+
+    // Variance of the mean luminance. A single sample says nothing about the noise, so it's
+    // assumed to be as large as the luminance itself.
+    fn variance(&self) -> Number {
+        if self.count < 2 {
+            return self.mean * self.mean;
+        }
+
+        self.m2 / (self.count - 1) as Number / self.count as Number
+    }
+}
+
+/// Renders the scene, calling `progress` with the finished fraction of the work every now and
+/// then. The render is abandoned as soon as it returns `false`.
+///
+/// Only the pixels inside the crop window, plus a margin as wide as the filter reaches, are
+/// traced and the returned image has the size of the crop. Samples only depend on the seed, the
+/// pixel and the sample index, so a crop matches the same pixels of a full render.
+pub fn render(
+    scene: &Scene,
+    camera: &Camera,
+    settings: &RenderSettings,
+    mut progress: impl FnMut(f32) -> bool,
+) -> Option<RenderOutput> {
+    let start = Instant::now();
+    let region = settings.region();
+    let margin = (settings.filter.radius - 0.5).ceil().max(0.0) as usize;
+    let traced = region.grow(margin, settings.width, settings.height);
+
+    let mut sampler = settings.sampler.create(settings.seed, settings.samples);
+    let mut film = Film::new(settings, region);
+    let mut pixels: Vec<Pixel> = (0..traced.height)
+        .flat_map(|y| {
+            let j = settings.height - 1 - (traced.y + y);
+            (traced.x..traced.x + traced.width).map(move |i| Pixel::new(i, j))
+        })
+        .collect();
+
+    match settings.adaptive {
+        None => {
+            for (y, row) in pixels.chunks_mut(traced.width.max(1)).enumerate() {
+                for pixel in row {
+                    let n = settings.samples;
+                    pixel.sample(scene, camera, settings, &mut *sampler, &mut film, n);
+                }
+
+                if !progress((y + 1) as f32 / traced.height as f32) {
+                    return None;
+                }
+            }
+        }
+
+        Some(adaptive) => {
+            let budget = pixels.len() * settings.samples;
+            let mut spent = 0;
+            let mut first = true;
+
+            loop {
+                let active: Vec<usize> = (0..pixels.len())
+                    .filter(|&index| {
+                        let pixel = &pixels[index];
+                        first
+                            || (pixel.count < adaptive.max_samples
+                                && pixel.error() > adaptive.threshold)
+                    })
+                    .collect();
+
+                // The first pass always runs in full, later ones shrink to fit the budget.
+                let batch = if first {
+                    adaptive.min_samples
+                } else {
+                    let remaining = budget.saturating_sub(spent);
+                    adaptive.min_samples.min(remaining / active.len().max(1))
+                };
+
+                if active.is_empty() || batch == 0 {
+                    break;
+                }
+
+                for chunk in active.chunks(traced.width.max(1)) {
+                    for &index in chunk {
+                        let pixel = &mut pixels[index];
+                        let n = batch.min(adaptive.max_samples.saturating_sub(pixel.count));
+                        pixel.sample(scene, camera, settings, &mut *sampler, &mut film, n);
+                        spent += n;
+                    }
+
+                    if !progress((spent as f32 / budget as f32).min(1.0)) {
+                        return None;
+                    }
+                }
+
+                first = false;
+            }
+        }
+    }
+
+    let inside: Vec<&Pixel> = pixels
+        .iter()
+        .filter(|pixel| {
+            let y = settings.height - 1 - pixel.j;
+            (region.x..region.x + region.width).contains(&pixel.i)
+                && (region.y..region.y + region.height).contains(&y)
+        })
+        .collect();
+
+    let mut stats = film.stats;
+    let (mut image, guide) = film.finish();
+
+    if let Some(mut guide) = guide {
+        guide.variance = inside.iter().map(|pixel| pixel.variance()).collect();
+        image = denoise(&image, &guide);
+    }
+
+    stats.render_time = start.elapsed();
+
+    Some(RenderOutput {
+        image,
+        samples: inside.iter().map(|pixel| pixel.count).collect(),
+        stats,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::camera::{CameraConfig, Lens, Projection};
+    use super::super::dielectric::{Dielectric, Ior};
+    use super::super::filter::FilterKind;
+    use super::super::hit::Hit;
+    use super::super::hit::HitData;
+    use super::super::material::{Lambertian, Material, Metal, ScatterResult};
+    use super::super::ray::Ray;
+    use super::super::scene::Sky;
+    use super::super::sphere::Sphere;
+    use super::super::vector::Vector3;
+    use super::*;
+
+    fn camera() -> Camera {
+        Camera::new(CameraConfig {
+            position: vector3(0.0, 0.0, 0.0),
+            viewport_width: 2.0,
+            viewport_height: 2.0,
+            focal_length: 1.0,
+            target: None,
+            projection: Projection::Perspective,
+            lens: Lens::Pinhole,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+            stereo: None,
+        })
+    }
+
+    fn settings(filter: Filter) -> RenderSettings {
+        RenderSettings {
+            width: 12,
+            height: 12,
+            samples: 16,
+            filter,
+            ..RenderSettings::default()
+        }
+    }
+
+    // A sky of a single color, with nothing in front of it, renders as that color whatever the
+    // filter does with the samples, negative lobes included.
+    #[test]
+    fn filters_keep_a_constant_image_constant() {
+        let sky = color(0.2, 0.5, 0.8);
+        let scene = Scene {
+            objects: Vec::new(),
+            sky: Sky {
+                top: sky,
+                bottom: sky,
+            },
+            fog: None,
+        };
+        let crop = Crop {
+            x: 3,
+            y: 2,
+            width: 5,
+            height: 7,
+        };
+
+        for kind in FilterKind::ALL {
+            for crop in [None, Some(crop)] {
+                let settings = RenderSettings {
+                    crop,
+                    ..settings(Filter::new(kind))
+                };
+                let output = render(&scene, &camera(), &settings, |_| true).unwrap();
+
+                assert_eq!(output.image.width, settings.region().width);
+                for pixel in &output.image.pixels {
+                    let error = *pixel - sky;
+                    assert!(
+                        error.r.abs().max(error.g.abs()).max(error.b.abs()) < 1e-4,
+                        "{}: {:?}",
+                        kind.name(),
+                        pixel
+                    );
+                }
+            }
+        }
+    }
+
+    // Rays leave surfaces from just past the rounding error of their hit, rather than a fixed
+    // distance away, so a scene renders the same whatever units it's modelled in.
+    #[test]
+    fn scaling_the_scene_leaves_the_image_alone() {
+        let render_at = |scale: Number| {
+            let sphere = |center: (Number, Number, Number), radius, material| -> Box<dyn Hit> {
+                Box::new(Sphere {
+                    center: vector3(center.0, center.1, center.2) * scale,
+                    radius: radius * scale,
+                    material,
+                })
+            };
+            let scene = Scene {
+                objects: vec![
+                    sphere(
+                        (0.0, -100.5, -1.0),
+                        100.0,
+                        Box::new(Lambertian {
+                            albedo: color(0.8, 0.8, 0.0),
+                        }),
+                    ),
+                    sphere(
+                        (0.0, 0.0, -1.0),
+                        0.5,
+                        Box::new(Lambertian {
+                            albedo: color(0.7, 0.3, 0.3),
+                        }),
+                    ),
+                    sphere(
+                        (-1.0, 0.0, -1.0),
+                        0.5,
+                        Box::new(Dielectric {
+                            ior: Ior::Constant(1.5),
+                            roughness_u: 0.0,
+                            roughness_v: 0.0,
+                        }),
+                    ),
+                    sphere(
+                        (1.0, 0.0, -1.0),
+                        0.5,
+                        Box::new(Metal {
+                            albedo: color(0.8, 0.6, 0.2),
+                            fuzz: 0.0,
+                        }),
+                    ),
+                ],
+                sky: Sky {
+                    top: color(0.5, 0.7, 1.0),
+                    bottom: color(1.0, 1.0, 1.0),
+                },
+                fog: None,
+            };
+            let camera = Camera::new(CameraConfig {
+                position: vector3(0.0, 0.0, 1.0) * scale,
+                viewport_width: 3.0 * scale,
+                viewport_height: 2.0 * scale,
+                focal_length: scale,
+                target: None,
+                projection: Projection::Perspective,
+                lens: Lens::Pinhole,
+                shutter_open: 0.0,
+                shutter_close: 0.0,
+                stereo: None,
+            });
+            let settings = RenderSettings {
+                width: 24,
+                height: 16,
+                samples: 32,
+                sampler: SamplerKind::Independent,
+                ..RenderSettings::default()
+            };
+
+            render(&scene, &camera, &settings, |_| true).unwrap().image
+        };
+
+        let reference = render_at(1.0);
+        let brightness = |image: &Image| {
+            image.pixels.iter().map(Color::luminance).sum::<Number>() / image.pixels.len() as Number
+        };
+
+        for scale in [0.001, 10000.0] {
+            let image = render_at(scale);
+            let difference = reference
+                .pixels
+                .iter()
+                .zip(&image.pixels)
+                .map(|(a, b)| (a.luminance() - b.luminance()).abs())
+                .sum::<Number>()
+                / image.pixels.len() as Number;
+
+            let relative = (brightness(&image) / brightness(&reference) - 1.0).abs();
+            assert!(relative < 0.005, "{}: {}", scale, relative);
+            assert!(
+                difference < 0.01 * brightness(&reference),
+                "{}: {}",
+                scale,
+                difference
+            );
+        }
+    }
+
+    // Glows without reflecting anything, so every sample of it comes out the same.
+    #[derive(Copy, Clone, PartialEq)]
+    struct Glowing;
+
+    impl Material for Glowing {
+        fn scatter(
+            &self,
+            ray: &Ray,
+            hit_data: &HitData,
+            sampler: &mut dyn Sampler,
+        ) -> ScatterResult {
+            black().scatter(ray, hit_data, sampler)
+        }
+
+        fn eval(&self, hit_data: &HitData, wo: &Vector3, wi: &Vector3) -> Color {
+            black().eval(hit_data, wo, wi)
+        }
+
+        fn pdf(&self, hit_data: &HitData, wo: &Vector3, wi: &Vector3) -> Number {
+            black().pdf(hit_data, wo, wi)
+        }
+
+        fn emitted(&self, _ray: &Ray, _hit_data: &HitData) -> Color {
+            color(2.0, 2.0, 2.0)
+        }
+
+        fn name(&self) -> &'static str {
+            "Glowing"
+        }
+    }
+
+    fn black() -> Lambertian {
+        Lambertian {
+            albedo: color(0.0, 0.0, 0.0),
+        }
+    }
+
+    // The left half of the image looks at a glowing ball, the right half at a diffuse one lit
+    // by the sky and the glow, whose samples vary a lot.
+    #[test]
+    fn adaptive_sampling_spends_samples_on_noisy_pixels() {
+        let ball = |x: Number, material: Box<dyn Material>| -> Box<dyn Hit> {
+            Box::new(Sphere {
+                center: vector3(x, 0.0, -2.0),
+                radius: 2.0,
+                material,
+            })
+        };
+        let scene = Scene {
+            objects: vec![
+                ball(-2.0, Box::new(Glowing)),
+                ball(
+                    2.0,
+                    Box::new(Lambertian {
+                        albedo: color(0.5, 0.5, 0.5),
+                    }),
+                ),
+            ],
+            sky: Sky {
+                top: color(0.5, 0.7, 1.0),
+                bottom: color(1.0, 1.0, 1.0),
+            },
+            fog: None,
+        };
+        // A thin strip across the middle, which the two balls fill.
+        let camera = Camera::new(CameraConfig {
+            position: vector3(0.0, 0.0, 0.0),
+            viewport_width: 2.0,
+            viewport_height: 0.25,
+            focal_length: 1.0,
+            target: None,
+            projection: Projection::Perspective,
+            lens: Lens::Pinhole,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+            stereo: None,
+        });
+        let adaptive = Adaptive {
+            min_samples: 8,
+            max_samples: 128,
+            threshold: 0.01,
+        };
+
+        // Pixels are spread over `u` from `i / 7` to `(i + 1) / 7`, so column 3 straddles the
+        // middle.
+        let width = 8;
+        let counts = |samples: usize| {
+            let settings = RenderSettings {
+                width,
+                height: width,
+                samples,
+                adaptive: Some(adaptive),
+                ..settings(Filter::default())
+            };
+            let output = render(&scene, &camera, &settings, |_| true).unwrap();
+            let column = |range: std::ops::Range<usize>| -> Vec<usize> {
+                (0..width)
+                    .flat_map(|row| range.clone().map(move |column| row * width + column))
+                    .map(|index| output.samples[index])
+                    .collect()
+            };
+            (column(0..3), column(4..width), output.samples.clone())
+        };
+
+        // On a budget, the glowing pixels stop as soon as they can and leave the rest to the
+        // noisy ones.
+        let (flat, noisy, all) = counts(32);
+        assert!(flat.iter().all(|&count| count == adaptive.min_samples));
+        assert!(noisy.iter().all(|&count| count >= 4 * adaptive.min_samples));
+        assert!(all.iter().sum::<usize>() <= 32 * all.len());
+
+        // With plenty to spare, the noisy pixels keep going until they reach the cap.
+        let (flat, noisy, all) = counts(1000);
+        assert!(flat.iter().all(|&count| count == adaptive.min_samples));
+        assert!(noisy.iter().all(|&count| count == adaptive.max_samples));
+        assert!(all.iter().all(|&count| count <= adaptive.max_samples));
+    }
+}
@@ -0,0 +1,613 @@
+use std::sync::OnceLock;
+
+use super::Number;
+
+// Samplers hand out the random numbers of one pixel sample, one dimension at a time. The values
+// only depend on the seed, the pixel, the sample index and the dimension, so samplers keep no
+// state between samples and a pixel can be revisited later (as adaptive sampling does) without
+// changing what it would have seen.
+
+pub trait Sampler {
+    /// Starts sample `index` of pixel `(i, j)`, resetting the dimension to 0.
+    fn start_pixel_sample(&mut self, i: usize, j: usize, index: usize);
+    fn get_1d(&mut self) -> Number;
+    fn get_2d(&mut self) -> (Number, Number);
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum SamplerKind {
+    Independent,
+    Stratified,
+    Halton,
+    Sobol,
+    BlueNoise,
+}
+
+impl SamplerKind {
+    pub const ALL: [SamplerKind; 5] = [
+        SamplerKind::Independent,
+        SamplerKind::Stratified,
+        SamplerKind::Halton,
+        SamplerKind::Sobol,
+        SamplerKind::BlueNoise,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SamplerKind::Independent => "independent",
+            SamplerKind::Stratified => "stratified",
+            SamplerKind::Halton => "halton",
+            SamplerKind::Sobol => "sobol",
+            SamplerKind::BlueNoise => "blue-noise",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.name() == name)
+    }
+
+    /// `samples` is the number of samples each pixel is expected to take, which only the
+    /// stratified sampler needs to know up front.
+    pub fn create(&self, seed: u64, samples: usize) -> Box<dyn Sampler> {
+        let state = State {
+            seed,
+            pixel: 0,
+            index: 0,
+            dimension: 0,
+        };
+
+        match self {
+            SamplerKind::Independent => Box::new(IndependentSampler { state }),
+            SamplerKind::Stratified => Box::new(StratifiedSampler { state, samples }),
+            SamplerKind::Halton => Box::new(HaltonSampler { state }),
+            SamplerKind::Sobol => Box::new(SobolSampler { state }),
+            SamplerKind::BlueNoise => Box::new(BlueNoiseSampler { state, i: 0, j: 0 }),
+        }
+    }
+}
+
+// What every sampler tracks: a hash of the seed and the pixel, plus the current sample index
+// and dimension.
+struct State {
+    seed: u64,
+    pixel: u64,
+    index: u64,
+    dimension: u64,
+}
+
+impl State {
+    fn start(&mut self, i: usize, j: usize, index: usize) {
+        self.pixel = hash(&[self.seed, i as u64, j as u64]);
+        self.index = index as u64;
+        self.dimension = 0;
+    }
+
+    fn next_dimension(&mut self) -> u64 {
+        self.dimension += 1;
+        self.dimension - 1
+    }
+
+    fn random(&self, dimension: u64) -> Number {
+        to_unit(hash(&[self.pixel, self.index, dimension]))
+    }
+}
+
+/// Uncorrelated uniform random numbers, the baseline every other sampler should beat.
+pub struct IndependentSampler {
+    state: State,
+}
+
+impl Sampler for IndependentSampler {
+    fn start_pixel_sample(&mut self, i: usize, j: usize, index: usize) {
+        self.state.start(i, j, index);
+    }
+
+    fn get_1d(&mut self) -> Number {
+        let dimension = self.state.next_dimension();
+        self.state.random(dimension)
+    }
+
+    fn get_2d(&mut self) -> (Number, Number) {
+        (self.get_1d(), self.get_1d())
+    }
+}
+
+/// Jittered sampling: each dimension is split into one stratum per sample and every sample
+/// lands in its own, randomly permuted, stratum. 2D samples use a square grid, so for sample
+/// counts that aren't a square the ones that don't fit the grid fall back to independent samples.
+pub struct StratifiedSampler {
+    state: State,
+    samples: usize,
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_pixel_sample(&mut self, i: usize, j: usize, index: usize) {
+        self.state.start(i, j, index);
+    }
+
+    fn get_1d(&mut self) -> Number {
+        let dimension = self.state.next_dimension();
+        let index = self.state.index as usize;
+        let jitter = self.state.random(dimension);
+
+        if index >= self.samples {
+            return jitter;
+        }
+
+        let seed = hash(&[self.state.pixel, dimension]) as u32;
+        let stratum = permutation_element(index as u32, self.samples as u32, seed);
+        (stratum as Number + jitter) / self.samples as Number
+    }
+
+    fn get_2d(&mut self) -> (Number, Number) {
+        let dimension = self.state.next_dimension();
+        self.state.next_dimension();
+
+        let index = self.state.index as usize;
+        let side = (self.samples as f64).sqrt() as usize;
+        let jitter = (
+            self.state.random(dimension),
+            self.state.random(dimension + 1),
+        );
+
+        if index >= side * side {
+            return jitter;
+        }
+
+        let seed = hash(&[self.state.pixel, dimension]) as u32;
+        let cell = permutation_element(index as u32, (side * side) as u32, seed) as usize;
+        (
+            ((cell % side) as Number + jitter.0) / side as Number,
+            ((cell / side) as Number + jitter.1) / side as Number,
+        )
+    }
+}
+
+const PRIMES: [u64; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131,
+];
+
+/// The Halton sequence, using the radical inverse in the n-th prime base for dimension n. Every
+/// pixel shifts the sequence by its own random offset (a Cranley-Patterson rotation), so that
+/// neighbouring pixels don't share their error. Dimensions past the prime table are independent.
+pub struct HaltonSampler {
+    state: State,
+}
+
+impl Sampler for HaltonSampler {
+    fn start_pixel_sample(&mut self, i: usize, j: usize, index: usize) {
+        self.state.start(i, j, index);
+    }
+
+    fn get_1d(&mut self) -> Number {
+        let dimension = self.state.next_dimension();
+        let offset = self.state.random(dimension);
+
+        match PRIMES.get(dimension as usize) {
+            Some(&base) => wrap(radical_inverse(base, self.state.index) + offset),
+            None => to_unit(hash(&[self.state.pixel, self.state.index, dimension, 1])),
+        }
+    }
+
+    fn get_2d(&mut self) -> (Number, Number) {
+        (self.get_1d(), self.get_1d())
+    }
+}
+
+/// Owen scrambled Sobol points, following Burley's "Practical Hash-based Owen Scrambling". Each
+/// group of four dimensions is a 4D Sobol point with its own shuffled index and scrambling, so
+/// any number of dimensions can be drawn. 2D samples always start on an even dimension, which
+/// keeps their pairs well stratified.
+pub struct SobolSampler {
+    state: State,
+}
+
+impl Sampler for SobolSampler {
+    fn start_pixel_sample(&mut self, i: usize, j: usize, index: usize) {
+        self.state.start(i, j, index);
+    }
+
+    fn get_1d(&mut self) -> Number {
+        let dimension = self.state.next_dimension();
+        u32_to_unit(owen_sobol(self.state.pixel, self.state.index, dimension))
+    }
+
+    fn get_2d(&mut self) -> (Number, Number) {
+        if self.state.dimension % 2 == 1 {
+            self.state.dimension += 1;
+        }
+
+        (self.get_1d(), self.get_1d())
+    }
+}
+
+/// Blue-noise dithered sampling: every pixel walks the same scrambled Sobol sequence, shifted by
+/// offsets read from a blue noise texture. Neighbouring pixels then have very different offsets,
+/// which pushes the remaining error to high frequencies where it's much less visible.
+pub struct BlueNoiseSampler {
+    state: State,
+    i: usize,
+    j: usize,
+}
+
+impl Sampler for BlueNoiseSampler {
+    fn start_pixel_sample(&mut self, i: usize, j: usize, index: usize) {
+        self.state.start(i, j, index);
+        self.i = i;
+        self.j = j;
+    }
+
+    fn get_1d(&mut self) -> Number {
+        let dimension = self.state.next_dimension();
+
+        // Every dimension reads the texture with a different toroidal shift, so the offsets of
+        // different dimensions aren't correlated.
+        let shift = hash(&[self.state.seed, dimension]) as usize;
+        let x = (self.i + shift) % BLUE_NOISE_SIZE;
+        let y = (self.j + (shift >> 32)) % BLUE_NOISE_SIZE;
+        let offset = blue_noise()[y * BLUE_NOISE_SIZE + x] as Number;
+
+        let sample = owen_sobol(self.state.seed, self.state.index, dimension);
+        wrap(u32_to_unit(sample) + offset)
+    }
+
+    fn get_2d(&mut self) -> (Number, Number) {
+        if self.state.dimension % 2 == 1 {
+            self.state.dimension += 1;
+        }
+
+        (self.get_1d(), self.get_1d())
+    }
+}
+
+const ONE_MINUS_EPSILON: Number = 1.0 - Number::EPSILON / 2.0;
+
+pub(crate) fn to_unit(x: u64) -> Number {
+    (((x >> 11) as f64 / (1u64 << 53) as f64) as Number).min(ONE_MINUS_EPSILON)
+}
+
+fn u32_to_unit(x: u32) -> Number {
+    ((x as f64 / (1u64 << 32) as f64) as Number).min(ONE_MINUS_EPSILON)
+}
+
+fn wrap(x: Number) -> Number {
+    if x >= 1.0 {
+        (x - 1.0).min(ONE_MINUS_EPSILON)
+    } else {
+        x
+    }
+}
+
+// The splitmix64 finalizer, folded over all values.
+pub(crate) fn hash(values: &[u64]) -> u64 {
+    values.iter().fold(0x9e3779b97f4a7c15, |h, &value| {
+        let mut x = h ^ value.wrapping_add(0x9e3779b97f4a7c15);
+        x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+        x ^ (x >> 31)
+    })
+}
+
+// Kensler's hash based permutation: element `i` of a random permutation of `0..len`.
+fn permutation_element(mut i: u32, len: u32, seed: u32) -> u32 {
+    let mut w = len - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+
+        if i < len {
+            break;
+        }
+    }
+
+    (i.wrapping_add(seed)) % len
+}
+
+fn radical_inverse(base: u64, mut index: u64) -> Number {
+    let inverse_base = 1.0 / base as f64;
+    let mut reversed = 0;
+    let mut inverse = 1.0;
+
+    while index > 0 {
+        let next = index / base;
+        reversed = reversed * base + (index - next * base);
+        inverse *= inverse_base;
+        index = next;
+    }
+
+    ((reversed as f64 * inverse) as Number).min(ONE_MINUS_EPSILON)
+}
+
+// Direction numbers for the first four Sobol dimensions, from the primitive polynomials and
+// initial values of Joe and Kuo.
+const SOBOL_DIRECTIONS: [[u32; 32]; 4] = [
+    sobol_directions(0, 0, &[1]),
+    sobol_directions(1, 0, &[1]),
+    sobol_directions(2, 1, &[1, 3]),
+    sobol_directions(3, 1, &[1, 3, 1]),
+];
+
+const fn sobol_directions(degree: usize, a: u32, m: &[u32]) -> [u32; 32] {
+    let mut v = [0; 32];
+    let mut i = 0;
+
+    while i < 32 {
+        if degree == 0 {
+            v[i] = 1 << (31 - i);
+        } else if i < degree {
+            v[i] = m[i] << (31 - i);
+        } else {
+            v[i] = v[i - degree] ^ (v[i - degree] >> degree);
+            let mut k = 1;
+            while k < degree {
+                v[i] ^= ((a >> (degree - 1 - k)) & 1) * v[i - k];
+                k += 1;
+            }
+        }
+        i += 1;
+    }
+
+    v
+}
+
+fn sobol(mut index: u32, dimension: usize) -> u32 {
+    let mut x = 0;
+    let mut bit = 0;
+
+    while index != 0 {
+        if index & 1 != 0 {
+            x ^= SOBOL_DIRECTIONS[dimension][bit];
+        }
+        index >>= 1;
+        bit += 1;
+    }
+
+    x
+}
+
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x
+}
+
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+fn owen_sobol(seed: u64, index: u64, dimension: u64) -> u32 {
+    let group_seed = hash(&[seed, dimension / 4]) as u32;
+    let index = nested_uniform_scramble(index as u32, group_seed);
+    let dimension_seed = hash(&[group_seed as u64, dimension]) as u32;
+    nested_uniform_scramble(sobol(index, (dimension % 4) as usize), dimension_seed)
+}
+
+const BLUE_NOISE_SIZE: usize = 64;
+
+fn blue_noise() -> &'static [f32] {
+    static TEXTURE: OnceLock<Vec<f32>> = OnceLock::new();
+    TEXTURE.get_or_init(void_and_cluster)
+}
+
+// Ulichney's void-and-cluster method. Points get ranked by repeatedly removing the one in the
+// tightest cluster or filling the largest void, with "tight" and "large" measured by a gaussian
+// energy that wraps around the edges so the texture tiles.
+fn void_and_cluster() -> Vec<f32> {
+    const SIGMA: f64 = 1.5;
+    const RADIUS: isize = 6;
+
+    let size = BLUE_NOISE_SIZE;
+    let count = size * size;
+
+    let mut kernel = Vec::new();
+    for dy in -RADIUS..=RADIUS {
+        for dx in -RADIUS..=RADIUS {
+            let weight = (-((dx * dx + dy * dy) as f64) / (2.0 * SIGMA * SIGMA)).exp();
+            kernel.push((dx, dy, weight));
+        }
+    }
+
+    let toggle = |points: &mut Vec<bool>, energy: &mut Vec<f64>, index: usize, on: bool| {
+        points[index] = on;
+        let (x, y) = ((index % size) as isize, (index / size) as isize);
+        for &(dx, dy, weight) in &kernel {
+            let nx = (x + dx).rem_euclid(size as isize) as usize;
+            let ny = (y + dy).rem_euclid(size as isize) as usize;
+            energy[ny * size + nx] += if on { weight } else { -weight };
+        }
+    };
+
+    let tightest_cluster = |points: &[bool], energy: &[f64]| {
+        (0..count)
+            .filter(|&index| points[index])
+            .max_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+            .unwrap()
+    };
+    let largest_void = |points: &[bool], energy: &[f64]| {
+        (0..count)
+            .filter(|&index| !points[index])
+            .min_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+            .unwrap()
+    };
+
+    let mut points = vec![false; count];
+    let mut energy = vec![0.0; count];
+
+    // A random initial pattern covering a tenth of the texture...
+    let initial = count / 10;
+    let mut placed = 0;
+    let mut n = 0;
+    while placed < initial {
+        let index = (hash(&[n]) % count as u64) as usize;
+        if !points[index] {
+            toggle(&mut points, &mut energy, index, true);
+            placed += 1;
+        }
+        n += 1;
+    }
+
+    // ...which gets spread out until moving the tightest point doesn't improve anything.
+    loop {
+        let cluster = tightest_cluster(&points, &energy);
+        toggle(&mut points, &mut energy, cluster, false);
+        let void = largest_void(&points, &energy);
+        toggle(&mut points, &mut energy, void, true);
+
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut rank = vec![0; count];
+
+    let (mut removing, mut removing_energy) = (points.clone(), energy.clone());
+    for r in (0..initial).rev() {
+        let cluster = tightest_cluster(&removing, &removing_energy);
+        toggle(&mut removing, &mut removing_energy, cluster, false);
+        rank[cluster] = r;
+    }
+
+    for r in initial..count {
+        let void = largest_void(&points, &energy);
+        toggle(&mut points, &mut energy, void, true);
+        rank[void] = r;
+    }
+
+    rank.into_iter()
+        .map(|r| (r as f32 + 0.5) / count as f32)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Takes `count` samples of pixel `(i, j)`, with `draw` picking the values of each.
+    fn samples<T>(
+        sampler: &mut dyn Sampler,
+        count: usize,
+        mut draw: impl FnMut(&mut dyn Sampler) -> T,
+    ) -> Vec<T> {
+        (0..count)
+            .map(|index| {
+                sampler.start_pixel_sample(3, 7, index);
+                draw(sampler)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn samples_stay_below_one() {
+        assert!(to_unit(u64::MAX) < 1.0);
+        assert!(u32_to_unit(u32::MAX) < 1.0);
+        assert!(wrap(ONE_MINUS_EPSILON + ONE_MINUS_EPSILON) < 1.0);
+        assert!(radical_inverse(2, u32::MAX as u64) < 1.0);
+
+        for kind in SamplerKind::ALL {
+            let mut sampler = kind.create(1, 64);
+            for values in samples(sampler.as_mut(), 64, |sampler| {
+                let (u, v) = sampler.get_2d();
+                [u, v, sampler.get_1d(), sampler.get_1d()]
+            }) {
+                for value in values {
+                    assert!((0.0..1.0).contains(&value), "{}: {}", kind.name(), value);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn stratified_samples_take_one_stratum_each() {
+        let mut sampler = SamplerKind::Stratified.create(1, 16);
+        let points = samples(sampler.as_mut(), 16, |sampler| {
+            (sampler.get_1d(), sampler.get_2d())
+        });
+
+        let mut strata = [false; 16];
+        let mut cells = [false; 16];
+        for (value, (u, v)) in points {
+            strata[(value * 16.0) as usize] = true;
+            cells[(v * 4.0) as usize * 4 + (u * 4.0) as usize] = true;
+        }
+
+        assert_eq!(strata, [true; 16]);
+        assert_eq!(cells, [true; 16]);
+    }
+
+    // The first eight points of each dimension in eighths, XORing together the direction
+    // numbers of the set bits of the index: 1/2, 1/4, 1/8 for the first dimension, 1/2, 3/4, 5/8
+    // for the second, 1/2, 3/4, 3/8 for the third and 1/2, 3/4, 1/8 for the fourth.
+    #[test]
+    fn sobol_starts_with_the_direction_numbers() {
+        let expected = [
+            [0, 4, 2, 6, 1, 5, 3, 7],
+            [0, 4, 6, 2, 5, 1, 3, 7],
+            [0, 4, 6, 2, 3, 7, 5, 1],
+            [0, 4, 6, 2, 1, 5, 7, 3],
+        ];
+
+        for (dimension, points) in expected.iter().enumerate() {
+            for (index, &eighths) in points.iter().enumerate() {
+                assert_eq!(sobol(index as u32, dimension), eighths << 29);
+            }
+        }
+    }
+
+    #[test]
+    fn owen_scrambling_depends_only_on_the_seed() {
+        let points = |seed| {
+            (0..64)
+                .map(|index| owen_sobol(seed, index, 5))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(points(1), points(1));
+        assert_ne!(points(1), points(2));
+
+        // Scrambling permutes the strata, so the points still take one stratum each.
+        let mut strata = points(1).into_iter().map(|x| x >> 26).collect::<Vec<_>>();
+        strata.sort();
+        assert_eq!(strata, (0..64).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn samplers_repeat_with_the_same_seed() {
+        for kind in SamplerKind::ALL {
+            let draw = |seed| {
+                let mut sampler = kind.create(seed, 16);
+                samples(sampler.as_mut(), 16, |sampler| {
+                    (sampler.get_1d(), sampler.get_2d())
+                })
+            };
+
+            assert_eq!(draw(1), draw(1), "{}", kind.name());
+            assert_ne!(draw(1), draw(2), "{}", kind.name());
+        }
+    }
+}
@@ -1,13 +1,17 @@
-use std::fmt::Display;
+use std::any::Any;
 
-use crate::color::{color, Color};
-use crate::hit::{Hit, HitData};
-use crate::material::ScatterResult;
-use crate::ray::Ray;
-use crate::Number;
-
-use rand::Rng;
+use super::color::{color, Color};
+use super::hit::{Hit, HitData};
+use super::material::ScatterResult;
+use super::medium::Fog;
+use super::ray::{Ray, RayPacket};
+use super::sampler::Sampler;
+use super::simd::{Float4, LANES};
+use super::spectrum::{Spectrum, Wavelengths};
+use super::stats::RenderStats;
+use super::Number;
 
+#[derive(Copy, Clone, PartialEq)]
 pub struct Sky {
     pub top: Color,
     pub bottom: Color,
@@ -17,23 +21,59 @@ impl Sky {
     pub fn get_color(&self, t: Number) -> Color {
         self.bottom * (1.0 - t) + self.top * t
     }
+
+    /// Color seen by a ray that escapes the scene.
+    pub fn get_ray_color(&self, ray: &Ray) -> Color {
+        let normalized = ray.direction.normalize();
+        self.get_color((normalized.y + 1.0) * 0.5)
+    }
 }
 
 pub struct Scene {
     pub objects: Vec<Box<dyn Hit>>,
     pub sky: Sky,
+    pub fog: Option<Fog>,
 }
 
 impl Hit for Scene {
-    fn hit(&self, ray: &Ray, t_min: Number, t_max: Number) -> Option<HitData> {
-        let mut best = None;
+    fn hit(&self, ray: &Ray, t_min: Number, t_max: Number) -> Option<HitData<'_>> {
+        self.closest(t_max, |object, t_max| object.hit(ray, t_min, t_max))
+            .0
+    }
+
+    fn hit_sampled(
+        &self,
+        ray: &Ray,
+        t_min: Number,
+        t_max: Number,
+        sampler: &mut dyn Sampler,
+    ) -> Option<HitData<'_>> {
+        self.closest(t_max, |object, t_max| {
+            object.hit_sampled(ray, t_min, t_max, sampler)
+        })
+        .0
+    }
+
+    fn hit_packet(
+        &self,
+        packet: &RayPacket,
+        t_min: Number,
+        t_max: Float4,
+    ) -> [Option<HitData<'_>>; LANES] {
+        let mut best = [None; LANES];
         let mut best_distance = t_max;
 
         for object in &self.objects {
-            if let Some(hit_data) = object.hit(ray, t_min, best_distance) {
-                best = Some(hit_data);
-                best_distance = hit_data.t;
+            let hits = object.hit_packet(packet, t_min, best_distance);
+
+            let mut distances = best_distance.to_array();
+            for ((best, distance), hit) in best.iter_mut().zip(&mut distances).zip(hits) {
+                if let Some(hit_data) = hit {
+                    *best = Some(hit_data);
+                    *distance = hit_data.t;
+                }
             }
+            best_distance = Float4::new(distances);
         }
 
         best
@@ -42,26 +82,333 @@ impl Hit for Scene {
     fn name(&self) -> &'static str {
         "Scene"
     }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
 }
 
+// Paths always get this many bounces before Russian roulette may end them.
+const ROULETTE_DEPTH: usize = 3;
+
 impl Scene {
-    pub fn ray_color(&self, ray: &Ray, depth: usize, rand: &mut impl Rng) -> Color {
-        if depth <= 0 {
-            return color(0.0, 0.0, 0.0);
+    /// Asks `hit` for each object's hit before `t_max`, or before the closest one found so far.
+    /// Returns the closest along with how many objects were asked.
+    pub(crate) fn closest<'a>(
+        &'a self,
+        t_max: Number,
+        mut hit: impl FnMut(&'a dyn Hit, Number) -> Option<HitData<'a>>,
+    ) -> (Option<HitData<'a>>, u64) {
+        let mut best = None;
+        let mut best_distance = t_max;
+        let mut tests = 0;
+
+        for object in &self.objects {
+            tests += 1;
+            if let Some(hit_data) = hit(object.as_ref(), best_distance) {
+                best = Some(hit_data);
+                best_distance = hit_data.t;
+            }
         }
 
-        if let Some(hit_data) = self.hit(ray, 0.001, f32::INFINITY) {
-            match hit_data.material.scatter(ray, &hit_data, rand) {
-                ScatterResult::Absorbed => color(0.0, 0.0, 0.0),
+        (best, tests)
+    }
+
+    /// Traces a path of at most `max_depth` bounces. Past `ROULETTE_DEPTH`, paths survive each
+    /// bounce with a probability equal to their throughput and get weighted up when they do, so
+    /// dim paths end early without biasing the result. The rays it traces are counted in `stats`.
+    pub fn ray_color(
+        &self,
+        ray: &Ray,
+        max_depth: usize,
+        sampler: &mut dyn Sampler,
+        stats: &mut RenderStats,
+    ) -> Color {
+        let mut ray = *ray;
+        let mut throughput = color(1.0, 1.0, 1.0);
+        let mut radiance = color(0.0, 0.0, 0.0);
+
+        for depth in 0..max_depth {
+            let hit_data = match self.next_hit(&ray, depth, sampler, stats) {
+                Some(hit_data) => hit_data,
+                None => return radiance + throughput * self.sky.get_ray_color(&ray),
+            };
+
+            radiance += throughput * hit_data.material.emitted(&ray, &hit_data);
+
+            match hit_data.material.scatter(&ray, &hit_data, sampler) {
+                ScatterResult::Absorbed => break,
                 ScatterResult::Scattered {
                     attenuation,
                     scattered,
-                } => attenuation * self.ray_color(&scattered, depth - 1, rand),
+                    ..
+                } => {
+                    throughput *= attenuation;
+                    ray = scattered;
+                }
+            }
+
+            if depth + 1 >= ROULETTE_DEPTH {
+                let survival = throughput.r.max(throughput.g).max(throughput.b).min(1.0);
+                if sampler.get_1d() >= survival {
+                    break;
+                }
+                throughput /= survival;
+            }
+        }
+
+        radiance
+    }
+
+    /// `ray_color` for spectral renders, carrying the path's light at `wavelengths`. Materials
+    /// may drop all but the hero wavelength along the way.
+    pub fn ray_spectrum(
+        &self,
+        ray: &Ray,
+        wavelengths: &mut Wavelengths,
+        max_depth: usize,
+        sampler: &mut dyn Sampler,
+        stats: &mut RenderStats,
+    ) -> Spectrum {
+        let mut ray = *ray;
+        let mut throughput = Spectrum::splat(1.0);
+        let mut radiance = Spectrum::splat(0.0);
+
+        for depth in 0..max_depth {
+            let hit_data = match self.next_hit(&ray, depth, sampler, stats) {
+                Some(hit_data) => hit_data,
+                None => {
+                    let sky = Spectrum::from_illuminant(self.sky.get_ray_color(&ray), wavelengths);
+                    return radiance + throughput * sky;
+                }
+            };
+
+            radiance += throughput
+                * hit_data
+                    .material
+                    .emitted_spectral(&ray, &hit_data, wavelengths);
+
+            match hit_data
+                .material
+                .scatter_spectral(&ray, &hit_data, sampler, wavelengths)
+            {
+                ScatterResult::Absorbed => break,
+                ScatterResult::Scattered {
+                    attenuation,
+                    scattered,
+                    ..
+                } => {
+                    throughput *= attenuation;
+                    ray = scattered;
+                }
+            }
+
+            if depth + 1 >= ROULETTE_DEPTH {
+                let survival = throughput.max().min(1.0);
+                if sampler.get_1d() >= survival {
+                    break;
+                }
+                throughput /= survival;
             }
+        }
+
+        radiance
+    }
+
+    // The closest surface along `ray`, unless fog scatters it somewhere on the way there, or to
+    // the sky. The ray is counted as primary at `depth` 0.
+    fn next_hit(
+        &self,
+        ray: &Ray,
+        depth: usize,
+        sampler: &mut dyn Sampler,
+        stats: &mut RenderStats,
+    ) -> Option<HitData<'_>> {
+        if depth == 0 {
+            stats.primary_rays += 1;
         } else {
-            let normalized = ray.direction.normalize();
-            let t = (normalized.y + 1.0) * 0.5;
-            self.sky.get_color(t)
+            stats.secondary_rays += 1;
+        }
+        let (hit, tests) = self.closest(Number::INFINITY, |object, t_max| {
+            object.hit_sampled(ray, 0.0, t_max, sampler)
+        });
+        stats.primitive_tests += tests;
+
+        if let Some(fog) = &self.fog {
+            stats.primitive_tests += 1;
+            let t_max = hit.map_or(Number::INFINITY, |hit_data| hit_data.t);
+            if let Some(scattering) = fog.hit(ray, t_max, sampler.get_1d()) {
+                return Some(scattering);
+            }
+        }
+
+        hit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::denoise::Features;
+    use super::super::material::{Lambertian, Material};
+    use super::super::medium::HenyeyGreenstein;
+    use super::super::sampler::SamplerKind;
+    use super::super::sphere::Sphere;
+    use super::super::vector::{vector3, Vector3};
+    use super::*;
+
+    // A diffuse surface that also glows, so that a path bouncing around inside a sphere of it
+    // gathers `emission` at every bounce, scaled down by `albedo` each time.
+    #[derive(Copy, Clone, PartialEq)]
+    struct Glowing {
+        albedo: Number,
+        emission: Number,
+    }
+
+    impl Glowing {
+        fn lambertian(&self) -> Lambertian {
+            Lambertian {
+                albedo: color(self.albedo, self.albedo, self.albedo),
+            }
+        }
+    }
+
+    impl Material for Glowing {
+        fn scatter(
+            &self,
+            ray: &Ray,
+            hit_data: &HitData,
+            sampler: &mut dyn Sampler,
+        ) -> ScatterResult {
+            self.lambertian().scatter(ray, hit_data, sampler)
+        }
+
+        fn eval(&self, hit_data: &HitData, wo: &Vector3, wi: &Vector3) -> Color {
+            self.lambertian().eval(hit_data, wo, wi)
+        }
+
+        fn pdf(&self, hit_data: &HitData, wo: &Vector3, wi: &Vector3) -> Number {
+            self.lambertian().pdf(hit_data, wo, wi)
+        }
+
+        fn emitted(&self, _ray: &Ray, _hit_data: &HitData) -> Color {
+            color(self.emission, self.emission, self.emission)
+        }
+
+        fn name(&self) -> &'static str {
+            "Glowing"
+        }
+    }
+
+    // Traces `count` paths from the center of a glowing sphere and averages their radiance.
+    fn furnace(glowing: Glowing, max_depth: usize, count: usize) -> (Number, RenderStats) {
+        let scene = Scene {
+            objects: vec![Box::new(Sphere {
+                center: vector3(0.0, 0.0, 0.0),
+                radius: 1.0,
+                material: Box::new(glowing),
+            })],
+            sky: Sky {
+                top: color(0.0, 0.0, 0.0),
+                bottom: color(0.0, 0.0, 0.0),
+            },
+            fog: None,
+        };
+        let ray = Ray {
+            origin: vector3(0.0, 0.0, 0.0),
+            direction: vector3(0.0, 0.0, -1.0),
+            time: 0.0,
+        };
+
+        let mut sampler = SamplerKind::Independent.create(7, 1);
+        let mut stats = RenderStats::default();
+        let mut total = 0.0;
+        for i in 0..count {
+            sampler.start_pixel_sample(i, 0, 0);
+            total += scene
+                .ray_color(&ray, max_depth, sampler.as_mut(), &mut stats)
+                .g as f64;
         }
+
+        ((total / count as f64) as Number, stats)
+    }
+
+    // Each bounce adds `emission * albedo^n`, so untruncated paths see `emission / (1 - albedo)`.
+    // Russian roulette ends paths early at random but mustn't change that on average.
+    #[test]
+    fn russian_roulette_is_unbiased() {
+        let glowing = Glowing {
+            albedo: 0.7,
+            emission: 1.0,
+        };
+
+        // Too short for roulette to start, so every path sees the same two bounces.
+        let (short, _) = furnace(glowing, ROULETTE_DEPTH - 1, 100);
+        assert!((short - 1.7).abs() < 1e-4, "{}", short);
+
+        let count = 50000;
+        let (radiance, stats) = furnace(glowing, 1000, count);
+        let expected = 1.0 / (1.0 - 0.7);
+        assert!(
+            (radiance - expected).abs() < 0.03 * expected,
+            "{}",
+            radiance
+        );
+
+        // Without roulette, paths would run all 1000 bounces.
+        assert!(stats.average_path_length() < 10.0);
+    }
+
+    #[test]
+    fn every_object_asked_for_a_hit_is_counted_as_a_test() {
+        let ball = |x| -> Box<dyn Hit> {
+            Box::new(Sphere {
+                center: vector3(x, 0.0, -5.0),
+                radius: 1.0,
+                material: Box::new(Lambertian {
+                    albedo: color(0.5, 0.5, 0.5),
+                }),
+            })
+        };
+        let mut scene = Scene {
+            objects: vec![ball(-3.0), ball(0.0), ball(3.0)],
+            sky: Sky {
+                top: color(1.0, 1.0, 1.0),
+                bottom: color(1.0, 1.0, 1.0),
+            },
+            fog: None,
+        };
+        // Straight up, past all of them.
+        let ray = Ray {
+            origin: vector3(0.0, 0.0, 0.0),
+            direction: vector3(0.0, 1.0, 0.0),
+            time: 0.0,
+        };
+        let mut sampler = SamplerKind::Independent.create(7, 1);
+        sampler.start_pixel_sample(0, 0, 0);
+
+        let mut stats = RenderStats::default();
+        scene.ray_color(&ray, 5, sampler.as_mut(), &mut stats);
+        Features::new(&scene, &ray, &mut stats);
+        assert_eq!(stats.path_rays(), 1);
+        assert_eq!(stats.feature_rays, 1);
+        assert_eq!(stats.primitive_tests, 6);
+
+        // Only paths look for the fog, the denoiser's features ignore it.
+        scene.fog = Some(Fog {
+            density: 0.0,
+            height: 1.0,
+            phase: HenyeyGreenstein {
+                albedo: color(1.0, 1.0, 1.0),
+                anisotropy: 0.0,
+            },
+        });
+        let mut stats = RenderStats::default();
+        scene.ray_color(&ray, 5, sampler.as_mut(), &mut stats);
+        Features::new(&scene, &ray, &mut stats);
+        assert_eq!(stats.primitive_tests, 7);
     }
 }
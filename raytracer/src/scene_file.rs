@@ -0,0 +1,1311 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::SplitWhitespace;
+use std::sync::Arc;
+
+use super::animation::{Animation, Channel, Interpolation, Keyframe, Track};
+use super::camera::{Aperture, CameraConfig, Lens, LensElement, Projection, Stereo, StereoLayout};
+use super::color::{color, Color};
+use super::color_space::TransferFunction;
+use super::conductor::Conductor;
+use super::dielectric::{Dielectric, Ior};
+use super::hit::Hit;
+use super::material::{Lambertian, Material, Metal};
+use super::medium::{ConstantMedium, Fog, HenyeyGreenstein};
+use super::microfacet::ComplexIor;
+use super::principled::Principled;
+use super::scene::{Scene, Sky};
+use super::sphere::{MovingSphere, Sphere};
+use super::texture::Texture;
+use super::transform::{Instance, Transform};
+use super::vector::{vector3, Vector3};
+use super::volume::{GridVolume, VoxelGrid};
+use super::Number;
+
+// Plain text scene description, one statement per line. Values are always written in the same
+// order and prefixed by their name, so files stay readable and diffable:
+//
+//   camera position 0 0 0 viewport 3.5555556 2 focal_length 1 target 0 0 -1 shutter 0 1
+//   sky top 0.5 0.7 1 bottom 1 1 1
+//   fog density 0.05 height 1 albedo 1 1 1 anisotropy 0
+//   sphere center 0 0 -1 radius 0.5 lambertian albedo 1 0.3 0.3
+//   sphere center 1 0 -1 radius 0.5 metal albedo 0.8 0 0 fuzz 0.5
+//   sphere center 2 0 -1 radius 0.5 conductor gold roughness 0.3 0.3
+//   sphere center 3 0 -1 radius 0.5 conductor eta 0.2 0.9 1.1 k 3.9 2.5 2.1 roughness 0.5 0.1
+//   sphere center 4 0 -1 radius 0.5 dielectric ior 1.5 roughness 0 0
+//   sphere center 5 0 -1 radius 0.5 principled base_color checker 1 1 1 0 0 0 scale 8
+//     metallic 0 roughness image rough.png specular 0.5 specular_tint 0 sheen 0 clearcoat 1
+//     transmission 0 ior 1.5
+//   medium density 2 albedo 0.9 0.9 0.9 anisotropy 0.5 sphere center 6 0 -1 radius 0.5
+//   grid file smoke.vol min 6 -0.5 -3 max 7 0.5 -2 density_scale 10 temperature_scale 0
+//     emission_scale 0 albedo 0.9 0.9 0.9 anisotropy 0
+//   moving_sphere center 7 0 -1 to 7 0.5 -1 time 0 1 radius 0.5 lambertian albedo 1 1 1
+//   instance keyframe 0 translate 8 0 -1 rotate 0 0 0 scale 1 keyframe 1 translate 8 0 -1
+//     rotate 0 90 0 scale 0.5 sphere center 0 0 0 radius 0.5 lambertian albedo 1 1 1
+//
+// The principled sphere, the grid and the instance go on a single line. Every principled
+// parameter is a texture: either a constant, `checker <even> <odd> scale <n>` or `image <path>`,
+// with a color or a single number for each value depending on the parameter. Image paths are
+// relative to the scene file and can't contain spaces. 8 bit images are taken to be sRGB encoded
+// unless `transfer linear` or `transfer gamma <g>` follows their path. Media are bounded by a
+// sphere, given without a material. Grids are loaded relative to the scene file too, and `.raw`
+// grids need `resolution x y z channels n` right after their path.
+//
+// Colors are linear sRGB, the space the renderer works in.
+//
+// A dielectric's `ior` can also change with the wavelength, which spectral renders turn into
+// dispersion: `cauchy a <a> b <b>` or `sellmeier b <b1> <b2> <b3> c <c1> <c2> <c3>`, with
+// wavelengths in micrometres. BK7 glass, for instance, is `sellmeier b 1.03961212 0.231792344
+// 1.01046945 c 0.00600069867 0.0200179144 103.560653`.
+//
+// The camera can look at a `target` point instead of down -z. Its `projection` is optional and
+// defaults to `perspective`, the others being
+// `orthographic`, `fisheye fov <degrees>`, `equirectangular` and `cubemap`. A `lens` can follow,
+// either `thin radius <r> focus_distance <d>` or `compound file <path> film_diagonal <mm>
+// focus_distance <d>`, both followed by `blades <n> rotation <degrees> tilt <x> <y>`. Lens files
+// are relative to the scene file like images, and hold a surface per line, given the same way
+// as a `LensElement`. Then comes an optional `stereo side_by_side|over_under eye_separation <d>
+// convergence <d>` for a view per eye, and last the `shutter`, which defaults to a still frame at
+// time 0.
+//
+// Instances take any number of keyframes in order of time, followed by the object they place,
+// which can be any of the objects above or another instance. Keyframes can be preceded by how to
+// interpolate between them, `linear` by default or `catmull_rom`.
+//
+// Animations follow keyframes the same way, with frame `n` at time `n / fps`:
+//
+//   animation fps 24
+//   animate camera position catmull_rom keyframe 0 0 0 0 keyframe 5 0 1 -2
+//   animate camera target keyframe 0 0 0 -1
+//   animate camera fov keyframe 0 90 keyframe 5 40
+//   animate object 0 center keyframe 0 0 0 -1 keyframe 5 0 1 -1
+//   animate object 1 albedo keyframe 0 1 0 0 keyframe 5 0 0 1
+//   animate object 2 transform keyframe 0 translate 0 0 -1 rotate 0 0 0 scale 1 keyframe 5
+//     translate 0 0 -1 rotate 0 360 0 scale 1
+//
+// Objects are counted from 0 in the order they appear. Besides their `center`, or their
+// `transform` for instances, the parameters of their material can be animated by name, such as
+// `albedo`, `fuzz`, `roughness` or `ior`.
+//
+// Blank lines and lines starting with `#` are ignored.
+
+pub struct SceneFile {
+    pub scene: Scene,
+    pub camera: CameraConfig,
+    pub animation: Animation,
+}
+
+impl SceneFile {
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        Self::parse_in(
+            &fs::read_to_string(path)?,
+            path.parent().unwrap_or(Path::new("")),
+        )
+    }
+
+    /// Saves a scene whose relative paths start from `directory`, usually the one it was loaded
+    /// from. They get rewritten to start from the directory of the new file instead.
+    pub fn save(
+        path: impl AsRef<Path>,
+        directory: impl AsRef<Path>,
+        scene: &Scene,
+        camera: &CameraConfig,
+        animation: &Animation,
+    ) -> io::Result<()> {
+        let path = path.as_ref();
+        let paths = Paths {
+            from: directory.as_ref(),
+            to: path.parent().unwrap_or(Path::new("")),
+        };
+        fs::write(path, Self::write_in(scene, camera, animation, &paths)?)
+    }
+
+    /// Parses a scene, with image paths relative to the current directory.
+    pub fn parse(source: &str) -> io::Result<Self> {
+        Self::parse_in(source, Path::new(""))
+    }
+
+    fn parse_in(source: &str, directory: &Path) -> io::Result<Self> {
+        let mut camera = None;
+        let mut sky = None;
+        let mut fog = None;
+        let mut objects: Vec<Box<dyn Hit>> = Vec::new();
+        let mut animation = Animation::default();
+
+        for (index, text) in source.lines().enumerate() {
+            let text = text.trim();
+            if text.is_empty() || text.starts_with('#') {
+                continue;
+            }
+
+            let mut line = Line {
+                number: index + 1,
+                tokens: text.split_whitespace(),
+                directory,
+            };
+
+            match line.next()? {
+                "camera" => camera = Some(line.camera()?),
+                "sky" => {
+                    sky = Some(Sky {
+                        top: line.color("top")?,
+                        bottom: line.color("bottom")?,
+                    })
+                }
+                "fog" => {
+                    fog = Some(Fog {
+                        density: line.number("density")?,
+                        height: line.number("height")?,
+                        phase: line.phase()?,
+                    })
+                }
+                "animation" => animation.fps = line.number("fps")?,
+                "animate" => animation.channels.push(line.channel()?),
+                other => objects.push(line.object(other)?),
+            }
+
+            line.end()?;
+        }
+
+        let scene = Scene {
+            sky: sky.ok_or_else(|| invalid("missing sky statement".to_string()))?,
+            fog,
+            objects,
+        };
+        animation.check(&scene)?;
+
+        Ok(Self {
+            camera: camera.ok_or_else(|| invalid("missing camera statement".to_string()))?,
+            scene,
+            animation,
+        })
+    }
+
+    /// Writes a scene, with its paths as they are.
+    pub fn write(
+        scene: &Scene,
+        camera: &CameraConfig,
+        animation: &Animation,
+    ) -> io::Result<String> {
+        let here = Path::new("");
+        Self::write_in(
+            scene,
+            camera,
+            animation,
+            &Paths {
+                from: here,
+                to: here,
+            },
+        )
+    }
+
+    fn write_in(
+        scene: &Scene,
+        camera: &CameraConfig,
+        animation: &Animation,
+        paths: &Paths,
+    ) -> io::Result<String> {
+        let mut out = String::new();
+        let sky = &scene.sky;
+
+        write!(
+            out,
+            "camera position {} viewport {} {} focal_length {}",
+            format_vector(&camera.position),
+            camera.viewport_width,
+            camera.viewport_height,
+            camera.focal_length
+        )
+        .unwrap();
+        if let Some(target) = &camera.target {
+            write!(out, " target {}", format_vector(target)).unwrap();
+        }
+        match camera.projection {
+            Projection::Perspective => {}
+            Projection::Fisheye { fov } => write!(out, " projection fisheye fov {}", fov).unwrap(),
+            projection => write!(out, " projection {}", projection.name()).unwrap(),
+        }
+        match &camera.lens {
+            Lens::Pinhole => {}
+            Lens::Thin {
+                radius,
+                focus_distance,
+                aperture,
+                tilt,
+            } => write!(
+                out,
+                " lens thin radius {} focus_distance {} {} tilt {} {}",
+                radius,
+                focus_distance,
+                format_aperture(aperture),
+                tilt.0,
+                tilt.1
+            )
+            .unwrap(),
+            Lens::Compound {
+                path,
+                film_diagonal,
+                focus_distance,
+                aperture,
+                tilt,
+                ..
+            } => write!(
+                out,
+                " lens compound file {} film_diagonal {} focus_distance {} {} tilt {} {}",
+                paths.format(path)?,
+                film_diagonal,
+                focus_distance,
+                format_aperture(aperture),
+                tilt.0,
+                tilt.1
+            )
+            .unwrap(),
+        }
+        if let Some(stereo) = &camera.stereo {
+            let layout = match stereo.layout {
+                StereoLayout::SideBySide => "side_by_side",
+                StereoLayout::OverUnder => "over_under",
+            };
+            write!(
+                out,
+                " stereo {} eye_separation {} convergence {}",
+                layout, stereo.eye_separation, stereo.convergence
+            )
+            .unwrap();
+        }
+        if camera.shutter_open != camera.shutter_close {
+            write!(
+                out,
+                " shutter {} {}",
+                camera.shutter_open, camera.shutter_close
+            )
+            .unwrap();
+        }
+        writeln!(out).unwrap();
+        writeln!(
+            out,
+            "sky top {} bottom {}",
+            format_color(&sky.top),
+            format_color(&sky.bottom)
+        )
+        .unwrap();
+
+        if let Some(fog) = &scene.fog {
+            writeln!(
+                out,
+                "fog density {} height {} {}",
+                fog.density,
+                fog.height,
+                format_phase(&fog.phase)
+            )
+            .unwrap();
+        }
+
+        for object in &scene.objects {
+            writeln!(out, "{}", format_object(object.as_ref(), paths)?).unwrap();
+        }
+
+        if !animation.channels.is_empty() {
+            writeln!(out, "animation fps {}", animation.fps).unwrap();
+        }
+        for channel in &animation.channels {
+            writeln!(out, "{}", format_channel(channel)).unwrap();
+        }
+
+        Ok(out)
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// Where the relative paths of a scene start from, and where they have to start from in the file
+// it's written to.
+struct Paths<'a> {
+    from: &'a Path,
+    to: &'a Path,
+}
+
+impl Paths<'_> {
+    // Paths are split on whitespace when parsed, so any in them can't be written.
+    fn format(&self, path: &str) -> io::Result<String> {
+        let rebased = self.rebase(path)?;
+        if path.is_empty() || rebased.contains(char::is_whitespace) {
+            return Err(invalid(format!(
+                "can't save path `{}`, paths can't be empty or contain spaces",
+                rebased
+            )));
+        }
+
+        Ok(rebased)
+    }
+
+    fn rebase(&self, path: &str) -> io::Result<String> {
+        if self.from == self.to || Path::new(path).is_absolute() {
+            return Ok(path.to_string());
+        }
+
+        let directory = |path: &Path| {
+            fs::canonicalize(if path.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                path
+            })
+        };
+        let (from, to) = (directory(self.from)?, directory(self.to)?);
+        if from == to {
+            return Ok(path.to_string());
+        }
+
+        // Up out of the new directory as far as the two have in common, then down to the file.
+        // Directories without anything in common, such as on different drives, get the whole
+        // path instead.
+        let target = from.join(path);
+        let common = to
+            .components()
+            .zip(target.components())
+            .take_while(|(a, b)| a == b)
+            .count();
+        if common == 0 {
+            return Ok(target.display().to_string());
+        }
+
+        let mut rebased = PathBuf::new();
+        for _ in to.components().skip(common) {
+            rebased.push("..");
+        }
+        for component in target.components().skip(common) {
+            rebased.push(component);
+        }
+
+        Ok(rebased.display().to_string())
+    }
+}
+
+fn format_vector(v: &Vector3) -> String {
+    format!("{} {} {}", v.x, v.y, v.z)
+}
+
+fn format_color(c: &Color) -> String {
+    format!("{} {} {}", c.r, c.g, c.b)
+}
+
+fn format_transform(transform: &Transform) -> String {
+    format!(
+        "translate {} rotate {} scale {}",
+        format_vector(&transform.translation),
+        format_vector(&transform.rotation),
+        transform.scale
+    )
+}
+
+fn format_track<T>(track: &Track<T>, format_value: impl Fn(&T) -> String) -> String {
+    let mut out = String::new();
+    if track.interpolation != Interpolation::Linear {
+        write!(out, "{} ", track.interpolation.name()).unwrap();
+    }
+
+    let keyframes = track
+        .keyframes
+        .iter()
+        .map(|keyframe| {
+            format!(
+                "keyframe {} {}",
+                keyframe.time,
+                format_value(&keyframe.value)
+            )
+        })
+        .collect::<Vec<_>>();
+    out + &keyframes.join(" ")
+}
+
+fn format_channel(channel: &Channel) -> String {
+    match channel {
+        Channel::CameraPosition(track) => {
+            format!(
+                "animate camera position {}",
+                format_track(track, format_vector)
+            )
+        }
+        Channel::CameraTarget(track) => {
+            format!(
+                "animate camera target {}",
+                format_track(track, format_vector)
+            )
+        }
+        Channel::CameraFov(track) => format!(
+            "animate camera fov {}",
+            format_track(track, |fov| fov.to_string())
+        ),
+        Channel::Center { object, track } => format!(
+            "animate object {} center {}",
+            object,
+            format_track(track, format_vector)
+        ),
+        Channel::Transform { object, track } => format!(
+            "animate object {} transform {}",
+            object,
+            format_track(track, format_transform)
+        ),
+        Channel::MaterialColor {
+            object,
+            parameter,
+            track,
+        } => format!(
+            "animate object {} {} {}",
+            object,
+            parameter,
+            format_track(track, format_color)
+        ),
+        Channel::MaterialNumber {
+            object,
+            parameter,
+            track,
+        } => format!(
+            "animate object {} {} {}",
+            object,
+            parameter,
+            format_track(track, |value| value.to_string())
+        ),
+    }
+}
+
+fn format_aperture(aperture: &Aperture) -> String {
+    format!("blades {} rotation {}", aperture.blades, aperture.rotation)
+}
+
+fn format_object(object: &dyn Hit, paths: &Paths) -> io::Result<String> {
+    let any = object.as_any();
+
+    if let Some(sphere) = any.downcast_ref::<Sphere>() {
+        Ok(format!(
+            "sphere center {} radius {} {}",
+            format_vector(&sphere.center),
+            sphere.radius,
+            format_material(sphere.material.as_ref(), paths)?
+        ))
+    } else if let Some(sphere) = any.downcast_ref::<MovingSphere>() {
+        Ok(format!(
+            "moving_sphere center {} to {} time {} {} radius {} {}",
+            format_vector(&sphere.center0),
+            format_vector(&sphere.center1),
+            sphere.time0,
+            sphere.time1,
+            sphere.radius,
+            format_material(sphere.material.as_ref(), paths)?
+        ))
+    } else if let Some(instance) = any.downcast_ref::<Instance>() {
+        Ok(format!(
+            "instance {} {}",
+            format_track(&instance.motion, format_transform),
+            format_object(instance.object.as_ref(), paths)?
+        ))
+    } else if let Some(medium) = any.downcast_ref::<ConstantMedium>() {
+        match medium.boundary.as_any().downcast_ref::<Sphere>() {
+            Some(sphere) => Ok(format!(
+                "medium density {} {} sphere center {} radius {}",
+                medium.density,
+                format_phase(&medium.phase),
+                format_vector(&sphere.center),
+                sphere.radius
+            )),
+            None => Err(invalid(format!(
+                "can't save medium bounded by `{}`",
+                medium.boundary.name()
+            ))),
+        }
+    } else if let Some(volume) = any.downcast_ref::<GridVolume>() {
+        let grid = &volume.grid;
+        let shape = if is_raw(&volume.path) {
+            format!(
+                " resolution {} {} {} channels {}",
+                grid.resolution[0], grid.resolution[1], grid.resolution[2], grid.channels
+            )
+        } else {
+            String::new()
+        };
+
+        Ok(format!(
+            "grid file {}{} min {} max {} density_scale {} temperature_scale {} \
+             emission_scale {} {}",
+            paths.format(&volume.path)?,
+            shape,
+            format_vector(&volume.min),
+            format_vector(&volume.max),
+            volume.density_scale,
+            volume.temperature_scale,
+            volume.emission_scale,
+            format_phase(&volume.phase)
+        ))
+    } else {
+        Err(invalid(format!("can't save object `{}`", object.name())))
+    }
+}
+
+fn is_raw(path: &str) -> bool {
+    matches!(
+        Path::new(path).extension().and_then(|extension| extension.to_str()),
+        Some(extension) if extension.eq_ignore_ascii_case("raw")
+    )
+}
+
+fn format_phase(phase: &HenyeyGreenstein) -> String {
+    format!(
+        "albedo {} anisotropy {}",
+        format_color(&phase.albedo),
+        phase.anisotropy
+    )
+}
+
+fn format_material(material: &dyn Material, paths: &Paths) -> io::Result<String> {
+    let any = material.as_any();
+
+    if let Some(Lambertian { albedo }) = any.downcast_ref::<Lambertian>() {
+        Ok(format!("lambertian albedo {}", format_color(albedo)))
+    } else if let Some(Metal { albedo, fuzz }) = any.downcast_ref::<Metal>() {
+        Ok(format!(
+            "metal albedo {} fuzz {}",
+            format_color(albedo),
+            fuzz
+        ))
+    } else if let Some(conductor) = any.downcast_ref::<Conductor>() {
+        let ior = match conductor.ior.name() {
+            Some(name) => name.to_string(),
+            None => format!(
+                "eta {} k {}",
+                format_color(&conductor.ior.eta),
+                format_color(&conductor.ior.k)
+            ),
+        };
+        Ok(format!(
+            "conductor {} roughness {} {}",
+            ior, conductor.roughness_u, conductor.roughness_v
+        ))
+    } else if let Some(dielectric) = any.downcast_ref::<Dielectric>() {
+        Ok(format!(
+            "dielectric ior {} roughness {} {}",
+            format_ior(&dielectric.ior),
+            dielectric.roughness_u,
+            dielectric.roughness_v
+        ))
+    } else if let Some(principled) = any.downcast_ref::<Principled>() {
+        Ok(format!(
+            "principled base_color {} metallic {} roughness {} specular {} specular_tint {} \
+             sheen {} clearcoat {} transmission {} ior {}",
+            format_texture(&principled.base_color, format_color, paths)?,
+            format_texture(&principled.metallic, format_scalar, paths)?,
+            format_texture(&principled.roughness, format_scalar, paths)?,
+            format_texture(&principled.specular, format_scalar, paths)?,
+            format_texture(&principled.specular_tint, format_scalar, paths)?,
+            format_texture(&principled.sheen, format_scalar, paths)?,
+            format_texture(&principled.clearcoat, format_scalar, paths)?,
+            format_texture(&principled.transmission, format_scalar, paths)?,
+            format_texture(&principled.ior, format_scalar, paths)?,
+        ))
+    } else {
+        Err(invalid(format!(
+            "can't save material `{}`",
+            material.name()
+        )))
+    }
+}
+
+// Scalar textures are grey, so any channel holds the value.
+fn format_scalar(c: &Color) -> String {
+    c.r.to_string()
+}
+
+fn format_texture(
+    texture: &Texture,
+    format_value: fn(&Color) -> String,
+    paths: &Paths,
+) -> io::Result<String> {
+    Ok(match texture {
+        Texture::Constant(value) => format_value(value),
+        Texture::Checker { even, odd, scale } => format!(
+            "checker {} {} scale {}",
+            format_value(even),
+            format_value(odd),
+            scale
+        ),
+        // The path of an image that didn't load probably doesn't lead anywhere, so the file
+        // would fail to load as well.
+        Texture::Image {
+            path, image: None, ..
+        } => return Err(invalid(format!("image `{}` was never loaded", path))),
+        Texture::Image {
+            path,
+            transfer: TransferFunction::Srgb,
+            ..
+        } => format!("image {}", paths.format(path)?),
+        Texture::Image { path, transfer, .. } => format!(
+            "image {} transfer {}",
+            paths.format(path)?,
+            format_transfer(transfer)
+        ),
+    })
+}
+
+fn format_transfer(transfer: &TransferFunction) -> String {
+    match transfer {
+        TransferFunction::Linear => "linear".to_string(),
+        TransferFunction::Srgb => "srgb".to_string(),
+        TransferFunction::Gamma(gamma) => format!("gamma {}", gamma),
+    }
+}
+
+fn format_ior(ior: &Ior) -> String {
+    match ior {
+        Ior::Constant(ior) => ior.to_string(),
+        Ior::Cauchy { a, b } => format!("cauchy a {} b {}", a, b),
+        Ior::Sellmeier { b, c } => format!(
+            "sellmeier b {} {} {} c {} {} {}",
+            b[0], b[1], b[2], c[0], c[1], c[2]
+        ),
+    }
+}
+
+struct Line<'a> {
+    number: usize,
+    tokens: SplitWhitespace<'a>,
+    /// Where relative image paths start from.
+    directory: &'a Path,
+}
+
+impl<'a> Line<'a> {
+    fn error(&self, message: String) -> io::Error {
+        invalid(format!("line {}: {}", self.number, message))
+    }
+
+    fn next(&mut self) -> io::Result<&'a str> {
+        self.tokens
+            .next()
+            .ok_or_else(|| self.error("unexpected end of line".to_string()))
+    }
+
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.clone().next()
+    }
+
+    fn keyword(&mut self, keyword: &str) -> io::Result<()> {
+        match self.next()? {
+            token if token == keyword => Ok(()),
+            token => Err(self.error(format!("expected `{}`, found `{}`", keyword, token))),
+        }
+    }
+
+    fn number_value(&mut self) -> io::Result<Number> {
+        let token = self.next()?;
+        token
+            .parse()
+            .map_err(|_| self.error(format!("expected a number, found `{}`", token)))
+    }
+
+    fn number(&mut self, keyword: &str) -> io::Result<Number> {
+        self.keyword(keyword)?;
+        self.number_value()
+    }
+
+    fn vector(&mut self, keyword: &str) -> io::Result<Vector3> {
+        self.keyword(keyword)?;
+        self.vector_value()
+    }
+
+    fn vector_value(&mut self) -> io::Result<Vector3> {
+        Ok(vector3(
+            self.number_value()?,
+            self.number_value()?,
+            self.number_value()?,
+        ))
+    }
+
+    fn color(&mut self, keyword: &str) -> io::Result<Color> {
+        self.keyword(keyword)?;
+        self.color_value()
+    }
+
+    fn material(&mut self) -> io::Result<Box<dyn Material>> {
+        Ok(match self.next()? {
+            "lambertian" => Box::new(Lambertian {
+                albedo: self.color("albedo")?,
+            }),
+            "metal" => Box::new(Metal {
+                albedo: self.color("albedo")?,
+                fuzz: self.number("fuzz")?,
+            }),
+            "conductor" => Box::new(Conductor {
+                ior: self.complex_ior()?,
+                roughness_u: self.number("roughness")?,
+                roughness_v: self.number_value()?,
+            }),
+            "dielectric" => Box::new(Dielectric {
+                ior: self.ior()?,
+                roughness_u: self.number("roughness")?,
+                roughness_v: self.number_value()?,
+            }),
+            "principled" => Box::new(Principled {
+                base_color: self.texture("base_color", Self::color_value)?,
+                metallic: self.texture("metallic", Self::scalar_value)?,
+                roughness: self.texture("roughness", Self::scalar_value)?,
+                specular: self.texture("specular", Self::scalar_value)?,
+                specular_tint: self.texture("specular_tint", Self::scalar_value)?,
+                sheen: self.texture("sheen", Self::scalar_value)?,
+                clearcoat: self.texture("clearcoat", Self::scalar_value)?,
+                transmission: self.texture("transmission", Self::scalar_value)?,
+                ior: self.texture("ior", Self::scalar_value)?,
+            }),
+            other => return Err(self.error(format!("unknown material `{}`", other))),
+        })
+    }
+
+    fn camera(&mut self) -> io::Result<CameraConfig> {
+        let mut camera = CameraConfig {
+            position: self.vector("position")?,
+            viewport_width: self.number("viewport")?,
+            viewport_height: self.number_value()?,
+            focal_length: self.number("focal_length")?,
+            target: None,
+            projection: Projection::Perspective,
+            lens: Lens::Pinhole,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+            stereo: None,
+        };
+
+        if self.peek() == Some("target") {
+            camera.target = Some(self.vector("target")?);
+        }
+
+        if self.peek() == Some("projection") {
+            self.next()?;
+            camera.projection = match self.next()? {
+                "perspective" => Projection::Perspective,
+                "orthographic" => Projection::Orthographic,
+                "fisheye" => Projection::Fisheye {
+                    fov: self.number("fov")?,
+                },
+                "equirectangular" => Projection::Equirectangular,
+                "cubemap" => Projection::Cubemap,
+                other => return Err(self.error(format!("unknown projection `{}`", other))),
+            };
+        }
+
+        if self.peek() == Some("lens") {
+            self.next()?;
+            camera.lens = self.lens()?;
+        }
+
+        if self.peek() == Some("stereo") {
+            self.next()?;
+            camera.stereo = Some(Stereo {
+                layout: match self.next()? {
+                    "side_by_side" => StereoLayout::SideBySide,
+                    "over_under" => StereoLayout::OverUnder,
+                    other => return Err(self.error(format!("unknown stereo layout `{}`", other))),
+                },
+                eye_separation: self.number("eye_separation")?,
+                convergence: self.number("convergence")?,
+            });
+        }
+
+        if self.peek() == Some("shutter") {
+            camera.shutter_open = self.number("shutter")?;
+            camera.shutter_close = self.number_value()?;
+        }
+
+        Ok(camera)
+    }
+
+    fn lens(&mut self) -> io::Result<Lens> {
+        Ok(match self.next()? {
+            "pinhole" => Lens::Pinhole,
+            "thin" => Lens::Thin {
+                radius: self.number("radius")?,
+                focus_distance: self.number("focus_distance")?,
+                aperture: self.aperture()?,
+                tilt: (self.number("tilt")?, self.number_value()?),
+            },
+            "compound" => {
+                self.keyword("file")?;
+                let path = self.next()?;
+                let elements = LensElement::load(self.directory.join(path))
+                    .map_err(|e| self.error(format!("couldn't load `{}`: {}", path, e)))?;
+
+                Lens::Compound {
+                    path: path.to_string(),
+                    elements,
+                    film_diagonal: self.number("film_diagonal")?,
+                    focus_distance: self.number("focus_distance")?,
+                    aperture: self.aperture()?,
+                    tilt: (self.number("tilt")?, self.number_value()?),
+                }
+            }
+            other => return Err(self.error(format!("unknown lens `{}`", other))),
+        })
+    }
+
+    fn aperture(&mut self) -> io::Result<Aperture> {
+        let blades = self.number("blades")?;
+        if blades < 0.0 || blades.fract() != 0.0 {
+            return Err(self.error(format!("expected a count, found `{}`", blades)));
+        }
+
+        Ok(Aperture {
+            blades: blades as u32,
+            rotation: self.number("rotation")?,
+        })
+    }
+
+    fn object(&mut self, keyword: &str) -> io::Result<Box<dyn Hit>> {
+        Ok(match keyword {
+            "sphere" => Box::new(Sphere {
+                center: self.vector("center")?,
+                radius: self.number("radius")?,
+                material: self.material()?,
+            }),
+            "moving_sphere" => Box::new(MovingSphere {
+                center0: self.vector("center")?,
+                center1: self.vector("to")?,
+                time0: self.number("time")?,
+                time1: self.number_value()?,
+                radius: self.number("radius")?,
+                material: self.material()?,
+            }),
+            "instance" => Box::new(self.instance()?),
+            "grid" => Box::new(self.grid()?),
+            "medium" => {
+                let density = self.number("density")?;
+                let phase = self.phase()?;
+                self.keyword("sphere")?;
+
+                Box::new(ConstantMedium {
+                    boundary: Box::new(Sphere {
+                        center: self.vector("center")?,
+                        radius: self.number("radius")?,
+                        material: Box::new(phase),
+                    }),
+                    density,
+                    phase,
+                })
+            }
+            other => return Err(self.error(format!("unknown statement `{}`", other))),
+        })
+    }
+
+    fn instance(&mut self) -> io::Result<Instance> {
+        let motion = self.track(Self::transform_value)?;
+        let keyword = self.next()?;
+        Ok(Instance {
+            object: self.object(keyword)?,
+            motion,
+        })
+    }
+
+    fn transform_value(&mut self) -> io::Result<Transform> {
+        let translation = self.vector("translate")?;
+        let rotation = self.vector("rotate")?;
+        let scale = self.number("scale")?;
+        if !(scale > 0.0 && scale.is_finite()) {
+            return Err(self.error(format!("scale must be positive, found {}", scale)));
+        }
+
+        Ok(Transform {
+            translation,
+            rotation,
+            scale,
+        })
+    }
+
+    // An optional interpolation followed by at least one keyframe, with `value` reading what
+    // each one holds.
+    fn track<T>(&mut self, value: fn(&mut Self) -> io::Result<T>) -> io::Result<Track<T>> {
+        let interpolation = match self.peek().and_then(Interpolation::from_name) {
+            Some(interpolation) => {
+                self.next()?;
+                interpolation
+            }
+            None => Interpolation::Linear,
+        };
+
+        let mut keyframes: Vec<Keyframe<T>> = Vec::new();
+        while self.peek() == Some("keyframe") {
+            let time = self.number("keyframe")?;
+            if matches!(keyframes.last(), Some(last) if last.time >= time) {
+                return Err(self.error("keyframes must be in order of time".to_string()));
+            }
+
+            keyframes.push(Keyframe {
+                time,
+                value: value(self)?,
+            });
+        }
+
+        if keyframes.is_empty() {
+            return Err(self.error("expected at least one keyframe".to_string()));
+        }
+
+        Ok(Track {
+            interpolation,
+            keyframes,
+        })
+    }
+
+    fn channel(&mut self) -> io::Result<Channel> {
+        match self.next()? {
+            "camera" => Ok(match self.next()? {
+                "position" => Channel::CameraPosition(self.track(Self::vector_value)?),
+                "target" => Channel::CameraTarget(self.track(Self::vector_value)?),
+                "fov" => Channel::CameraFov(self.track(Self::number_value)?),
+                other => {
+                    return Err(self.error(format!("can't animate camera `{}`", other)));
+                }
+            }),
+            "object" => {
+                let object = self.count()?;
+                Ok(match self.next()? {
+                    "center" => Channel::Center {
+                        object,
+                        track: self.track(Self::vector_value)?,
+                    },
+                    "transform" => Channel::Transform {
+                        object,
+                        track: self.track(Self::transform_value)?,
+                    },
+                    parameter if Channel::COLOR_PARAMETERS.contains(&parameter) => {
+                        Channel::MaterialColor {
+                            object,
+                            parameter: parameter.to_string(),
+                            track: self.track(Self::color_value)?,
+                        }
+                    }
+                    parameter => Channel::MaterialNumber {
+                        object,
+                        parameter: parameter.to_string(),
+                        track: self.track(Self::number_value)?,
+                    },
+                })
+            }
+            other => Err(self.error(format!("can't animate `{}`", other))),
+        }
+    }
+
+    fn grid(&mut self) -> io::Result<GridVolume> {
+        self.keyword("file")?;
+        let path = self.next()?;
+        let full_path = self.directory.join(path);
+
+        let grid = if is_raw(path) {
+            self.keyword("resolution")?;
+            let resolution = [self.count()?, self.count()?, self.count()?];
+            self.keyword("channels")?;
+            VoxelGrid::load_raw(&full_path, resolution, self.count()?)
+        } else {
+            VoxelGrid::load_vol(&full_path).map(|(grid, _)| grid)
+        }
+        .map_err(|e| self.error(format!("couldn't load `{}`: {}", path, e)))?;
+
+        Ok(GridVolume {
+            path: path.to_string(),
+            grid: Arc::new(grid),
+            min: self.vector("min")?,
+            max: self.vector("max")?,
+            density_scale: self.number("density_scale")?,
+            temperature_scale: self.number("temperature_scale")?,
+            emission_scale: self.number("emission_scale")?,
+            phase: self.phase()?,
+        })
+    }
+
+    fn count(&mut self) -> io::Result<usize> {
+        let token = self.next()?;
+        token
+            .parse()
+            .map_err(|_| self.error(format!("expected a count, found `{}`", token)))
+    }
+
+    fn phase(&mut self) -> io::Result<HenyeyGreenstein> {
+        Ok(HenyeyGreenstein {
+            albedo: self.color("albedo")?,
+            anisotropy: self.number("anisotropy")?,
+        })
+    }
+
+    fn color_value(&mut self) -> io::Result<Color> {
+        Ok(color(
+            self.number_value()?,
+            self.number_value()?,
+            self.number_value()?,
+        ))
+    }
+
+    fn scalar_value(&mut self) -> io::Result<Color> {
+        let value = self.number_value()?;
+        Ok(color(value, value, value))
+    }
+
+    // A constant, a checker or an image, with `value` reading a single color or number.
+    fn texture(
+        &mut self,
+        keyword: &str,
+        value: fn(&mut Self) -> io::Result<Color>,
+    ) -> io::Result<Texture> {
+        self.keyword(keyword)?;
+
+        // Constants start right away with their value.
+        match self.peek() {
+            Some("checker") => {
+                self.next()?;
+                Ok(Texture::Checker {
+                    even: value(self)?,
+                    odd: value(self)?,
+                    scale: self.number("scale")?,
+                })
+            }
+            Some("image") => {
+                self.next()?;
+                let path = self.next()?;
+                let transfer = match self.peek() {
+                    Some("transfer") => {
+                        self.next()?;
+                        self.transfer()?
+                    }
+                    _ => TransferFunction::Srgb,
+                };
+                Texture::load(path, self.directory, transfer)
+                    .map_err(|e| self.error(format!("couldn't load `{}`: {}", path, e)))
+            }
+            _ => Ok(Texture::Constant(value(self)?)),
+        }
+    }
+
+    fn transfer(&mut self) -> io::Result<TransferFunction> {
+        match self.next()? {
+            "linear" => Ok(TransferFunction::Linear),
+            "srgb" => Ok(TransferFunction::Srgb),
+            "gamma" => Ok(TransferFunction::Gamma(self.number_value()?)),
+            other => Err(self.error(format!("unknown transfer function `{}`", other))),
+        }
+    }
+
+    // Either the name of a preset or explicit `eta` and `k` values.
+    fn complex_ior(&mut self) -> io::Result<ComplexIor> {
+        match self.next()? {
+            "eta" => Ok(ComplexIor {
+                eta: self.color_value()?,
+                k: self.color("k")?,
+            }),
+            name => ComplexIor::from_name(name)
+                .ok_or_else(|| self.error(format!("unknown conductor `{}`", name))),
+        }
+    }
+
+    // A single number, or the coefficients of a formula for glass that disperses light.
+    fn ior(&mut self) -> io::Result<Ior> {
+        self.keyword("ior")?;
+
+        match self.peek() {
+            Some("cauchy") => {
+                self.next()?;
+                Ok(Ior::Cauchy {
+                    a: self.number("a")?,
+                    b: self.number("b")?,
+                })
+            }
+            Some("sellmeier") => {
+                self.next()?;
+                let b = self.vector("b")?;
+                let c = self.vector("c")?;
+                Ok(Ior::Sellmeier {
+                    b: [b.x, b.y, b.z],
+                    c: [c.x, c.y, c.z],
+                })
+            }
+            _ => Ok(Ior::Constant(self.number_value()?)),
+        }
+    }
+
+    fn end(&mut self) -> io::Result<()> {
+        match self.tokens.next() {
+            Some(token) => Err(self.error(format!("unexpected `{}`", token))),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Every statement that doesn't need another file, written the way `write` writes it.
+    const SCENE: &str = "\
+camera position 0 0 0 viewport 3.5555556 2 focal_length 1 target 0 0 -1 projection fisheye fov 120 lens thin radius 0.1 focus_distance 2 blades 6 rotation 15 tilt 0 0 stereo side_by_side eye_separation 0.065 convergence 3 shutter 0 1
+sky top 0.5 0.7 1 bottom 1 1 1
+fog density 0.05 height 1 albedo 1 1 1 anisotropy 0
+sphere center 0 0 -1 radius 0.5 lambertian albedo 1 0.3 0.3
+sphere center 1 0 -1 radius 0.5 metal albedo 0.8 0 0 fuzz 0.5
+sphere center 2 0 -1 radius 0.5 conductor gold roughness 0.3 0.3
+sphere center 3 0 -1 radius 0.5 conductor eta 0.2 0.9 1.1 k 3.9 2.5 2.1 roughness 0.5 0.1
+sphere center 4 0 -1 radius 0.5 dielectric ior 1.5 roughness 0 0
+sphere center 4 1 -1 radius 0.5 dielectric ior cauchy a 1.5 b 0.004 roughness 0 0
+sphere center 5 0 -1 radius 0.5 principled base_color checker 1 1 1 0 0 0 scale 8 metallic 0 roughness 0.5 specular 0.5 specular_tint 0 sheen 0 clearcoat 1 transmission 0 ior 1.5
+medium density 2 albedo 0.9 0.9 0.9 anisotropy 0.5 sphere center 6 0 -1 radius 0.5
+moving_sphere center 7 0 -1 to 7 0.5 -1 time 0 1 radius 0.5 lambertian albedo 1 1 1
+instance keyframe 0 translate 8 0 -1 rotate 0 0 0 scale 1 keyframe 1 translate 8 0 -1 rotate 0 90 0 scale 0.5 sphere center 0 0 0 radius 0.5 lambertian albedo 1 1 1
+animation fps 24
+animate camera position catmull_rom keyframe 0 0 0 0 keyframe 5 0 1 -2
+animate camera target keyframe 0 0 0 -1
+animate camera fov keyframe 0 90 keyframe 5 40
+animate object 0 center keyframe 0 0 0 -1 keyframe 5 0 1 -1
+animate object 1 albedo keyframe 0 1 0 0 keyframe 5 0 0 1
+animate object 9 transform catmull_rom keyframe 0 translate 8 0 -1 rotate 0 0 0 scale 1 keyframe 5 translate 8 2 -1 rotate 0 360 0 scale 2
+";
+
+    #[test]
+    fn write_gives_back_what_was_parsed() {
+        let file = SceneFile::parse(SCENE).unwrap();
+        let written = SceneFile::write(&file.scene, &file.camera, &file.animation).unwrap();
+
+        assert_eq!(written, SCENE);
+    }
+
+    const CAMERA_AND_SKY: &str = "\
+camera position 0 0 0 viewport 2 2 focal_length 1 target 0 0 -1 projection perspective lens pinhole shutter 0 0
+sky top 0.5 0.7 1 bottom 1 1 1
+";
+
+    #[test]
+    fn instances_need_a_positive_scale() {
+        let instance = |scale: &str| {
+            SceneFile::parse(&format!(
+                "{}instance keyframe 0 translate 0 0 0 rotate 0 0 0 scale {} sphere center 0 0 0 radius 1 lambertian albedo 1 1 1",
+                CAMERA_AND_SKY, scale
+            ))
+        };
+
+        assert!(instance("0.5").is_ok());
+        for scale in ["0", "-1", "inf", "NaN"] {
+            assert!(instance(scale).is_err(), "scale {}", scale);
+        }
+    }
+
+    // A directory of its own for each precision, since their tests run side by side.
+    fn temp_dir(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!(
+            "scene_file_{}_{}_{}",
+            name,
+            std::any::type_name::<Number>(),
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    #[test]
+    fn saving_elsewhere_rebases_relative_paths() {
+        let root = temp_dir("rebase");
+        fs::create_dir_all(root.join("a/textures")).unwrap();
+        fs::create_dir_all(root.join("b/c")).unwrap();
+        fs::write(root.join("a/textures/t.ppm"), "P3 1 1 255\n255 255 255\n").unwrap();
+        fs::write(
+            root.join("a/scene.txt"),
+            CAMERA_AND_SKY.to_string()
+                + "sphere center 0 0 -1 radius 0.5 principled base_color image textures/t.ppm metallic 0 roughness 0.5 specular 0.5 specular_tint 0 sheen 0 clearcoat 0 transmission 0 ior 1.5\n",
+        )
+        .unwrap();
+
+        let file = SceneFile::load(root.join("a/scene.txt")).unwrap();
+        let saved = root.join("b/c/scene.txt");
+        SceneFile::save(
+            &saved,
+            root.join("a"),
+            &file.scene,
+            &file.camera,
+            &file.animation,
+        )
+        .unwrap();
+
+        assert!(fs::read_to_string(&saved)
+            .unwrap()
+            .contains("base_color image ../../a/textures/t.ppm "));
+        let reloaded = SceneFile::load(&saved).unwrap();
+        let written = |file: &SceneFile| {
+            SceneFile::write(&file.scene, &file.camera, &file.animation).unwrap()
+        };
+        assert_eq!(written(&reloaded).replace("../../a/", ""), written(&file));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn images_that_never_loaded_are_rejected_when_writing() {
+        let mut file = SceneFile::parse(
+            &(CAMERA_AND_SKY.to_string()
+                + "sphere center 0 0 -1 radius 0.5 lambertian albedo 1 1 1"),
+        )
+        .unwrap();
+        file.scene.objects[0]
+            .as_any_mut()
+            .downcast_mut::<Sphere>()
+            .unwrap()
+            .material = Box::new(Principled {
+            base_color: Texture::Image {
+                path: "missing.ppm".to_string(),
+                transfer: TransferFunction::Srgb,
+                image: None,
+            },
+            ..Principled::default()
+        });
+
+        let error = SceneFile::write(&file.scene, &file.camera, &file.animation).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn paths_with_spaces_are_rejected_when_saving() {
+        let root = temp_dir("spaces");
+        for directory in ["my_textures", "my textures"] {
+            fs::create_dir_all(root.join(directory)).unwrap();
+            fs::write(
+                root.join(directory).join("t.ppm"),
+                "P3 1 1 255\n255 255 255\n",
+            )
+            .unwrap();
+        }
+
+        // The parser can't read such a path, but one can still be typed into the editor.
+        let mut file = SceneFile::parse_in(
+            &(CAMERA_AND_SKY.to_string()
+                + "sphere center 0 0 -1 radius 0.5 principled base_color image my_textures/t.ppm metallic 0 roughness 0.5 specular 0.5 specular_tint 0 sheen 0 clearcoat 0 transmission 0 ior 1.5"),
+            &root,
+        )
+        .unwrap();
+        let sphere = file.scene.objects[0]
+            .as_any_mut()
+            .downcast_mut::<Sphere>()
+            .unwrap();
+        let principled = sphere
+            .material
+            .as_any_mut()
+            .downcast_mut::<Principled>()
+            .unwrap();
+        principled.base_color =
+            Texture::load("my textures/t.ppm", &root, TransferFunction::Srgb).unwrap();
+
+        let error = SceneFile::save(
+            root.join("scene.txt"),
+            &root,
+            &file.scene,
+            &file.camera,
+            &file.animation,
+        )
+        .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
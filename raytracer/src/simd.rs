@@ -0,0 +1,568 @@
+use std::ops::{Add, BitAnd, BitOr, Div, Mul, Neg, Not, Sub};
+
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+use super::vector::{vector3, Vector3};
+use super::Number;
+
+/// How many rays are traced together in a `RayPacket`.
+pub const LANES: usize = 4;
+
+// Four numbers kept in whatever registers the target has for them: SSE on x86-64, or AVX for
+// doubles when the build enables it. Other targets fall back to arrays, which the compiler is
+// still free to vectorize. Comparisons give masks, which hold every bit of a lane where they're
+// true.
+trait Lanes: Copy {
+    type Register: Copy;
+    type Mask: Copy;
+
+    fn splat(value: Self) -> Self::Register;
+    fn from_array(values: [Self; LANES]) -> Self::Register;
+    fn to_array(register: Self::Register) -> [Self; LANES];
+
+    fn add(a: Self::Register, b: Self::Register) -> Self::Register;
+    fn sub(a: Self::Register, b: Self::Register) -> Self::Register;
+    fn mul(a: Self::Register, b: Self::Register) -> Self::Register;
+    fn div(a: Self::Register, b: Self::Register) -> Self::Register;
+    fn min(a: Self::Register, b: Self::Register) -> Self::Register;
+    fn max(a: Self::Register, b: Self::Register) -> Self::Register;
+    fn sqrt(a: Self::Register) -> Self::Register;
+
+    fn lt(a: Self::Register, b: Self::Register) -> Self::Mask;
+    fn le(a: Self::Register, b: Self::Register) -> Self::Mask;
+    fn and(a: Self::Mask, b: Self::Mask) -> Self::Mask;
+    fn or(a: Self::Mask, b: Self::Mask) -> Self::Mask;
+    fn not(a: Self::Mask) -> Self::Mask;
+    /// One bit per lane, the first lane in the lowest bit.
+    fn bits(mask: Self::Mask) -> u8;
+    fn select(mask: Self::Mask, a: Self::Register, b: Self::Register) -> Self::Register;
+}
+
+// Intrinsics that don't touch memory became safe to call in later compilers, which would
+// otherwise complain about the `unsafe` blocks older ones need.
+#[cfg(target_arch = "x86_64")]
+#[allow(unused_unsafe)]
+impl Lanes for f32 {
+    type Register = __m128;
+    type Mask = __m128;
+
+    fn splat(value: Self) -> Self::Register {
+        unsafe { _mm_set1_ps(value) }
+    }
+
+    fn from_array(values: [Self; LANES]) -> Self::Register {
+        unsafe { _mm_set_ps(values[3], values[2], values[1], values[0]) }
+    }
+
+    fn to_array(register: Self::Register) -> [Self; LANES] {
+        unsafe { std::mem::transmute(register) }
+    }
+
+    fn add(a: Self::Register, b: Self::Register) -> Self::Register {
+        unsafe { _mm_add_ps(a, b) }
+    }
+
+    fn sub(a: Self::Register, b: Self::Register) -> Self::Register {
+        unsafe { _mm_sub_ps(a, b) }
+    }
+
+    fn mul(a: Self::Register, b: Self::Register) -> Self::Register {
+        unsafe { _mm_mul_ps(a, b) }
+    }
+
+    fn div(a: Self::Register, b: Self::Register) -> Self::Register {
+        unsafe { _mm_div_ps(a, b) }
+    }
+
+    fn min(a: Self::Register, b: Self::Register) -> Self::Register {
+        unsafe { _mm_min_ps(a, b) }
+    }
+
+    fn max(a: Self::Register, b: Self::Register) -> Self::Register {
+        unsafe { _mm_max_ps(a, b) }
+    }
+
+    fn sqrt(a: Self::Register) -> Self::Register {
+        unsafe { _mm_sqrt_ps(a) }
+    }
+
+    fn lt(a: Self::Register, b: Self::Register) -> Self::Mask {
+        unsafe { _mm_cmplt_ps(a, b) }
+    }
+
+    fn le(a: Self::Register, b: Self::Register) -> Self::Mask {
+        unsafe { _mm_cmple_ps(a, b) }
+    }
+
+    fn and(a: Self::Mask, b: Self::Mask) -> Self::Mask {
+        unsafe { _mm_and_ps(a, b) }
+    }
+
+    fn or(a: Self::Mask, b: Self::Mask) -> Self::Mask {
+        unsafe { _mm_or_ps(a, b) }
+    }
+
+    fn not(a: Self::Mask) -> Self::Mask {
+        unsafe { _mm_xor_ps(a, _mm_castsi128_ps(_mm_set1_epi32(-1))) }
+    }
+
+    fn bits(mask: Self::Mask) -> u8 {
+        unsafe { _mm_movemask_ps(mask) as u8 }
+    }
+
+    fn select(mask: Self::Mask, a: Self::Register, b: Self::Register) -> Self::Register {
+        unsafe { _mm_or_ps(_mm_and_ps(mask, a), _mm_andnot_ps(mask, b)) }
+    }
+}
+
+// Without AVX, doubles take two SSE registers.
+#[cfg(all(target_arch = "x86_64", not(target_feature = "avx")))]
+#[allow(unused_unsafe)]
+impl Lanes for f64 {
+    type Register = [__m128d; 2];
+    type Mask = [__m128d; 2];
+
+    fn splat(value: Self) -> Self::Register {
+        unsafe { [_mm_set1_pd(value); 2] }
+    }
+
+    fn from_array(values: [Self; LANES]) -> Self::Register {
+        unsafe {
+            [
+                _mm_set_pd(values[1], values[0]),
+                _mm_set_pd(values[3], values[2]),
+            ]
+        }
+    }
+
+    fn to_array(register: Self::Register) -> [Self; LANES] {
+        unsafe { std::mem::transmute(register) }
+    }
+
+    fn add(a: Self::Register, b: Self::Register) -> Self::Register {
+        unsafe { [_mm_add_pd(a[0], b[0]), _mm_add_pd(a[1], b[1])] }
+    }
+
+    fn sub(a: Self::Register, b: Self::Register) -> Self::Register {
+        unsafe { [_mm_sub_pd(a[0], b[0]), _mm_sub_pd(a[1], b[1])] }
+    }
+
+    fn mul(a: Self::Register, b: Self::Register) -> Self::Register {
+        unsafe { [_mm_mul_pd(a[0], b[0]), _mm_mul_pd(a[1], b[1])] }
+    }
+
+    fn div(a: Self::Register, b: Self::Register) -> Self::Register {
+        unsafe { [_mm_div_pd(a[0], b[0]), _mm_div_pd(a[1], b[1])] }
+    }
+
+    fn min(a: Self::Register, b: Self::Register) -> Self::Register {
+        unsafe { [_mm_min_pd(a[0], b[0]), _mm_min_pd(a[1], b[1])] }
+    }
+
+    fn max(a: Self::Register, b: Self::Register) -> Self::Register {
+        unsafe { [_mm_max_pd(a[0], b[0]), _mm_max_pd(a[1], b[1])] }
+    }
+
+    fn sqrt(a: Self::Register) -> Self::Register {
+        unsafe { [_mm_sqrt_pd(a[0]), _mm_sqrt_pd(a[1])] }
+    }
+
+    fn lt(a: Self::Register, b: Self::Register) -> Self::Mask {
+        unsafe { [_mm_cmplt_pd(a[0], b[0]), _mm_cmplt_pd(a[1], b[1])] }
+    }
+
+    fn le(a: Self::Register, b: Self::Register) -> Self::Mask {
+        unsafe { [_mm_cmple_pd(a[0], b[0]), _mm_cmple_pd(a[1], b[1])] }
+    }
+
+    fn and(a: Self::Mask, b: Self::Mask) -> Self::Mask {
+        unsafe { [_mm_and_pd(a[0], b[0]), _mm_and_pd(a[1], b[1])] }
+    }
+
+    fn or(a: Self::Mask, b: Self::Mask) -> Self::Mask {
+        unsafe { [_mm_or_pd(a[0], b[0]), _mm_or_pd(a[1], b[1])] }
+    }
+
+    fn not(a: Self::Mask) -> Self::Mask {
+        unsafe {
+            let ones = _mm_castsi128_pd(_mm_set1_epi32(-1));
+            [_mm_xor_pd(a[0], ones), _mm_xor_pd(a[1], ones)]
+        }
+    }
+
+    fn bits(mask: Self::Mask) -> u8 {
+        unsafe { (_mm_movemask_pd(mask[0]) | _mm_movemask_pd(mask[1]) << 2) as u8 }
+    }
+
+    fn select(mask: Self::Mask, a: Self::Register, b: Self::Register) -> Self::Register {
+        unsafe {
+            [
+                _mm_or_pd(_mm_and_pd(mask[0], a[0]), _mm_andnot_pd(mask[0], b[0])),
+                _mm_or_pd(_mm_and_pd(mask[1], a[1]), _mm_andnot_pd(mask[1], b[1])),
+            ]
+        }
+    }
+}
+
+#[cfg(all(target_arch = "x86_64", target_feature = "avx"))]
+#[allow(unused_unsafe)]
+impl Lanes for f64 {
+    type Register = __m256d;
+    type Mask = __m256d;
+
+    fn splat(value: Self) -> Self::Register {
+        unsafe { _mm256_set1_pd(value) }
+    }
+
+    fn from_array(values: [Self; LANES]) -> Self::Register {
+        unsafe { _mm256_set_pd(values[3], values[2], values[1], values[0]) }
+    }
+
+    fn to_array(register: Self::Register) -> [Self; LANES] {
+        unsafe { std::mem::transmute(register) }
+    }
+
+    fn add(a: Self::Register, b: Self::Register) -> Self::Register {
+        unsafe { _mm256_add_pd(a, b) }
+    }
+
+    fn sub(a: Self::Register, b: Self::Register) -> Self::Register {
+        unsafe { _mm256_sub_pd(a, b) }
+    }
+
+    fn mul(a: Self::Register, b: Self::Register) -> Self::Register {
+        unsafe { _mm256_mul_pd(a, b) }
+    }
+
+    fn div(a: Self::Register, b: Self::Register) -> Self::Register {
+        unsafe { _mm256_div_pd(a, b) }
+    }
+
+    fn min(a: Self::Register, b: Self::Register) -> Self::Register {
+        unsafe { _mm256_min_pd(a, b) }
+    }
+
+    fn max(a: Self::Register, b: Self::Register) -> Self::Register {
+        unsafe { _mm256_max_pd(a, b) }
+    }
+
+    fn sqrt(a: Self::Register) -> Self::Register {
+        unsafe { _mm256_sqrt_pd(a) }
+    }
+
+    fn lt(a: Self::Register, b: Self::Register) -> Self::Mask {
+        unsafe { _mm256_cmp_pd(a, b, _CMP_LT_OQ) }
+    }
+
+    fn le(a: Self::Register, b: Self::Register) -> Self::Mask {
+        unsafe { _mm256_cmp_pd(a, b, _CMP_LE_OQ) }
+    }
+
+    fn and(a: Self::Mask, b: Self::Mask) -> Self::Mask {
+        unsafe { _mm256_and_pd(a, b) }
+    }
+
+    fn or(a: Self::Mask, b: Self::Mask) -> Self::Mask {
+        unsafe { _mm256_or_pd(a, b) }
+    }
+
+    fn not(a: Self::Mask) -> Self::Mask {
+        unsafe { _mm256_xor_pd(a, _mm256_castsi256_pd(_mm256_set1_epi64x(-1))) }
+    }
+
+    fn bits(mask: Self::Mask) -> u8 {
+        unsafe { _mm256_movemask_pd(mask) as u8 }
+    }
+
+    fn select(mask: Self::Mask, a: Self::Register, b: Self::Register) -> Self::Register {
+        unsafe { _mm256_blendv_pd(b, a, mask) }
+    }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+macro_rules! array_lanes {
+    ($($t:ty),*) => {$(
+        impl Lanes for $t {
+            type Register = [$t; LANES];
+            type Mask = [bool; LANES];
+
+            fn splat(value: Self) -> Self::Register {
+                [value; LANES]
+            }
+
+            fn from_array(values: [Self; LANES]) -> Self::Register {
+                values
+            }
+
+            fn to_array(register: Self::Register) -> [Self; LANES] {
+                register
+            }
+
+            fn add(a: Self::Register, b: Self::Register) -> Self::Register {
+                [a[0] + b[0], a[1] + b[1], a[2] + b[2], a[3] + b[3]]
+            }
+
+            fn sub(a: Self::Register, b: Self::Register) -> Self::Register {
+                [a[0] - b[0], a[1] - b[1], a[2] - b[2], a[3] - b[3]]
+            }
+
+            fn mul(a: Self::Register, b: Self::Register) -> Self::Register {
+                [a[0] * b[0], a[1] * b[1], a[2] * b[2], a[3] * b[3]]
+            }
+
+            fn div(a: Self::Register, b: Self::Register) -> Self::Register {
+                [a[0] / b[0], a[1] / b[1], a[2] / b[2], a[3] / b[3]]
+            }
+
+            fn min(a: Self::Register, b: Self::Register) -> Self::Register {
+                [a[0].min(b[0]), a[1].min(b[1]), a[2].min(b[2]), a[3].min(b[3])]
+            }
+
+            fn max(a: Self::Register, b: Self::Register) -> Self::Register {
+                [a[0].max(b[0]), a[1].max(b[1]), a[2].max(b[2]), a[3].max(b[3])]
+            }
+
+            fn sqrt(a: Self::Register) -> Self::Register {
+                [a[0].sqrt(), a[1].sqrt(), a[2].sqrt(), a[3].sqrt()]
+            }
+
+            fn lt(a: Self::Register, b: Self::Register) -> Self::Mask {
+                [a[0] < b[0], a[1] < b[1], a[2] < b[2], a[3] < b[3]]
+            }
+
+            fn le(a: Self::Register, b: Self::Register) -> Self::Mask {
+                [a[0] <= b[0], a[1] <= b[1], a[2] <= b[2], a[3] <= b[3]]
+            }
+
+            fn and(a: Self::Mask, b: Self::Mask) -> Self::Mask {
+                [a[0] && b[0], a[1] && b[1], a[2] && b[2], a[3] && b[3]]
+            }
+
+            fn or(a: Self::Mask, b: Self::Mask) -> Self::Mask {
+                [a[0] || b[0], a[1] || b[1], a[2] || b[2], a[3] || b[3]]
+            }
+
+            fn not(a: Self::Mask) -> Self::Mask {
+                [!a[0], !a[1], !a[2], !a[3]]
+            }
+
+            fn bits(mask: Self::Mask) -> u8 {
+                mask.iter()
+                    .enumerate()
+                    .fold(0, |bits, (lane, &set)| bits | (set as u8) << lane)
+            }
+
+            fn select(mask: Self::Mask, a: Self::Register, b: Self::Register) -> Self::Register {
+                [
+                    if mask[0] { a[0] } else { b[0] },
+                    if mask[1] { a[1] } else { b[1] },
+                    if mask[2] { a[2] } else { b[2] },
+                    if mask[3] { a[3] } else { b[3] },
+                ]
+            }
+        }
+    )*};
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+array_lanes!(f32, f64);
+
+/// `LANES` numbers worked on at once.
+#[derive(Copy, Clone)]
+pub struct Float4(<Number as Lanes>::Register);
+
+impl Float4 {
+    pub fn splat(value: Number) -> Self {
+        Self(<Number as Lanes>::splat(value))
+    }
+
+    pub fn new(values: [Number; LANES]) -> Self {
+        Self(<Number as Lanes>::from_array(values))
+    }
+
+    pub fn to_array(self) -> [Number; LANES] {
+        <Number as Lanes>::to_array(self.0)
+    }
+
+    pub fn min(self, other: Self) -> Self {
+        Self(<Number as Lanes>::min(self.0, other.0))
+    }
+
+    pub fn max(self, other: Self) -> Self {
+        Self(<Number as Lanes>::max(self.0, other.0))
+    }
+
+    pub fn sqrt(self) -> Self {
+        Self(<Number as Lanes>::sqrt(self.0))
+    }
+
+    pub fn lt(self, other: Self) -> Mask4 {
+        Mask4(<Number as Lanes>::lt(self.0, other.0))
+    }
+
+    pub fn le(self, other: Self) -> Mask4 {
+        Mask4(<Number as Lanes>::le(self.0, other.0))
+    }
+
+    pub fn gt(self, other: Self) -> Mask4 {
+        other.lt(self)
+    }
+
+    pub fn ge(self, other: Self) -> Mask4 {
+        other.le(self)
+    }
+
+    /// Takes each lane from `a` where `mask` is set, and from `b` elsewhere.
+    pub fn select(mask: Mask4, a: Self, b: Self) -> Self {
+        Self(<Number as Lanes>::select(mask.0, a.0, b.0))
+    }
+}
+
+impl Neg for Float4 {
+    type Output = Self;
+
+    fn neg(self) -> Self::Output {
+        Self::splat(0.0) - self
+    }
+}
+
+macro_rules! operator_impl {
+    ($(($t:ty, $fn:ident)),*) => {$(
+        impl $t for Float4 {
+            type Output = Self;
+
+            fn $fn(self, other: Self) -> Self::Output {
+                Self(<Number as Lanes>::$fn(self.0, other.0))
+            }
+        }
+    )*};
+}
+
+operator_impl!((Add, add), (Sub, sub), (Mul, mul), (Div, div));
+
+/// Which lanes of a `Float4` a comparison holds for.
+#[derive(Copy, Clone)]
+pub struct Mask4(<Number as Lanes>::Mask);
+
+impl Mask4 {
+    /// One bit per lane, the first lane in the lowest bit.
+    pub fn bits(self) -> u8 {
+        <Number as Lanes>::bits(self.0)
+    }
+
+    pub fn lane(self, lane: usize) -> bool {
+        self.bits() & 1 << lane != 0
+    }
+
+    pub fn any(self) -> bool {
+        self.bits() != 0
+    }
+
+    pub fn all(self) -> bool {
+        self.bits() == (1 << LANES) - 1
+    }
+}
+
+impl BitAnd for Mask4 {
+    type Output = Self;
+
+    fn bitand(self, other: Self) -> Self::Output {
+        Self(<Number as Lanes>::and(self.0, other.0))
+    }
+}
+
+impl BitOr for Mask4 {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self::Output {
+        Self(<Number as Lanes>::or(self.0, other.0))
+    }
+}
+
+impl Not for Mask4 {
+    type Output = Self;
+
+    fn not(self) -> Self::Output {
+        Self(<Number as Lanes>::not(self.0))
+    }
+}
+
+/// `LANES` vectors, stored component by component so each operation works on all of them at
+/// once.
+#[derive(Copy, Clone)]
+pub struct Vector3x4 {
+    pub x: Float4,
+    pub y: Float4,
+    pub z: Float4,
+}
+
+impl Vector3x4 {
+    pub fn splat(vector: Vector3) -> Self {
+        Self {
+            x: Float4::splat(vector.x),
+            y: Float4::splat(vector.y),
+            z: Float4::splat(vector.z),
+        }
+    }
+
+    pub fn new(vectors: [Vector3; LANES]) -> Self {
+        Self {
+            x: Float4::new(vectors.map(|vector| vector.x)),
+            y: Float4::new(vectors.map(|vector| vector.y)),
+            z: Float4::new(vectors.map(|vector| vector.z)),
+        }
+    }
+
+    pub fn lane(&self, lane: usize) -> Vector3 {
+        vector3(
+            self.x.to_array()[lane],
+            self.y.to_array()[lane],
+            self.z.to_array()[lane],
+        )
+    }
+
+    pub fn dot(&self, other: &Self) -> Float4 {
+        self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    pub fn length_squared(&self) -> Float4 {
+        self.dot(self)
+    }
+}
+
+impl Add for Vector3x4 {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self::Output {
+        Self {
+            x: self.x + other.x,
+            y: self.y + other.y,
+            z: self.z + other.z,
+        }
+    }
+}
+
+impl Sub for Vector3x4 {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self::Output {
+        Self {
+            x: self.x - other.x,
+            y: self.y - other.y,
+            z: self.z - other.z,
+        }
+    }
+}
+
+impl Mul<Float4> for Vector3x4 {
+    type Output = Self;
+
+    fn mul(self, other: Float4) -> Self::Output {
+        Self {
+            x: self.x * other,
+            y: self.y * other,
+            z: self.z * other,
+        }
+    }
+}
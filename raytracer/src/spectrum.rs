@@ -0,0 +1,255 @@
+use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign};
+
+use super::color::{color, Color};
+use super::color_space::ColorSpace;
+use super::Number;
+
+// Spectral renders follow each path at a few wavelengths at once, with hero wavelength sampling
+// (Wilkie et al. 2014): the first one is picked at random and the rest are spread evenly through
+// the visible range from there. Colors in the scene are given in linear sRGB, so they get
+// turned into smooth spectra on the way in, and the spectra reaching the camera are integrated
+// against the CIE 1931 observer and turned back into sRGB on the way out.
+
+/// How many wavelengths a path carries.
+pub const SPECTRUM_SAMPLES: usize = 4;
+
+/// The visible range in nanometres, which wavelengths get picked from.
+pub const WAVELENGTH_MIN: Number = 380.0;
+pub const WAVELENGTH_MAX: Number = 780.0;
+
+// Integral of `cie_xyz`'s y over the visible range, which scales an equal energy spectrum of 1
+// to a luminance of 1.
+const CIE_Y_INTEGRAL: Number = 106.9198;
+
+/// The wavelengths of a path in nanometres, with the density each was picked with.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Wavelengths {
+    pub lambda: [Number; SPECTRUM_SAMPLES],
+    pub pdf: [Number; SPECTRUM_SAMPLES],
+}
+
+impl Wavelengths {
+    /// Picks the hero wavelength with `u`, and the others at even steps after it, wrapping
+    /// around at the end of the range.
+    pub fn sample(u: Number) -> Self {
+        let range = WAVELENGTH_MAX - WAVELENGTH_MIN;
+        let mut lambda = [0.0; SPECTRUM_SAMPLES];
+
+        for (i, lambda) in lambda.iter_mut().enumerate() {
+            let offset = (u + i as Number / SPECTRUM_SAMPLES as Number).fract();
+            *lambda = WAVELENGTH_MIN + offset * range;
+        }
+
+        Self {
+            lambda,
+            pdf: [1.0 / range; SPECTRUM_SAMPLES],
+        }
+    }
+
+    pub fn hero(&self) -> Number {
+        self.lambda[0]
+    }
+
+    /// Drops every wavelength but the hero, for when they'd go separate ways, such as through
+    /// dispersive glass. The hero then stands in for all of them.
+    pub fn terminate_secondary(&mut self) {
+        if self.pdf[1] == 0.0 {
+            return;
+        }
+
+        for pdf in &mut self.pdf[1..] {
+            *pdf = 0.0;
+        }
+        self.pdf[0] /= SPECTRUM_SAMPLES as Number;
+    }
+}
+
+/// Values of a spectrum at the wavelengths of a path.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Spectrum(pub [Number; SPECTRUM_SAMPLES]);
+
+impl Spectrum {
+    pub fn splat(value: Number) -> Self {
+        Self([value; SPECTRUM_SAMPLES])
+    }
+
+    pub fn from_fn(wavelengths: &Wavelengths, f: impl Fn(Number) -> Number) -> Self {
+        Self(wavelengths.lambda.map(f))
+    }
+
+    /// A smooth spectrum that looks like `rgb`, for reflectances and other colors that aren't
+    /// light. It's a blend of a red, a green and a blue band that add up to 1, weighted so that
+    /// the result comes back to `rgb` under a white light. White stays flat, and colors outside
+    /// of what the bands can make are clipped at 0.
+    pub fn from_rgb(rgb: Color, wavelengths: &Wavelengths) -> Self {
+        let weight = |row: [Number; 3]| row[0] * rgb.r + row[1] * rgb.g + row[2] * rgb.b;
+        let [red, green, blue] = RGB_TO_BANDS.map(weight);
+
+        Self::from_fn(wavelengths, |lambda| {
+            let (r, b) = bands(lambda);
+            (red * r + green * (1.0 - r - b).max(0.0) + blue * b).max(0.0)
+        })
+    }
+
+    /// A spectrum for light of color `rgb`, which is `from_rgb` lit by D65, the white of sRGB.
+    pub fn from_illuminant(rgb: Color, wavelengths: &Wavelengths) -> Self {
+        Self::from_rgb(rgb, wavelengths) * Self::from_fn(wavelengths, d65)
+    }
+
+    /// Light given off by a black body at `temperature` kelvin, scaled to a luminance of 1 like
+    /// `Color::blackbody`.
+    #[allow(clippy::unnecessary_cast)]
+    pub fn blackbody(temperature: Number, wavelengths: &Wavelengths) -> Self {
+        if temperature <= 0.0 {
+            return Self::splat(0.0);
+        }
+
+        let temperature = temperature as f64;
+        let luminance = (0..=80)
+            .map(|step| {
+                let lambda = 380.0 + 5.0 * step as f64;
+                planck(lambda, temperature) * cie_xyz(lambda as Number)[1] as f64 * 5.0
+            })
+            .sum::<f64>()
+            / CIE_Y_INTEGRAL as f64;
+
+        if luminance <= 0.0 || luminance.is_nan() {
+            return Self::splat(0.0);
+        }
+
+        Self::from_fn(wavelengths, |lambda| {
+            (planck(lambda as f64, temperature) / luminance) as Number
+        })
+    }
+
+    pub fn max(&self) -> Number {
+        self.0
+            .iter()
+            .fold(0.0, |max: Number, &value| max.max(value))
+    }
+
+    /// The color of the spectrum in linear sRGB, estimated from its values at `wavelengths`.
+    /// Wavelengths that were dropped don't count.
+    pub fn to_rgb(&self, wavelengths: &Wavelengths) -> Color {
+        let mut xyz = [0.0; 3];
+
+        for ((&value, &lambda), &pdf) in
+            self.0.iter().zip(&wavelengths.lambda).zip(&wavelengths.pdf)
+        {
+            if pdf == 0.0 {
+                continue;
+            }
+
+            for (xyz, cie) in xyz.iter_mut().zip(cie_xyz(lambda)) {
+                *xyz += value * cie / pdf;
+            }
+        }
+
+        let [x, y, z] = xyz.map(|xyz| xyz / (SPECTRUM_SAMPLES as Number * CIE_Y_INTEGRAL));
+        color(x, y, z).convert(ColorSpace::Xyz, ColorSpace::Srgb)
+    }
+}
+
+macro_rules! operator_impl {
+    ($(($t:ty, $tf:ty, $fn:ident, $op:tt, $at:ty, $atf:ty, $afn:ident, $aop:tt)),*) => {$(
+        impl $t for Spectrum {
+            type Output = Self;
+
+            fn $fn(mut self, other: Self) -> Self::Output {
+                for (value, other) in self.0.iter_mut().zip(other.0) {
+                    *value $aop other;
+                }
+                self
+            }
+        }
+
+        impl $tf for Spectrum {
+            type Output = Self;
+
+            fn $fn(self, other: Number) -> Self::Output {
+                Self(self.0.map(|value| value $op other))
+            }
+        }
+
+        impl $at for Spectrum {
+            fn $afn(&mut self, other: Self) {
+                *self = *self $op other;
+            }
+        }
+
+        impl $atf for Spectrum {
+            fn $afn(&mut self, other: Number) {
+                *self = *self $op other;
+            }
+        }
+    )*};
+}
+
+operator_impl!(
+    (Add, Add<Number>, add, +, AddAssign, AddAssign<Number>, add_assign, +=),
+    (Mul, Mul<Number>, mul, *, MulAssign, MulAssign<Number>, mul_assign, *=),
+    (Div, Div<Number>, div, /, DivAssign, DivAssign<Number>, div_assign, /=)
+);
+
+// The red and blue bands of `Spectrum::from_rgb` rise and fall smoothly at these wavelengths, and
+// the green one fills the gap between them. The matrix makes up for the bands' overlap with the
+// other channels of sRGB, found by integrating them under D65.
+const RED_EDGE: Number = 590.0;
+const BLUE_EDGE: Number = 490.0;
+const EDGE_WIDTH: Number = 8.0;
+const RGB_TO_BANDS: [[Number; 3]; 3] = [
+    [0.991052, -0.003798, 0.013116],
+    [-0.014737, 0.997874, 0.016705],
+    [0.021481, 0.006551, 0.972480],
+];
+
+// How much of the red and the blue band there is at `lambda`.
+fn bands(lambda: Number) -> (Number, Number) {
+    let sigmoid = |x: Number| 1.0 / (1.0 + (-x).exp());
+    (
+        sigmoid((lambda - RED_EDGE) / EDGE_WIDTH),
+        sigmoid((BLUE_EDGE - lambda) / EDGE_WIDTH),
+    )
+}
+
+// The CIE D65 illuminant from 380 to 780 nm in steps of 10, scaled so that it has a luminance of
+// 1.
+const D65: [Number; 41] = [
+    49.98, 54.65, 82.75, 91.49, 93.43, 86.68, 104.86, 117.01, 117.81, 114.86, 115.92, 108.81,
+    109.35, 107.80, 104.79, 107.69, 104.41, 104.05, 100.00, 96.33, 95.79, 88.69, 90.01, 89.60,
+    87.70, 83.29, 83.70, 80.03, 80.21, 82.28, 78.28, 69.72, 71.61, 74.35, 61.60, 69.89, 75.09,
+    63.59, 46.42, 66.81, 63.38,
+];
+const D65_LUMINANCE: Number = 98.8531;
+
+fn d65(lambda: Number) -> Number {
+    let position = ((lambda - 380.0) / 10.0).clamp(0.0, 40.0);
+    let index = (position as usize).min(39);
+    let t = position - index as Number;
+
+    (D65[index] * (1.0 - t) + D65[index + 1] * t) / D65_LUMINANCE
+}
+
+/// Spectral radiance of a black body at `lambda` nanometres, up to a constant factor. The
+/// radiation constants fold into one, since only the shape of the spectrum matters.
+pub(crate) fn planck(lambda: f64, temperature: f64) -> f64 {
+    let c2 = 1.4387769e7; // nm K
+    1.0 / (lambda.powi(5) * (c2 / (lambda * temperature)).exp_m1())
+}
+
+/// The CIE 1931 color matching functions at `lambda` nanometres, approximated by the multi-lobe
+/// fit of Wyman et al.
+pub(crate) fn cie_xyz(lambda: Number) -> [Number; 3] {
+    let lobe = |mean: Number, below: Number, above: Number| {
+        let sigma = if lambda < mean { below } else { above };
+        let x = (lambda - mean) / sigma;
+        (-0.5 * x * x).exp()
+    };
+
+    [
+        1.056 * lobe(599.8, 37.9, 31.0) + 0.362 * lobe(442.0, 16.0, 26.7)
+            - 0.065 * lobe(501.1, 20.4, 26.2),
+        0.821 * lobe(568.8, 46.9, 40.5) + 0.286 * lobe(530.9, 16.3, 31.1),
+        1.217 * lobe(437.0, 11.8, 36.0) + 0.681 * lobe(459.0, 26.0, 13.8),
+    ]
+}
@@ -1,56 +1,417 @@
-use std::fmt::Display;
+use std::any::Any;
 
-use crate::hit::{Face, Hit, HitData};
-use crate::material::Material;
-use crate::ray::Ray;
-use crate::vector::Vector3;
-use crate::Number;
+use super::hit::{gamma, Face, Hit, HitData};
+use super::material::Material;
+use super::ray::{Ray, RayPacket};
+use super::simd::{Float4, Vector3x4, LANES};
+use super::vector::Vector3;
+use super::Number;
 
+#[derive(Clone)]
 pub struct Sphere {
     pub center: Vector3,
     pub radius: Number,
-    pub material: Material,
+    pub material: Box<dyn Material>,
+}
+
+// Written out because deriving it trips over comparing boxed trait objects.
+impl PartialEq for Sphere {
+    fn eq(&self, other: &Self) -> bool {
+        self.center == other.center
+            && self.radius == other.radius
+            && *self.material == *other.material
+    }
 }
 
 impl Hit for Sphere {
-    fn hit(&self, ray: &Ray, t_min: Number, t_max: Number) -> Option<HitData> {
-        let distance = ray.origin - self.center;
-        let a = ray.direction.length_squared();
-        let b = ray.direction.dot(&distance);
-        let c = distance.length_squared() - self.radius * self.radius;
-        let discriminant = b * b - a * c;
-
-        if discriminant < 0.0 {
-            return None;
+    fn hit(&self, ray: &Ray, t_min: Number, t_max: Number) -> Option<HitData<'_>> {
+        hit_sphere(
+            &self.center,
+            self.radius,
+            self.material.as_ref(),
+            ray,
+            t_min,
+            t_max,
+        )
+    }
+
+    fn hit_packet(
+        &self,
+        packet: &RayPacket,
+        t_min: Number,
+        t_max: Float4,
+    ) -> [Option<HitData<'_>>; LANES] {
+        hit_sphere_packet(
+            &Vector3x4::splat(self.center),
+            self.radius,
+            self.material.as_ref(),
+            packet,
+            t_min,
+            t_max,
+        )
+    }
+
+    fn name(&self) -> &'static str {
+        "Sphere"
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// A sphere moving in a straight line, from `center0` at `time0` to `center1` at `time1`. It keeps
+/// going at the same speed outside of that interval.
+#[derive(Clone)]
+pub struct MovingSphere {
+    pub center0: Vector3,
+    pub center1: Vector3,
+    pub time0: Number,
+    pub time1: Number,
+    pub radius: Number,
+    pub material: Box<dyn Material>,
+}
+
+impl MovingSphere {
+    pub fn center(&self, time: Number) -> Vector3 {
+        if self.time1 == self.time0 {
+            return self.center0;
         }
 
-        let sqrt_d = discriminant.sqrt();
-        let mut t = (-b - sqrt_d) / a;
-        if t < t_min || t > t_max {
-            t = (-b + sqrt_d) / a;
-            if t < t_min || t > t_max {
-                return None;
+        let t = (time - self.time0) / (self.time1 - self.time0);
+        self.center0 + (self.center1 - self.center0) * t
+    }
+}
+
+impl PartialEq for MovingSphere {
+    fn eq(&self, other: &Self) -> bool {
+        self.center0 == other.center0
+            && self.center1 == other.center1
+            && self.time0 == other.time0
+            && self.time1 == other.time1
+            && self.radius == other.radius
+            && *self.material == *other.material
+    }
+}
+
+impl Hit for MovingSphere {
+    fn hit(&self, ray: &Ray, t_min: Number, t_max: Number) -> Option<HitData<'_>> {
+        hit_sphere(
+            &self.center(ray.time),
+            self.radius,
+            self.material.as_ref(),
+            ray,
+            t_min,
+            t_max,
+        )
+    }
+
+    fn hit_packet(
+        &self,
+        packet: &RayPacket,
+        t_min: Number,
+        t_max: Float4,
+    ) -> [Option<HitData<'_>>; LANES] {
+        let center = if self.time1 == self.time0 {
+            Vector3x4::splat(self.center0)
+        } else {
+            let t =
+                (packet.time - Float4::splat(self.time0)) / Float4::splat(self.time1 - self.time0);
+            Vector3x4::splat(self.center0) + Vector3x4::splat(self.center1 - self.center0) * t
+        };
+
+        hit_sphere_packet(
+            &center,
+            self.radius,
+            self.material.as_ref(),
+            packet,
+            t_min,
+            t_max,
+        )
+    }
+
+    fn name(&self) -> &'static str {
+        "Moving Sphere"
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+fn hit_sphere<'a>(
+    center: &Vector3,
+    radius: Number,
+    material: &'a dyn Material,
+    ray: &Ray,
+    t_min: Number,
+    t_max: Number,
+) -> Option<HitData<'a>> {
+    let distance = ray.origin - *center;
+    let a = ray.direction.length_squared();
+    let b = ray.direction.dot(&distance);
+    let c = distance.length_squared() - radius * radius;
+
+    // `b * b - a * c` cancels badly for rays passing far from the center, so the discriminant is
+    // found from how close the ray gets to the center instead.
+    let closest = (distance - ray.direction * (b / a)).length();
+    let discriminant = a * (radius + closest) * (radius - closest);
+    if discriminant < 0.0 {
+        return None;
+    }
+
+    // The stable form of the quadratic formula, which doesn't subtract nearly equal numbers
+    // either. The root near 0 is always `c / q`.
+    let q = -(b + discriminant.sqrt().copysign(b));
+    let (t0, t1) = (q / a, c / q);
+    let (near, far) = (t0.min(t1), t0.max(t1));
+
+    // A root only counts once it's clearly past `t_min`, so that rays leaving the surface can't
+    // find it again at a t that is only rounding error.
+    let c_error = gamma(5) * (distance.length_squared() + radius * radius);
+    let valid = |t: Number| t - (c_error / q.abs() + gamma(7) * t.abs()) > t_min && t <= t_max;
+
+    let t = if valid(near) {
+        near
+    } else if valid(far) {
+        far
+    } else {
+        return None;
+    };
+
+    Some(sphere_hit_data(center, radius, material, ray, t))
+}
+
+// The same test as `hit_sphere` for every lane of a packet. Lanes that miss take the square root
+// of a negative number, which fails every comparison after it.
+fn hit_sphere_packet<'a>(
+    center: &Vector3x4,
+    radius: Number,
+    material: &'a dyn Material,
+    packet: &RayPacket,
+    t_min: Number,
+    t_max: Float4,
+) -> [Option<HitData<'a>>; LANES] {
+    let mut hits = [None; LANES];
+
+    let distance = packet.origin - *center;
+    let a = packet.direction.length_squared();
+    let b = packet.direction.dot(&distance);
+    let c = distance.length_squared() - Float4::splat(radius * radius);
+
+    let closest = (distance - packet.direction * (b / a))
+        .length_squared()
+        .sqrt();
+    let radius4 = Float4::splat(radius);
+    let discriminant = a * (radius4 + closest) * (radius4 - closest);
+
+    if !discriminant.ge(Float4::splat(0.0)).any() {
+        return hits;
+    }
+
+    let zero = Float4::splat(0.0);
+    let sqrt_d = discriminant.sqrt();
+    let q = -(b + Float4::select(b.lt(zero), -sqrt_d, sqrt_d));
+    let (t0, t1) = (q / a, c / q);
+    let (near, far) = (t0.min(t1), t0.max(t1));
+
+    let abs = |x: Float4| x.max(-x);
+    let c_error = Float4::splat(gamma(5)) * (distance.length_squared() + radius4 * radius4);
+    let valid = |t: Float4| {
+        let error = c_error / abs(q) + Float4::splat(gamma(7)) * abs(t);
+        (t - error).gt(Float4::splat(t_min)) & t.le(t_max)
+    };
+    let near_hit = valid(near);
+    let far_hit = valid(far);
+
+    let hit = near_hit | far_hit;
+    if !hit.any() {
+        return hits;
+    }
+
+    let t = Float4::select(near_hit, near, far).to_array();
+    for (lane, ray) in packet.rays.iter().enumerate() {
+        if hit.lane(lane) {
+            hits[lane] = Some(sphere_hit_data(
+                &center.lane(lane),
+                radius,
+                material,
+                ray,
+                t[lane],
+            ));
+        }
+    }
+
+    hits
+}
+
+fn sphere_hit_data<'a>(
+    center: &Vector3,
+    radius: Number,
+    material: &'a dyn Material,
+    ray: &Ray,
+    t: Number,
+) -> HitData<'a> {
+    // The hit is put back onto the surface, which leaves a much smaller error than finding it
+    // along the ray.
+    let local = ray.at(t) - *center;
+    let local = local * (radius / local.length());
+    let point = *center + local;
+    let error = local.abs() * gamma(5) + point.abs() * gamma(1);
+
+    let mut normal = local / radius;
+    let face = Face::get(ray, &normal);
+
+    // Longitude around the y axis starting at -x, and latitude from the bottom pole.
+    let pi = std::f64::consts::PI as Number;
+    let uv = (
+        ((-normal.z).atan2(normal.x) + pi) / (2.0 * pi),
+        (-normal.y).clamp(-1.0, 1.0).acos() / pi,
+    );
+
+    normal = match face {
+        Face::Outwards => normal,
+        Face::Inwards => -normal,
+    };
+
+    HitData {
+        t,
+        point,
+        normal,
+        face,
+        uv,
+        material,
+        error,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::color::color;
+    use super::super::material::Lambertian;
+    use super::super::vector::vector3;
+    use super::*;
+
+    fn ray(origin: Vector3, direction: Vector3, time: Number) -> Ray {
+        Ray {
+            origin,
+            direction,
+            time,
+        }
+    }
+
+    // Packets have to give bit for bit what tracing their rays one at a time gives, or the
+    // benchmark would measure a different image than renders produce.
+    fn check_packet(object: &dyn Hit, rays: [Ray; LANES], t_max: [Number; LANES]) -> u8 {
+        let packet = object.hit_packet(&RayPacket::new(rays), 0.001, Float4::new(t_max));
+        let mut hits = 0;
+
+        for lane in 0..LANES {
+            let single = object.hit(&rays[lane], 0.001, t_max[lane]);
+            match (&packet[lane], &single) {
+                (None, None) => {}
+                (Some(packet), Some(single)) => {
+                    assert_eq!(packet.t.to_bits(), single.t.to_bits(), "lane {}", lane);
+                    assert!(packet.point == single.point && packet.normal == single.normal);
+                    hits |= 1 << lane;
+                }
+                _ => panic!(
+                    "lane {} hit {} in the packet and {} on its own",
+                    lane,
+                    packet[lane].is_some(),
+                    single.is_some()
+                ),
             }
         }
 
-        let point = ray.at(t);
-        let mut normal = (point - self.center) / self.radius;
-        let face = Face::get(ray, &normal);
-        normal = match face {
-            Face::Outwards => normal,
-            Face::Inwards => -normal,
+        hits
+    }
+
+    #[test]
+    fn packets_hit_spheres_like_single_rays() {
+        let sphere = Sphere {
+            center: vector3(0.0, 0.0, -3.0),
+            radius: 1.0,
+            material: Box::new(Lambertian {
+                albedo: color(1.0, 1.0, 1.0),
+            }),
         };
+        let origin = vector3(0.0, 0.0, 0.0);
+
+        // A hit from outside, a miss, a hit from the center, and one cut short by its `t_max`.
+        let hits = check_packet(
+            &sphere,
+            [
+                ray(origin, vector3(0.1, 0.2, -1.0), 0.0),
+                ray(origin, vector3(1.0, 0.0, -1.0), 0.0),
+                ray(sphere.center, vector3(0.3, -0.4, 0.5), 0.0),
+                ray(origin, vector3(0.0, 0.0, -1.0), 0.0),
+            ],
+            [Number::INFINITY, Number::INFINITY, Number::INFINITY, 1.5],
+        );
+        assert_eq!(hits, 0b0101);
 
-        Some(HitData {
-            t,
-            point,
-            normal,
-            face,
-            material: &self.material,
-        })
+        // Nothing hits, behind the ray or beside it, which skips the rest of the test early.
+        let hits = check_packet(
+            &sphere,
+            [
+                ray(origin, vector3(0.0, 0.0, 1.0), 0.0),
+                ray(origin, vector3(0.0, 1.0, 0.0), 0.0),
+                ray(origin, vector3(-2.0, 0.0, -1.0), 0.0),
+                ray(vector3(0.0, 0.0, -6.0), vector3(0.0, 0.0, -1.0), 0.0),
+            ],
+            [Number::INFINITY; LANES],
+        );
+        assert_eq!(hits, 0);
+
+        // Far away rays, which the error bounds care most about, and a grazing one.
+        let far = vector3(1000.0, 2000.0, -3000.0);
+        let hits = check_packet(
+            &sphere,
+            [
+                ray(far, sphere.center - far, 0.0),
+                ray(far, sphere.center - far + vector3(0.5, 0.5, 0.0), 0.0),
+                ray(far, sphere.center - far + vector3(5.0, 0.0, 0.0), 0.0),
+                ray(vector3(-3.0, 1.0, -3.0), vector3(1.0, 0.0, 0.0), 0.0),
+            ],
+            [Number::INFINITY; LANES],
+        );
+        assert_eq!(hits & 0b0111, 0b0011);
     }
 
-    fn name(&self) -> &'static str {
-        "Sphere"
+    #[test]
+    fn packets_hit_moving_spheres_like_single_rays() {
+        let sphere = MovingSphere {
+            center0: vector3(0.0, 0.0, -3.0),
+            center1: vector3(2.0, 0.0, -3.0),
+            time0: 0.0,
+            time1: 1.0,
+            radius: 0.5,
+            material: Box::new(Lambertian {
+                albedo: color(1.0, 1.0, 1.0),
+            }),
+        };
+        let direction = vector3(0.0, 0.0, -1.0);
+
+        // The same ray at different times finds the sphere somewhere else.
+        let hits = check_packet(
+            &sphere,
+            [
+                ray(vector3(0.0, 0.0, 0.0), direction, 0.0),
+                ray(vector3(0.0, 0.0, 0.0), direction, 1.0),
+                ray(vector3(2.0, 0.0, 0.0), direction, 1.0),
+                ray(vector3(1.0, 0.2, 0.0), direction, 0.5),
+            ],
+            [Number::INFINITY; LANES],
+        );
+        assert_eq!(hits, 0b1101);
     }
 }
@@ -0,0 +1,359 @@
+use std::fmt::Write;
+use std::ops::AddAssign;
+use std::time::Duration;
+
+/// Counts gathered while rendering.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct RenderStats {
+    /// Rays leaving the camera, one per path.
+    pub primary_rays: u64,
+    /// Rays continuing a path after it scattered.
+    pub secondary_rays: u64,
+    /// Rays traced once more from the camera to find the features guiding the denoiser.
+    pub feature_rays: u64,
+    /// Objects tested for a hit, counted at the top level of the scene as each one gets asked,
+    /// and the fog for every path ray in a scene that has some. An instance or a medium counts
+    /// once, however many tests it makes of what's inside it.
+    pub primitive_tests: u64,
+    pub render_time: Duration,
+}
+
+impl RenderStats {
+    /// Every ray traced, for the paths and for the denoiser.
+    pub fn rays(&self) -> u64 {
+        self.path_rays() + self.feature_rays
+    }
+
+    pub fn path_rays(&self) -> u64 {
+        self.primary_rays + self.secondary_rays
+    }
+
+    pub fn rays_per_second(&self) -> f64 {
+        ratio(self.rays() as f64, self.render_time.as_secs_f64())
+    }
+
+    /// Rays per path, counting the one from the camera.
+    pub fn average_path_length(&self) -> f64 {
+        ratio(self.path_rays() as f64, self.primary_rays as f64)
+    }
+
+    pub fn primitive_tests_per_ray(&self) -> f64 {
+        ratio(self.primitive_tests as f64, self.rays() as f64)
+    }
+}
+
+// Zero when there's nothing to divide, so empty renders still give numbers JSON can hold.
+fn ratio(numerator: f64, denominator: f64) -> f64 {
+    if denominator > 0.0 {
+        numerator / denominator
+    } else {
+        0.0
+    }
+}
+
+/// Sums the stats of several renders, such as the frames of an animation.
+impl AddAssign for RenderStats {
+    fn add_assign(&mut self, other: Self) {
+        self.primary_rays += other.primary_rays;
+        self.secondary_rays += other.secondary_rays;
+        self.feature_rays += other.feature_rays;
+        self.primitive_tests += other.primitive_tests;
+        self.render_time += other.render_time;
+    }
+}
+
+/// Where the time of a run went, phase by phase, along with the stats of what it rendered.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Profile {
+    /// Phases in the order they first ran, with their total time.
+    pub phases: Vec<(&'static str, Duration)>,
+    pub stats: RenderStats,
+    /// Peak resident memory of the process in bytes, where the platform tells.
+    pub peak_memory: Option<u64>,
+}
+
+impl Profile {
+    /// Adds `duration` to the phase called `name`, which gets added if it's new.
+    pub fn add_phase(&mut self, name: &'static str, duration: Duration) {
+        match self.phases.iter_mut().find(|(phase, _)| *phase == name) {
+            Some((_, total)) => *total += duration,
+            None => self.phases.push((name, duration)),
+        }
+    }
+
+    /// A plain text table for people to read.
+    pub fn table(&self) -> String {
+        let mut rows = Vec::new();
+
+        for (name, duration) in &self.phases {
+            rows.push((format!("Time to {}", name), format_duration(*duration)));
+        }
+
+        let stats = &self.stats;
+        rows.push(("Primary rays".to_string(), stats.primary_rays.to_string()));
+        rows.push((
+            "Secondary rays".to_string(),
+            stats.secondary_rays.to_string(),
+        ));
+        rows.push(("Feature rays".to_string(), stats.feature_rays.to_string()));
+        rows.push((
+            "Rays per second".to_string(),
+            format!("{:.3} M", stats.rays_per_second() / 1e6),
+        ));
+        rows.push((
+            "Average path length".to_string(),
+            format!("{:.3}", stats.average_path_length()),
+        ));
+        rows.push((
+            "Primitive tests per ray".to_string(),
+            format!("{:.3}", stats.primitive_tests_per_ray()),
+        ));
+        rows.push((
+            "Peak memory".to_string(),
+            match self.peak_memory {
+                Some(bytes) => format!("{:.1} MiB", bytes as f64 / (1024.0 * 1024.0)),
+                None => "unknown".to_string(),
+            },
+        ));
+
+        let width = rows.iter().map(|(label, _)| label.len()).max().unwrap_or(0);
+        let mut out = String::new();
+        for (label, value) in rows {
+            writeln!(out, "{:<width$}  {}", label, value, width = width).unwrap();
+        }
+
+        out
+    }
+
+    /// The same numbers as JSON, for tools that track them over time. Times are in seconds and
+    /// the peak memory is `null` where it's unknown.
+    pub fn json(&self) -> String {
+        let stats = &self.stats;
+        let phases = self
+            .phases
+            .iter()
+            .map(|(name, duration)| format!("\"{}\": {}", name, duration.as_secs_f64()))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let peak_memory = self
+            .peak_memory
+            .map_or("null".to_string(), |bytes| bytes.to_string());
+
+        let mut out = String::new();
+        writeln!(out, "{{").unwrap();
+        writeln!(out, "  \"phases\": {{{}}},", phases).unwrap();
+        writeln!(out, "  \"primary_rays\": {},", stats.primary_rays).unwrap();
+        writeln!(out, "  \"secondary_rays\": {},", stats.secondary_rays).unwrap();
+        writeln!(out, "  \"feature_rays\": {},", stats.feature_rays).unwrap();
+        writeln!(out, "  \"primitive_tests\": {},", stats.primitive_tests).unwrap();
+        writeln!(out, "  \"rays_per_second\": {},", stats.rays_per_second()).unwrap();
+        writeln!(
+            out,
+            "  \"average_path_length\": {},",
+            stats.average_path_length()
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "  \"primitive_tests_per_ray\": {},",
+            stats.primitive_tests_per_ray()
+        )
+        .unwrap();
+        writeln!(out, "  \"peak_memory\": {}", peak_memory).unwrap();
+        writeln!(out, "}}").unwrap();
+
+        out
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    format!("{:.1} ms", duration.as_secs_f64() * 1000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Just enough of JSON to read back what `Profile::json` writes, strictly enough that anything
+    // malformed fails to parse.
+    #[derive(PartialEq, Debug)]
+    enum Json {
+        Null,
+        Number(f64),
+        Object(Vec<(String, Json)>),
+    }
+
+    impl Json {
+        fn get(&self, key: &str) -> &Json {
+            match self {
+                Json::Object(members) => members
+                    .iter()
+                    .find(|(name, _)| name == key)
+                    .map(|(_, value)| value)
+                    .unwrap_or_else(|| panic!("no `{}`", key)),
+                _ => panic!("not an object"),
+            }
+        }
+
+        fn number(&self) -> f64 {
+            match self {
+                Json::Number(number) => *number,
+                _ => panic!("not a number"),
+            }
+        }
+    }
+
+    fn parse(text: &str) -> Result<Json, String> {
+        let mut rest = text.trim_start();
+        let value = parse_value(&mut rest)?;
+        match rest.trim_start() {
+            "" => Ok(value),
+            rest => Err(format!("trailing `{}`", rest)),
+        }
+    }
+
+    fn parse_value(rest: &mut &str) -> Result<Json, String> {
+        *rest = rest.trim_start();
+
+        if let Some(after) = rest.strip_prefix("null") {
+            *rest = after;
+            Ok(Json::Null)
+        } else if let Some(after) = rest.strip_prefix('{') {
+            *rest = after.trim_start();
+            let mut members = Vec::new();
+            if let Some(after) = rest.strip_prefix('}') {
+                *rest = after;
+                return Ok(Json::Object(members));
+            }
+
+            loop {
+                let name = parse_string(rest)?;
+                *rest = rest
+                    .trim_start()
+                    .strip_prefix(':')
+                    .ok_or_else(|| format!("no `:` after `{}`", name))?;
+                members.push((name, parse_value(rest)?));
+
+                *rest = rest.trim_start();
+                if let Some(after) = rest.strip_prefix(',') {
+                    *rest = after.trim_start();
+                } else if let Some(after) = rest.strip_prefix('}') {
+                    *rest = after;
+                    return Ok(Json::Object(members));
+                } else {
+                    return Err(format!("expected `,` or `}}` at `{}`", rest));
+                }
+            }
+        } else {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_digit() || "+-.eE".contains(c)))
+                .unwrap_or(rest.len());
+            // JSON has no leading `+`, and no NaN or infinity, which Rust would parse.
+            let number = rest[..end]
+                .parse::<f64>()
+                .ok()
+                .filter(|number| number.is_finite() && !rest.starts_with('+'))
+                .ok_or_else(|| format!("expected a value at `{}`", rest))?;
+            *rest = &rest[end..];
+            Ok(Json::Number(number))
+        }
+    }
+
+    fn parse_string(rest: &mut &str) -> Result<String, String> {
+        let after = rest
+            .strip_prefix('"')
+            .ok_or_else(|| format!("expected a string at `{}`", rest))?;
+        let end = after
+            .find(['"', '\\'])
+            .filter(|&end| after[end..].starts_with('"'))
+            .ok_or_else(|| format!("unterminated or escaped string at `{}`", rest))?;
+        *rest = &after[end + 1..];
+        Ok(after[..end].to_string())
+    }
+
+    fn profile() -> Profile {
+        let mut profile = Profile {
+            phases: Vec::new(),
+            stats: RenderStats {
+                primary_rays: 4000,
+                secondary_rays: 6000,
+                feature_rays: 1000,
+                primitive_tests: 33000,
+                render_time: Duration::from_millis(250),
+            },
+            peak_memory: Some(64 * 1024 * 1024),
+        };
+        for (name, milliseconds) in [
+            ("load", 12),
+            ("render", 250),
+            ("denoise", 40),
+            ("render", 30),
+            ("write", 3),
+        ] {
+            profile.add_phase(name, Duration::from_millis(milliseconds));
+        }
+
+        profile
+    }
+
+    #[test]
+    fn profile_json_is_valid_and_has_every_phase() {
+        let profile = profile();
+        let json = parse(&profile.json()).unwrap();
+
+        // Repeated phases add up, in the order they first ran.
+        let phases = match json.get("phases") {
+            Json::Object(phases) => phases,
+            _ => panic!("phases aren't an object"),
+        };
+        let names: Vec<&str> = phases.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["load", "render", "denoise", "write"]);
+        assert!((json.get("phases").get("render").number() - 0.28).abs() < 1e-9);
+
+        assert_eq!(json.get("primary_rays").number(), 4000.0);
+        assert_eq!(json.get("secondary_rays").number(), 6000.0);
+        assert_eq!(json.get("feature_rays").number(), 1000.0);
+        assert_eq!(json.get("primitive_tests").number(), 33000.0);
+        assert_eq!(json.get("rays_per_second").number(), 44000.0);
+        assert_eq!(json.get("average_path_length").number(), 2.5);
+        assert_eq!(json.get("primitive_tests_per_ray").number(), 3.0);
+        assert_eq!(json.get("peak_memory").number(), 64.0 * 1024.0 * 1024.0);
+
+        // Nothing rendered, nothing measured: the ratios have nothing to divide by.
+        let json = parse(&Profile::default().json()).unwrap();
+        assert_eq!(json.get("phases"), &Json::Object(Vec::new()));
+        assert_eq!(json.get("rays_per_second").number(), 0.0);
+        assert_eq!(json.get("average_path_length").number(), 0.0);
+        assert_eq!(json.get("peak_memory"), &Json::Null);
+
+        assert!(parse("{\"a\": 1,}").is_err());
+        assert!(parse("{\"a\": NaN}").is_err());
+    }
+
+    #[test]
+    fn profile_table_columns_line_up() {
+        for profile in [profile(), Profile::default()] {
+            let table = profile.table();
+            let lines: Vec<&str> = table.lines().collect();
+
+            for (name, _) in &profile.phases {
+                let label = format!("Time to {}", name);
+                assert!(
+                    lines.iter().any(|line| line.starts_with(&label)),
+                    "{}",
+                    label
+                );
+            }
+            assert_eq!(lines.len(), profile.phases.len() + 7);
+
+            // Labels only have single spaces in them, so each value starts after the first run of
+            // several. They all start in the same column, two spaces past the longest label.
+            let width = "Primitive tests per ray".len();
+            for line in &lines {
+                let gap = line.find("  ").expect("label and value are apart");
+                let start = gap + line[gap..].find(|c| c != ' ').expect("there's a value");
+                assert_eq!(start, width + 2, "{}", table);
+            }
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use super::color::{color, Color};
+use super::color_space::{ColorSpace, DisplayTransform, TransferFunction};
+use super::image::Image;
+use super::image_reader::ImageReader;
+use super::image_writer::ImageFormat;
+use super::Number;
+
+// Textures are looked up by the surface coordinates of a hit, with `u` and `v` going from 0 to 1.
+// Scalar parameters read the luminance of the texture, so grey images drive them directly.
+
+#[derive(Clone)]
+pub enum Texture {
+    Constant(Color),
+    /// Alternating squares, `scale` of them along each of `u` and `v`.
+    Checker {
+        even: Color,
+        odd: Color,
+        scale: Number,
+    },
+    /// An image file, filtered bilinearly and repeated outside of the unit square. Images that
+    /// failed to load show up magenta, so they're easy to spot in a render. `transfer` tells
+    /// how 8 bit images are encoded: `Srgb` for colors painted or photographed, `Linear` for data
+    /// such as roughness. Float images are always linear.
+    Image {
+        path: String,
+        transfer: TransferFunction,
+        image: Option<Arc<Image>>,
+    },
+}
+
+impl Texture {
+    pub fn scalar(value: Number) -> Self {
+        Texture::Constant(color(value, value, value))
+    }
+
+    /// Loads an image texture, with `path` relative to `directory`. The path is kept as written,
+    /// so scene files can refer to images next to them wherever they're moved.
+    pub fn load(
+        path: &str,
+        directory: impl AsRef<Path>,
+        transfer: TransferFunction,
+    ) -> io::Result<Self> {
+        let format = ImageFormat::from_path(path).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unknown image format for `{}`", path),
+            )
+        })?;
+        let display = DisplayTransform {
+            space: ColorSpace::Srgb,
+            transfer,
+        };
+        let file = File::open(directory.as_ref().join(path))?;
+        let image = ImageReader::new(file, format, display).read()?;
+
+        Ok(Texture::Image {
+            path: path.to_string(),
+            transfer,
+            image: Some(Arc::new(image)),
+        })
+    }
+
+    pub fn value(&self, (u, v): (Number, Number)) -> Color {
+        match self {
+            Texture::Constant(value) => *value,
+
+            Texture::Checker { even, odd, scale } => {
+                let cells = (u * scale).floor() as i64 + (v * scale).floor() as i64;
+                if cells.rem_euclid(2) == 0 {
+                    *even
+                } else {
+                    *odd
+                }
+            }
+
+            Texture::Image { image: None, .. } => color(1.0, 0.0, 1.0),
+
+            Texture::Image {
+                image: Some(image), ..
+            } => {
+                // Image rows start at the top, while `v` goes up.
+                let x = u.rem_euclid(1.0) * image.width as Number - 0.5;
+                let y = (1.0 - v.rem_euclid(1.0)) * image.height as Number - 0.5;
+                let (x0, y0) = (x.floor(), y.floor());
+                let (tx, ty) = (x - x0, y - y0);
+
+                let texel = |dx: i64, dy: i64| {
+                    let x = (x0 as i64 + dx).rem_euclid(image.width as i64) as usize;
+                    let y = (y0 as i64 + dy).rem_euclid(image.height as i64) as usize;
+                    image.get(x, y)
+                };
+
+                (texel(0, 0) * (1.0 - tx) + texel(1, 0) * tx) * (1.0 - ty)
+                    + (texel(0, 1) * (1.0 - tx) + texel(1, 1) * tx) * ty
+            }
+        }
+    }
+
+    pub fn scalar_value(&self, uv: (Number, Number)) -> Number {
+        self.value(uv).luminance()
+    }
+}
+
+impl PartialEq for Texture {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Texture::Constant(a), Texture::Constant(b)) => a == b,
+            (
+                Texture::Checker { even, odd, scale },
+                Texture::Checker {
+                    even: other_even,
+                    odd: other_odd,
+                    scale: other_scale,
+                },
+            ) => even == other_even && odd == other_odd && scale == other_scale,
+            // The same path can point to a changed file, so loaded images only equal themselves.
+            (
+                Texture::Image {
+                    path,
+                    transfer,
+                    image,
+                },
+                Texture::Image {
+                    path: other_path,
+                    transfer: other_transfer,
+                    image: other_image,
+                },
+            ) => {
+                path == other_path
+                    && transfer == other_transfer
+                    && match (image, other_image) {
+                        (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+                        (None, None) => true,
+                        _ => false,
+                    }
+            }
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,244 @@
+use std::any::Any;
+use std::ops::{Add, Mul};
+
+use super::animation::Track;
+use super::hit::{gamma, Hit, HitData};
+use super::ray::Ray;
+use super::sampler::Sampler;
+use super::vector::{vector3, Vector3};
+use super::Number;
+
+/// Scales an object uniformly, rotates it by `rotation` degrees around the x, then y, then z axis
+/// and moves it by `translation`, in that order. `scale` has to be positive and finite, objects
+/// with any other scale are never hit.
+#[derive(Copy, Clone, PartialEq)]
+pub struct Transform {
+    pub translation: Vector3,
+    pub rotation: Vector3,
+    pub scale: Number,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            translation: vector3(0.0, 0.0, 0.0),
+            rotation: vector3(0.0, 0.0, 0.0),
+            scale: 1.0,
+        }
+    }
+}
+
+impl Transform {
+    // Rows of the rotation matrix.
+    fn rotation_matrix(&self) -> [Vector3; 3] {
+        let (sx, cx) = self.rotation.x.to_radians().sin_cos();
+        let (sy, cy) = self.rotation.y.to_radians().sin_cos();
+        let (sz, cz) = self.rotation.z.to_radians().sin_cos();
+
+        [
+            vector3(cz * cy, cz * sy * sx - sz * cx, cz * sy * cx + sz * sx),
+            vector3(sz * cy, sz * sy * sx + cz * cx, sz * sy * cx - cz * sx),
+            vector3(-sy, cy * sx, cy * cx),
+        ]
+    }
+}
+
+// Transforms blend field by field, angles included, so they can follow a `Track`. Rotations
+// therefore blend as Euler angles rather than along the shortest arc: going from 0 to 360 degrees
+// turns a full circle instead of standing still, which is what turntables want, but turning
+// around more than one axis at once can take a detour on the way.
+impl Add for Transform {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            translation: self.translation + other.translation,
+            rotation: self.rotation + other.rotation,
+            scale: self.scale + other.scale,
+        }
+    }
+}
+
+impl Mul<Number> for Transform {
+    type Output = Self;
+
+    fn mul(self, weight: Number) -> Self {
+        Self {
+            translation: self.translation * weight,
+            rotation: self.rotation * weight,
+            scale: self.scale * weight,
+        }
+    }
+}
+
+/// Places `object` somewhere else in the scene, and moves it around over time. The transform
+/// follows `motion` at the time of each ray, so it blurs with the shutter.
+pub struct Instance {
+    pub object: Box<dyn Hit>,
+    pub motion: Track<Transform>,
+}
+
+impl Instance {
+    pub fn transform(&self, time: Number) -> Transform {
+        self.motion.value(time).unwrap_or_default()
+    }
+
+    // The ray is taken into the object's space instead of moving the object. Distances along it
+    // stay the same as long as its direction is scaled along with the origin.
+    fn hit_local<'a>(
+        &'a self,
+        ray: &Ray,
+        hit: impl FnOnce(&Ray) -> Option<HitData<'a>>,
+    ) -> Option<HitData<'a>> {
+        let transform = self.transform(ray.time);
+        if !(transform.scale > 0.0 && transform.scale.is_finite()) {
+            return None;
+        }
+
+        let [x, y, z] = transform.rotation_matrix();
+
+        let unrotate = |v: Vector3| x * v.x + y * v.y + z * v.z;
+        let rotate = |v: Vector3| vector3(x.dot(&v), y.dot(&v), z.dot(&v));
+        let rotate_abs = |v: Vector3| vector3(x.abs().dot(&v), y.abs().dot(&v), z.abs().dot(&v));
+
+        let local = Ray {
+            origin: unrotate(ray.origin - transform.translation) / transform.scale,
+            direction: unrotate(ray.direction) / transform.scale,
+            time: ray.time,
+        };
+
+        let mut hit_data = hit(&local)?;
+
+        // The point is taken back out of the object's space rather than found along the ray, so
+        // its error bounds carry over, grown by the rounding of the transform itself.
+        let scaled = hit_data.point * transform.scale;
+        hit_data.point = rotate(scaled) + transform.translation;
+        hit_data.error = rotate_abs(hit_data.error * transform.scale) * (1.0 + gamma(4))
+            + (rotate_abs(scaled.abs()) + transform.translation.abs()) * gamma(4);
+        hit_data.normal = rotate(hit_data.normal);
+        Some(hit_data)
+    }
+}
+
+impl Hit for Instance {
+    fn hit(&self, ray: &Ray, t_min: Number, t_max: Number) -> Option<HitData<'_>> {
+        self.hit_local(ray, |local| self.object.hit(local, t_min, t_max))
+    }
+
+    fn hit_sampled(
+        &self,
+        ray: &Ray,
+        t_min: Number,
+        t_max: Number,
+        sampler: &mut dyn Sampler,
+    ) -> Option<HitData<'_>> {
+        self.hit_local(ray, |local| {
+            self.object.hit_sampled(local, t_min, t_max, sampler)
+        })
+    }
+
+    fn name(&self) -> &'static str {
+        "Instance"
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::animation::{Interpolation, Keyframe};
+    use super::super::color::color;
+    use super::super::material::Lambertian;
+    use super::super::sphere::Sphere;
+    use super::*;
+
+    fn instance(transform: Transform) -> Instance {
+        Instance {
+            object: Box::new(Sphere {
+                center: vector3(0.5, 0.0, 0.0),
+                radius: 1.0,
+                material: Box::new(Lambertian {
+                    albedo: color(1.0, 1.0, 1.0),
+                }),
+            }),
+            motion: Track {
+                interpolation: Interpolation::Linear,
+                keyframes: vec![Keyframe {
+                    time: 0.0,
+                    value: transform,
+                }],
+            },
+        }
+    }
+
+    fn ray(origin: Vector3, direction: Vector3) -> Ray {
+        Ray {
+            origin,
+            direction,
+            time: 0.0,
+        }
+    }
+
+    #[test]
+    fn instances_are_hit_where_their_transform_puts_them() {
+        // Scaled to a radius of 2, then turned a quarter around y, which takes the center from
+        // (1, 0, 0) to (0, 0, -1), and moved to (1, 2, -6).
+        let instance = instance(Transform {
+            translation: vector3(1.0, 2.0, -5.0),
+            rotation: vector3(0.0, 90.0, 0.0),
+            scale: 2.0,
+        });
+
+        // Straight at the center, with a direction that isn't normalized.
+        let hit_data = instance
+            .hit(
+                &ray(vector3(1.0, 2.0, 0.0), vector3(0.0, 0.0, -3.0)),
+                0.001,
+                100.0,
+            )
+            .unwrap();
+        assert!((hit_data.t - 4.0 / 3.0).abs() < 1e-4);
+        assert!((hit_data.point - vector3(1.0, 2.0, -4.0)).length() < 1e-4);
+        assert!((hit_data.normal - vector3(0.0, 0.0, 1.0)).length() < 1e-4);
+
+        // Off to the side, where the normal leans out.
+        let sqrt3 = (3.0 as Number).sqrt();
+        let hit_data = instance
+            .hit(
+                &ray(vector3(2.0, 2.0, 0.0), vector3(0.0, 0.0, -3.0)),
+                0.001,
+                100.0,
+            )
+            .unwrap();
+        assert!((hit_data.t - (6.0 - sqrt3) / 3.0).abs() < 1e-4);
+        assert!((hit_data.normal.length() - 1.0).abs() < 1e-4);
+        assert!((hit_data.normal - vector3(0.5, 0.0, sqrt3 / 2.0)).length() < 1e-4);
+
+        // And past the edge of the scaled sphere, which the unscaled one wouldn't reach.
+        assert!(instance
+            .hit(
+                &ray(vector3(3.5, 2.0, 0.0), vector3(0.0, 0.0, -1.0)),
+                0.001,
+                100.0
+            )
+            .is_none());
+    }
+
+    #[test]
+    fn instances_without_a_positive_scale_are_never_hit() {
+        for scale in [0.0, -1.0, Number::INFINITY, Number::NAN] {
+            let instance = instance(Transform {
+                scale,
+                ..Transform::default()
+            });
+            let ray = ray(vector3(0.5, 0.0, 5.0), vector3(0.0, 0.0, -1.0));
+            assert!(instance.hit(&ray, 0.001, 100.0).is_none());
+        }
+    }
+}
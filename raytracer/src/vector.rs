@@ -1,11 +1,10 @@
-use crate::{
+use super::{
     color::{color, Color},
     Number,
 };
-use rand::Rng;
 use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub, SubAssign};
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Vector3 {
     pub x: Number,
     pub y: Number,
@@ -17,32 +16,23 @@ pub fn vector3(x: Number, y: Number, z: Number) -> Vector3 {
 }
 
 impl Vector3 {
-    pub fn random(rand: &mut impl Rng) -> Self {
-        vector3(
-            rand.gen_range(-1.0..1.0),
-            rand.gen_range(-1.0..1.0),
-            rand.gen_range(-1.0..1.0),
-        )
+    pub fn reflect(&self, normal: &Self) -> Self {
+        *self - *normal * self.dot(normal) * 2.0
     }
 
-    pub fn random_in_unit_sphere(rand: &mut impl Rng) -> Self {
-        loop {
-            let random = Self::random(rand);
-
-            if random.length_squared() >= 1.0 {
-                continue;
-            }
+    /// Maps a uniform 2D sample to a point on the surface of the unit sphere.
+    pub fn on_unit_sphere((u, v): (Number, Number)) -> Self {
+        let z = 1.0 - 2.0 * u;
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let phi = 2.0 * std::f64::consts::PI as Number * v;
 
-            return random;
-        }
+        vector3(r * phi.cos(), r * phi.sin(), z)
     }
 
-    pub fn reflect(&self, normal: &Self) -> Self {
-        *self - *normal * self.dot(normal) * 2.0
-    }
-
-    pub fn random_normalized(rand: &mut impl Rng) -> Self {
-        Self::random_in_unit_sphere(rand)
+    /// Maps a uniform 2D sample for the direction and a 1D one for the radius to a point inside
+    /// the unit sphere.
+    pub fn in_unit_sphere(direction: (Number, Number), radius: Number) -> Self {
+        Self::on_unit_sphere(direction) * radius.cbrt()
     }
 
     pub fn near_zero(&self) -> bool {
@@ -74,6 +64,10 @@ impl Vector3 {
     pub fn normalize(&self) -> Self {
         *self / self.length()
     }
+
+    pub fn abs(&self) -> Self {
+        vector3(self.x.abs(), self.y.abs(), self.z.abs())
+    }
 }
 
 impl Neg for Vector3 {
@@ -88,9 +82,9 @@ impl Neg for Vector3 {
     }
 }
 
-impl Into<Color> for Vector3 {
-    fn into(self) -> Color {
-        color(self.x, self.y, self.z)
+impl From<Vector3> for Color {
+    fn from(v: Vector3) -> Self {
+        color(v.x, v.y, v.z)
     }
 }
 
@@ -144,3 +138,85 @@ operator_impl!(
     (Mul, Mul<Number>, mul, *, MulAssign, MulAssign<Number>, mul_assign, *=),
     (Div, Div<Number>, div, /, DivAssign, DivAssign<Number>, div_assign, /=)
 );
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: Vector3, b: Vector3) {
+        assert!((a - b).length() < 1e-5, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn operators_work_per_component() {
+        let a = vector3(1.0, -2.0, 3.0);
+        let b = vector3(4.0, 5.0, -6.0);
+
+        assert_eq!(a + b, vector3(5.0, 3.0, -3.0));
+        assert_eq!(a - b, vector3(-3.0, -7.0, 9.0));
+        assert_eq!(a * b, vector3(4.0, -10.0, -18.0));
+        assert_eq!(b / a, vector3(4.0, -2.5, -2.0));
+        assert_eq!(a * 2.0, vector3(2.0, -4.0, 6.0));
+        assert_eq!(-a, vector3(-1.0, 2.0, -3.0));
+
+        let mut c = a;
+        c += b;
+        c -= 1.0;
+        c *= 2.0;
+        c /= b;
+        assert_close(c, vector3(2.0, 0.8, 4.0 / 3.0));
+    }
+
+    #[test]
+    fn cross_products_are_right_handed_and_orthogonal() {
+        let x = vector3(1.0, 0.0, 0.0);
+        let y = vector3(0.0, 1.0, 0.0);
+        assert_eq!(x.cross(&y), vector3(0.0, 0.0, 1.0));
+        assert_eq!(y.cross(&x), vector3(0.0, 0.0, -1.0));
+
+        let a = vector3(1.0, -2.0, 3.0);
+        let b = vector3(4.0, 5.0, -6.0);
+        let c = a.cross(&b);
+        assert_eq!(a.dot(&b), -24.0);
+        assert!(c.dot(&a).abs() < 1e-4 && c.dot(&b).abs() < 1e-4);
+    }
+
+    #[test]
+    fn normalizing_and_reflecting_keep_unit_length() {
+        let v = vector3(3.0, 4.0, 12.0);
+        assert_eq!(v.length_squared(), 169.0);
+        assert_eq!(v.length(), 13.0);
+        assert_close(v.normalize(), vector3(3.0, 4.0, 12.0) / 13.0);
+
+        let normal = vector3(0.0, 1.0, 0.0);
+        let reflected = v.normalize().reflect(&normal);
+        assert_close(reflected, vector3(3.0, -4.0, 12.0) / 13.0);
+        assert!((reflected.length() - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn sphere_samples_land_where_they_should() {
+        let n = 64;
+        let mut mean = vector3(0.0, 0.0, 0.0);
+
+        for i in 0..n {
+            for j in 0..n {
+                let uv = (
+                    (i as Number + 0.5) / n as Number,
+                    (j as Number + 0.5) / n as Number,
+                );
+
+                let on = Vector3::on_unit_sphere(uv);
+                assert!((on.length() - 1.0).abs() < 1e-5);
+                mean += on / (n * n) as Number;
+
+                let radius = j as Number / n as Number;
+                let inside = Vector3::in_unit_sphere(uv, radius);
+                assert!((inside.length() - radius.cbrt()).abs() < 1e-5);
+            }
+        }
+
+        // A uniform grid of samples covers the sphere evenly, so it balances out.
+        assert!(mean.length() < 1e-3, "{:?}", mean);
+    }
+}